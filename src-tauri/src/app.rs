@@ -1,34 +1,53 @@
+use std::fmt::{Display, Formatter};
 use std::path::PathBuf;
 use std::sync::{Arc, mpsc};
-use std::time::Duration;
 use atomic_refcell::AtomicRefCell;
 use futures::{SinkExt, StreamExt};
-use tauri::{command, GlobalWindowEvent, Menu, WindowEvent};
+use serde::Serialize;
+use tauri::{command, AppHandle, GlobalWindowEvent, Manager, Menu, RunEvent, State, WindowEvent};
 use tokio::fs::{OpenOptions};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::sync::Mutex;
 use tokio::sync::mpsc::channel;
 use tokio::time::sleep;
 use tracing::{error, info};
-use websocket::{ClientBuilder, Message, OwnedMessage, WebSocketResult};
-use starship_plugin_api::api::StarShipPluginAPI;
 use crate::blazzy_client::BlazzyClient;
 use crate::blazzy_runner::BlazzyRunner;
-use crate::config_manager::{AppState, ConfigManager};
-use crate::meilisearch_runner::runner::{MeilisearchHost, MeilisearchMasterKey, MeilisearchRunner};
-use crate::tasker::{Tasker, TaskerError,};
-use crate::ws_connector::WsConnector;
+use crate::config_manager::{default_conf_path, AppState, ConfigManager, IndexConfig, SharedConfig};
+use crate::config_manager::bundle::{BundleError, ConflictStrategy, ImportReport};
+use crate::config_manager::profile::ResolvedProfile;
+use crate::config_manager::validator::ConfigError;
+use crate::meilisearch_runner::runner::{MeilisearchHost, MeilisearchMasterKey, MeilisearchRunner, SharedRunner};
+use crate::secrets::{Secrets, SecretsStatus, SharedSecrets, MASTER_KEY_SECRET};
+use crate::file_ops::{copy_path, create_dir, delete_path, event_bus, list_dir, move_path, EventBus, FileOps, SharedFileOps};
+use crate::plugin_manager::{disable_plugin, dispatch_event, enable_plugin, list_plugins, plugin_settings, plugin_ui, plugins_dir, render_plugin_panel, resolve_configs, run_plugin_action, set_plugin_settings, with_plugins, PluginManager, SharedPlugins};
+use crate::plugin_manager::dev;
+use crate::plugin_manager::files::{preview_file, MetadataExtractors};
+use crate::plugin_manager::registry::{install_plugin_archive, install_registry_plugin, registry_plugins, remove_plugin, rollback_plugin};
+use crate::plugin_manager::host::{AppHost, PluginNotification};
+use crate::search::search;
+use starship_plugin_api::context::HostEvent;
+use crate::onboarding::{onboarding_detect_first_run, onboarding_finish, onboarding_generate_credentials, onboarding_run_initial_index, onboarding_set_index_roots, onboarding_status};
+use crate::rpc::{self, Protocol, RpcError};
+use crate::rpc::tasker::{Observe, TaskerProtocol};
+use crate::tasker::{self, Tasker, TaskerError,};
+use crate::ws_connector::{connection_metrics, connection_states, ConnectionEvent, ConnectionState, SharedConnector, WsConnector, WsConnectorError};
 
 pub struct App {
-    config: ConfigManager,
-    ws_connector: WsConnector,
-    plugins: Vec<Box<dyn StarShipPluginAPI>>,
-
+    config: SharedConfig,
+    ws_connector: SharedConnector,
+    search: SharedRunner,
+    secrets: SharedSecrets,
+    plugins: SharedPlugins,
+    file_ops: SharedFileOps,
+    events: EventBus,
+    notifications: Option<tokio::sync::mpsc::UnboundedReceiver<PluginNotification>>,
 }
 
 impl App {
-    pub async fn init_conf(conf_path: Option<PathBuf>) -> Self {
-        let conf_path = conf_path.unwrap_or(PathBuf::from(std::env::current_exe().unwrap().parent().unwrap().join(".conf.toml")));
+    pub async fn init_conf(conf_path: Option<PathBuf>) -> Result<Self, StartupError> {
+        let conf_path = conf_path.unwrap_or_else(default_conf_path);
         {
             OpenOptions::new()
                 .create(true)
@@ -37,21 +56,265 @@ impl App {
                 .open(conf_path.clone())
                 .await.unwrap();
         }
+        let secrets = Secrets::init(conf_path.with_file_name(".secrets.toml"));
         let mut config = ConfigManager::new(conf_path).await;
-        config.setup().await;
-        Self{
-            config,
-            plugins: vec![],
-            ws_connector: WsConnector::init(),
+        if let Err(e) = config.setup().await {
+            error!("{}", e);
+        }
+        let events = event_bus();
+        let file_ops = Arc::new(FileOps::new(events.clone()));
+        let search = Arc::new(Mutex::new(None));
+        let (notifications_tx, notifications_rx) = tokio::sync::mpsc::unbounded_channel();
+        let host = AppHost::new(search.clone(), file_ops.clone(), notifications_tx);
+        let mut plugins = PluginManager::new(plugins_dir(), Arc::new(host), events.clone())
+            .with_runtime(&config.app_conf().plugin_runtime, std::env::current_exe().ok())
+            .with_disabled(config.app_conf().disabled_plugins.clone());
+        if dev::is_dev_mode() {
+            plugins = plugins.with_hot_reload();
+        }
+        let ws_connector = WsConnector::init(&config.app_conf().connector).map_err(StartupError::Connector)?;
+        let app = Self{
+            config: Arc::new(Mutex::new(config)),
+            plugins: Arc::new(Mutex::new(plugins)),
+            search,
+            secrets: Arc::new(Mutex::new(secrets)),
+            ws_connector: Arc::new(Mutex::new(ws_connector)),
+            file_ops,
+            events,
+            notifications: Some(notifications_rx),
+        };
+        App::migrate_master_key(&app.config, &app.secrets).await;
+        Ok(app)
+    }
+
+    //Older configs kept master key in plain text, move it to secrets store once it is unlocked
+    async fn migrate_master_key(config: &SharedConfig, secrets: &SharedSecrets) {
+        let mut config = config.lock().await;
+        let mut secrets = secrets.lock().await;
+        if let Some(master_key) = config.app_conf().search.master_key.clone() {
+            if !secrets.is_unlocked() {
+                return;
+            }
+            if let Err(e) = secrets.set(MASTER_KEY_SECRET, &master_key) {
+                error!("{}", e);
+                return;
+            }
+            match config.update(|app_conf| app_conf.search.master_key = None).await {
+                Ok(_) => info!("Search master key moved to secrets store"),
+                Err(e) => error!("{}", e)
+            }
         }
     }
 
     pub async fn get_state(&mut self) -> AppState {
-        self.config.get_state().await
+        self.config.lock().await.get_state().await
     }
 
     pub async fn conf_first_setup(&mut self) {
-        self.config.setup().await;
+        if let Err(e) = self.config.lock().await.setup().await {
+            error!("{}", e);
+        }
+    }
+
+    //Start search engine with saved credentials, on first run onboarding starts it
+    pub async fn start_search(config: &SharedConfig, secrets: &SharedSecrets, search_runner: &SharedRunner) {
+        if search_runner.lock().await.is_some() {
+            return;
+        }
+        let (search, profile) = {
+            let config = config.lock().await;
+            if config.app_conf().state() != AppState::Stable {
+                return;
+            }
+            (config.app_conf().search.clone(), config.subscribe_profile().borrow().clone())
+        };
+        let master_key = match MeilisearchMasterKey::from_secrets(&*secrets.lock().await) {
+            Ok(Some(master_key)) => master_key,
+            Ok(None) => {
+                error!("Search master key is missing, search engine is not started");
+                return;
+            }
+            Err(e) => {
+                error!("Search engine is not started: {}", e);
+                return;
+            }
+        };
+        let mut runner = MeilisearchRunner::new(MeilisearchHost::new(&search.host, search.port), master_key).await;
+        if let Err(e) = runner.safe_run().await {
+            error!(name: "Search run error", "Error: {}", e);
+            return;
+        }
+        runner.run_client().await;
+        runner.set_indexing(profile.indexing);
+        *search_runner.lock().await = Some(runner);
+    }
+
+    //Apply every profile switch to running subsystems and notify UI. Index is rebuilt when profile
+    //indexes other roots or rules, switches made meanwhile are applied once it's done.
+    //Index roots are watched for changes made outside the app
+    fn watch_profile(app: AppHandle, config: SharedConfig, search: SharedRunner, plugins: SharedPlugins, file_ops: SharedFileOps, events: EventBus) {
+        tauri::async_runtime::spawn(async move {
+            let mut profile_rx = config.lock().await.subscribe_profile();
+            let mut index = profile_rx.borrow().index.clone();
+            let mut watcher = match file_ops.watch() {
+                Ok(watcher) => Some(watcher),
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                }
+            };
+            if let Some(watcher) = watcher.as_mut() {
+                watcher.watch_roots(&index.roots);
+            }
+            while profile_rx.changed().await.is_ok() {
+                let profile = profile_rx.borrow().clone();
+                info!("Active profile changed to {}", profile.name);
+                if let Some(runner) = search.lock().await.as_mut() {
+                    runner.set_indexing(profile.indexing.clone());
+                }
+                let _ = events.send(HostEvent::ProfileChanged { name: profile.name.clone() });
+                if let Err(e) = app.emit_all("profile-changed", profile.clone()) {
+                    error!("{}", e);
+                }
+                if profile.index != index {
+                    index = profile.index.clone();
+                    if let Some(watcher) = watcher.as_mut() {
+                        watcher.watch_roots(&index.roots);
+                    }
+                    App::reindex(&app, &search, &plugins, &index).await;
+                }
+            }
+        });
+    }
+
+    //Search engine not running yet indexes with current profile once onboarding or next start runs it
+    async fn reindex(app: &AppHandle, search: &SharedRunner, plugins: &SharedPlugins, index: &IndexConfig) {
+        let indexer = match search.lock().await.as_ref().map(|runner| runner.indexer()) {
+            Some(Ok(indexer)) => indexer,
+            Some(Err(e)) => {
+                error!("Index is not rebuilt: {}", e);
+                return;
+            }
+            None => return,
+        };
+        let extractors = MetadataExtractors::new(plugins).await;
+        let result = indexer.update_fs_info(index, |progress| {
+            if let Err(e) = app.emit_all("index-progress", progress) {
+                error!("{}", e);
+            }
+        }, |path| extractors.extract(path)).await;
+        match result {
+            Ok(_) => info!("Index rebuilt for {} roots", index.roots.len()),
+            Err(e) => error!("Index is not rebuilt: {}", e),
+        }
+    }
+
+    //Deliver filesystem and app events to subscribed plugins
+    fn forward_events(plugins: SharedPlugins, events: EventBus) {
+        tauri::async_runtime::spawn(async move {
+            let mut events_rx = events.subscribe();
+            loop {
+                match events_rx.recv().await {
+                    Ok(event) => dispatch_event(&plugins, event).await,
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                        error!("Plugins missed {} events", skipped);
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    //Menus, toolbar and sidebar are rebuilt by UI when plugin is enabled or disabled
+    fn forward_plugin_ui(app: AppHandle, events: EventBus) {
+        tauri::async_runtime::spawn(async move {
+            let mut events_rx = events.subscribe();
+            loop {
+                match events_rx.recv().await {
+                    Ok(HostEvent::PluginEnabled { id }) | Ok(HostEvent::PluginDisabled { id }) => {
+                        if let Err(e) = app.emit_all("plugin-ui-changed", id) {
+                            error!("{}", e);
+                        }
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    //Connection state of local services for UI
+    fn forward_connection_events(app: AppHandle, mut connection_events: tokio::sync::broadcast::Receiver<ConnectionEvent>) {
+        tauri::async_runtime::spawn(async move {
+            loop {
+                match connection_events.recv().await {
+                    Ok(event) => {
+                        if let Err(e) = app.emit_all("connection-state", event) {
+                            error!("{}", e);
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    //Services are asked for their protocol version every time they connect, service of other major
+    //version is disconnected. Events are subscribed by caller before connecting, so the first
+    //connection isn't missed
+    fn check_protocols(connector: SharedConnector, mut connection_events: tokio::sync::broadcast::Receiver<ConnectionEvent>) {
+        tauri::async_runtime::spawn(async move {
+            loop {
+                match connection_events.recv().await {
+                    Ok(event) if event.state == ConnectionState::Connected && event.connection_key == TaskerProtocol::CONNECTION_KEY => {
+                        let hello = rpc::handshake::<TaskerProtocol>(&*connector.lock().await);
+                        match hello.await {
+                            Ok(version) => {
+                                info!("Task observer speaks protocol {}", version);
+                                let observe = Tasker::call(&*connector.lock().await, &Observe {
+                                    main_app: "SpaceTraveler".to_string(),
+                                    sub_apps: vec!["blazzy".to_string()],
+                                    safe: "WoSafe".to_string(),
+                                });
+                                if let Err(e) = observe.await {
+                                    error!("Task observer doesn't observe app: {}", e);
+                                }
+                            }
+                            //Messages of other version would be misread, connection is not used at all
+                            Err(e @ RpcError::IncompatibleVersion { .. }) => {
+                                error!("Task observer: {}, disconnecting", e);
+                                connector.lock().await.disconnect(TaskerProtocol::CONNECTION_KEY).await;
+                            }
+                            Err(e) => error!("Task observer: {}", e)
+                        }
+                    }
+                    Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+    }
+
+    fn forward_notifications(app: AppHandle, mut notifications: tokio::sync::mpsc::UnboundedReceiver<PluginNotification>) {
+        tauri::async_runtime::spawn(async move {
+            while let Some(notification) = notifications.recv().await {
+                if let Err(e) = app.emit_all("plugin-notification", notification) {
+                    error!("{}", e);
+                }
+            }
+        });
+    }
+
+    //Load every plugin from `plugins` dir next to executable
+    pub async fn load_plugins(&mut self) {
+        let configs = resolve_configs(&self.config, &self.secrets).await;
+        with_plugins(&self.plugins, move |plugins| plugins.load_all(&configs)).await;
+    }
+
+    async fn configure_plugins(config: &SharedConfig, secrets: &SharedSecrets, plugins: &SharedPlugins) {
+        let configs = resolve_configs(config, secrets).await;
+        with_plugins(plugins, move |plugins| plugins.configure_all(&configs)).await;
     }
 
     pub async fn default_run(&mut self) {
@@ -63,24 +326,110 @@ impl App {
             }
         });
 
-        let blazzy_client = BlazzyClient::init();
-
-        self.ws_connector.connect("tasker","ws://127.0.0.1:5000/", None).await;
-
+        let mut blazzy_client = BlazzyClient::init();
+        blazzy_client.attach(self.ws_connector.clone());
 
+        App::start_search(&self.config, &self.secrets, &self.search).await;
 
-        //self.ws_connector.send("tasker", "observe { \"main_app\": \"SpaceTraveler\", \"sub_apps\": [\"blazzy\"], \"safe\": \"WoSafe\" }").await;
-        //self.ws_connector.send("tasker", "run_app { \"app\": \"F:/devProjects/explorer/src-tauri/assets/blazzy.exe\", \"args\": [\"-p\", \"C:/\"], \"window\": false }").await;
-
+        App::forward_events(self.plugins.clone(), self.events.clone());
+        self.load_plugins().await;
+        if dev::is_dev_mode() {
+            dev::watch_libraries(self.plugins.clone(), self.config.clone(), self.secrets.clone());
+        }
 
+        let connection_events = self.ws_connector.lock().await.subscribe();
+        App::check_protocols(self.ws_connector.clone(), self.ws_connector.lock().await.subscribe());
+        if let Err(e) = self.ws_connector.lock().await.connect(tasker::CONNECTION_KEY, tasker::URL).await {
+            error!("{}", e);
+        }
 
         let menu = Menu::new();
+        let errors = self.config.lock().await.errors();
+        let warnings = self.config.lock().await.warnings();
+        let plugin_errors = self.plugins.lock().await.errors();
+        let config = self.config.clone();
+        let search = self.search.clone();
+        let plugins = self.plugins.clone();
+        let profile_plugins = self.plugins.clone();
+        let file_ops = self.file_ops.clone();
+        let events = self.events.clone();
+        let notifications = self.notifications.take();
 
         tauri::Builder::default()
             .menu(menu)
-            .invoke_handler(tauri::generate_handler![call])
-            .run(tauri::generate_context!())
-            .expect("error while running tauri application");
+            .manage(self.config.clone())
+            .manage(self.search.clone())
+            .manage(self.secrets.clone())
+            .manage(self.plugins.clone())
+            .manage(self.file_ops.clone())
+            .manage(self.ws_connector.clone())
+            .setup(move |app| {
+                App::forward_connection_events(app.handle(), connection_events);
+                App::forward_plugin_ui(app.handle(), events.clone());
+                App::watch_profile(app.handle(), config, search, profile_plugins, file_ops, events);
+                if let Some(notifications) = notifications {
+                    App::forward_notifications(app.handle(), notifications);
+                }
+                if !errors.is_empty() {
+                    app.emit_all("config-error", errors)?;
+                }
+                if !warnings.is_empty() {
+                    app.emit_all("config-warning", warnings)?;
+                }
+                for e in plugin_errors {
+                    app.emit_all("plugin-load-error", e)?;
+                }
+                Ok(())
+            })
+            .invoke_handler(tauri::generate_handler![
+                call,
+                config_errors,
+                config_warnings,
+                list_profiles,
+                switch_profile,
+                export_config,
+                import_config,
+                secrets_status,
+                unlock_secrets,
+                set_plugin_secret,
+                list_plugins,
+                enable_plugin,
+                disable_plugin,
+                plugin_settings,
+                set_plugin_settings,
+                plugin_ui,
+                run_plugin_action,
+                render_plugin_panel,
+                preview_file,
+                registry_plugins,
+                install_plugin_archive,
+                install_registry_plugin,
+                rollback_plugin,
+                remove_plugin,
+                search,
+                connection_states,
+                connection_metrics,
+                list_dir,
+                copy_path,
+                move_path,
+                delete_path,
+                create_dir,
+                onboarding_status,
+                onboarding_detect_first_run,
+                onboarding_set_index_roots,
+                onboarding_generate_credentials,
+                onboarding_run_initial_index,
+                onboarding_finish
+            ])
+            .build(tauri::generate_context!())
+            .expect("error while running tauri application")
+            .run(move |_app, event| {
+                if let RunEvent::Exit = event {
+                    tauri::async_runtime::block_on(async {
+                        plugins.lock().await.unload_all();
+                    });
+                }
+            });
 
     }
 
@@ -88,9 +437,105 @@ impl App {
         info!("Calling")
     }
 
+    pub async fn config_errors(config: &SharedConfig) -> Vec<ConfigError> {
+        config.lock().await.errors()
+    }
+
+    pub async fn config_warnings(config: &SharedConfig) -> Vec<ConfigError> {
+        config.lock().await.warnings()
+    }
+
+    pub async fn list_profiles(config: &SharedConfig) -> Vec<String> {
+        config.lock().await.profile_names()
+    }
+
+    pub async fn switch_profile(config: &SharedConfig, name: &str) -> Result<ResolvedProfile, String> {
+        config.lock().await.switch_profile(name).await.map_err(|e| e.to_string())
+    }
+
+    pub async fn export_config(config: &SharedConfig, path: PathBuf) -> Result<(), BundleError> {
+        config.lock().await.export_bundle(&path).await
+    }
+
+    pub async fn unlock_secrets(config: &SharedConfig, secrets: &SharedSecrets, search: &SharedRunner, plugins: &SharedPlugins, passphrase: &str) -> Result<(), String> {
+        secrets.lock().await.unlock(passphrase).map_err(|e| e.to_string())?;
+        App::migrate_master_key(config, secrets).await;
+        App::start_search(config, secrets, search).await;
+        App::configure_plugins(config, secrets, plugins).await;
+        Ok(())
+    }
+
+    pub async fn import_config(config: &SharedConfig, secrets: &SharedSecrets, plugins: &SharedPlugins, path: PathBuf, strategy: ConflictStrategy) -> Result<ImportReport, BundleError> {
+        let report = config.lock().await.import_bundle(&path, strategy).await?;
+        info!("Config imported from {}, {} conflicts", path.display(), report.conflicts.len());
+        App::configure_plugins(config, secrets, plugins).await;
+        Ok(report)
+    }
+
+}
+
+//App can't start without these, other subsystems report their errors once running
+#[derive(Serialize, Debug)]
+pub enum StartupError {
+    Connector(WsConnectorError),
+}
+
+impl Display for StartupError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StartupError::Connector(e) => write!(f, "Can't set up service connections: {}", e),
+        }
+    }
 }
 
 #[command]
 pub async fn call() {
     App::call().await;
-}
\ No newline at end of file
+}
+
+#[command]
+pub async fn config_errors(config: State<'_, SharedConfig>) -> Result<Vec<ConfigError>, ()> {
+    Ok(App::config_errors(&config).await)
+}
+
+#[command]
+pub async fn config_warnings(config: State<'_, SharedConfig>) -> Result<Vec<ConfigError>, ()> {
+    Ok(App::config_warnings(&config).await)
+}
+
+#[command]
+pub async fn list_profiles(config: State<'_, SharedConfig>) -> Result<Vec<String>, ()> {
+    Ok(App::list_profiles(&config).await)
+}
+
+#[command]
+pub async fn switch_profile(config: State<'_, SharedConfig>, name: String) -> Result<ResolvedProfile, String> {
+    App::switch_profile(&config, &name).await
+}
+
+#[command]
+pub async fn export_config(config: State<'_, SharedConfig>, path: PathBuf) -> Result<(), BundleError> {
+    App::export_config(&config, path).await
+}
+
+#[command]
+pub async fn import_config(config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>, plugins: State<'_, SharedPlugins>, path: PathBuf, strategy: ConflictStrategy) -> Result<ImportReport, BundleError> {
+    App::import_config(&config, &secrets, &plugins, path, strategy).await
+}
+
+#[command]
+pub async fn secrets_status(secrets: State<'_, SharedSecrets>) -> Result<SecretsStatus, ()> {
+    Ok(secrets.lock().await.status())
+}
+
+#[command]
+pub async fn unlock_secrets(config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>, search: State<'_, SharedRunner>, plugins: State<'_, SharedPlugins>, passphrase: String) -> Result<(), String> {
+    App::unlock_secrets(&config, &secrets, &search, &plugins, &passphrase).await
+}
+
+#[command]
+pub async fn set_plugin_secret(secrets: State<'_, SharedSecrets>, plugin: String, name: String, value: String) -> Result<(), String> {
+    secrets.lock().await
+        .set(&Secrets::plugin_secret_name(&plugin, &name), &value)
+        .map_err(|e| e.to_string())
+}
@@ -1,15 +1,16 @@
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
-use std::sync::Arc;
-use atomic_refcell::AtomicRefCell;
-use tokio::sync::mpsc::{Sender, Receiver};
-use tracing::{error, info};
-use websocket::OwnedMessage;
+use crate::rpc::{self, Notification, Protocol, RpcError};
+use crate::rpc::blazzy::BlazzyProtocol;
+use crate::ws_connector::{SharedConnector, WsConnectorError};
+
+//Blazzy is reached through `WsConnector` under this key. Nothing connects it yet,
+//so notifications fail with `NotConnected` until it's started with a known address
+pub const CONNECTION_KEY: &str = BlazzyProtocol::CONNECTION_KEY;
 
 pub struct BlazzyClient {
-    sender: Option<Sender<OwnedMessage>>,
-    recv: Option<Arc<AtomicRefCell<Receiver<OwnedMessage>>>>,
+    connector: Option<SharedConnector>,
     exe_path: PathBuf
 }
 
@@ -34,38 +35,20 @@ impl BlazzyClient {
         }
 
         Self {
-            sender: None,
-            recv: None,
+            connector: None,
             exe_path
         }
 
     }
 
-    pub fn connect_channel(&mut self, channel: (Sender<OwnedMessage>, Receiver<OwnedMessage>)) {
-        self.sender = Some(channel.0);
-        self.recv = Some(Arc::new(AtomicRefCell::new(channel.1)));
-    }
-
-    pub async fn send(&self, message: &str) {
-        if let Some(sender) = self.sender.clone() {
-            if let Err(e) = sender.send(OwnedMessage::Text(message.to_string())).await {
-                error!("{}", e)
-            }
-        }
+    pub fn attach(&mut self, connector: SharedConnector) {
+        self.connector = Some(connector);
     }
 
-    pub async fn listen(&mut self) {
-        if let Some(mut rx) = self.recv.clone() {
-            tokio::task::spawn(async move {
-                if let Some(message) = rx.borrow_mut().recv().await {
-                    match message {
-                        OwnedMessage::Text(data) => {
-                            info!("{data}")
-                        }
-                        _ => {}
-                    }
-                }
-            });
+    pub async fn send<N: Notification<Protocol = BlazzyProtocol>>(&self, notification: &N) -> Result<(), RpcError> {
+        match &self.connector {
+            Some(connector) => rpc::notify(&*connector.lock().await, notification).await,
+            None => Err(RpcError::Transport(WsConnectorError::NotConnected(CONNECTION_KEY.to_string())))
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,339 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use chrono::Local;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use starship_plugin_api::plugin_config::PluginConfig;
+use crate::config_manager::{deserialize_plugins_conf, AppConfig, Bookmark, ConfigSaveError, IndexConfig};
+use crate::config_manager::profile::{IndexingConfig, Profile};
+use crate::config_manager::validator;
+
+//Increase when bundle layout changes, older bundles must still be readable
+pub const BUNDLE_VERSION: u32 = 1;
+//Settings inside bundle archive
+const BUNDLE_FILE: &str = "bundle.toml";
+
+//Gzipped tar archive with settings shared between machines, never holds secrets or machine state
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigBundle {
+    pub bundle_version: u32,
+    pub app_version: String,
+    pub created: String,
+    pub settings: PortableConfig,
+}
+
+//Only settings changed from their defaults are exported, so settings left alone on one machine
+//never override the ones changed on the other
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PortableConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    index: Option<IndexConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    indexing: Option<IndexingConfig>,
+    #[serde(default)]
+    search: PortableSearch,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    active_profile: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    bookmarks: Vec<Bookmark>,
+    #[serde(default)]
+    keybindings: BTreeMap<String, String>,
+    #[serde(default, deserialize_with = "deserialize_plugins_conf")]
+    plugins_conf: BTreeMap<String, PluginConfig>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PortableSearch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
+}
+
+fn changed<T: PartialEq + Clone>(value: &T, default: &T) -> Option<T> {
+    if value == default {
+        None
+    } else {
+        Some(value.clone())
+    }
+}
+
+impl PortableConfig {
+    fn from_app_conf(app_conf: &AppConfig) -> Self {
+        let defaults = AppConfig::init();
+        Self {
+            index: changed(&app_conf.index, &defaults.index),
+            indexing: changed(&app_conf.indexing, &defaults.indexing),
+            search: PortableSearch {
+                host: changed(&app_conf.search.host, &defaults.search.host),
+                port: changed(&app_conf.search.port, &defaults.search.port),
+            },
+            active_profile: app_conf.active_profile.clone(),
+            profiles: app_conf.profiles.clone(),
+            bookmarks: app_conf.bookmarks.clone(),
+            keybindings: app_conf.keybindings.clone(),
+            plugins_conf: app_conf.plugins_conf.clone(),
+        }
+    }
+}
+
+//What to do with settings that exist on both sides with different values
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ConflictStrategy {
+    Abort,
+    KeepExisting,
+    UseIncoming,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImportConflict {
+    pub key: String,
+    pub existing: String,
+    pub incoming: String,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct ImportReport {
+    pub conflicts: Vec<ImportConflict>,
+    pub skipped_roots: Vec<PathBuf>,
+}
+
+impl ConfigBundle {
+    pub fn from_app_conf(app_conf: &AppConfig) -> Self {
+        Self {
+            bundle_version: BUNDLE_VERSION,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: Local::now().to_rfc3339(),
+            settings: PortableConfig::from_app_conf(app_conf),
+        }
+    }
+
+    pub async fn write(&self, path: &Path) -> Result<(), BundleError> {
+        let con = toml::to_string(self).map_err(|e| BundleError::Parse(e.to_string()))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_size(con.len() as u64);
+        header.set_mode(0o644);
+        let mut archive = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+        let data = archive.append_data(&mut header, BUNDLE_FILE, con.as_bytes())
+            .and_then(|_| archive.into_inner())
+            .and_then(|encoder| encoder.finish())
+            .map_err(|e| BundleError::Io(e.to_string()))?;
+        tokio::fs::write(path, data).await.map_err(|e| BundleError::Io(e.to_string()))
+    }
+
+    fn unpack(data: &[u8]) -> Result<String, BundleError> {
+        let invalid = |e: std::io::Error| BundleError::Parse(format!("not a config bundle: {}", e));
+        let mut archive = tar::Archive::new(GzDecoder::new(data));
+        for entry in archive.entries().map_err(invalid)? {
+            let mut entry = entry.map_err(invalid)?;
+            if entry.path().map_or(false, |path| path == Path::new(BUNDLE_FILE)) {
+                let mut con = String::new();
+                entry.read_to_string(&mut con).map_err(invalid)?;
+                return Ok(con);
+            }
+        }
+        Err(BundleError::Parse(format!("bundle has no {}", BUNDLE_FILE)))
+    }
+
+    pub async fn read(path: &Path) -> Result<Self, BundleError> {
+        let data = tokio::fs::read(path).await.map_err(|e| BundleError::Io(e.to_string()))?;
+        let con = Self::unpack(&data)?;
+        let version = toml::from_str::<toml::Table>(&con).ok()
+            .and_then(|table| table.get("bundle_version").and_then(|v| v.as_integer()))
+            .ok_or_else(|| BundleError::Parse("missing `bundle_version`".to_string()))?;
+        if version < 1 || version > BUNDLE_VERSION as i64 {
+            return Err(BundleError::UnsupportedVersion(version));
+        }
+        toml::from_str::<ConfigBundle>(&con).map_err(|e| BundleError::Parse(e.to_string()))
+    }
+
+    //Merge bundle into current config, returns merged config without touching the original.
+    //Settings only one side changed from defaults are taken from that side without conflict
+    pub fn merge_into(self, app_conf: &AppConfig, strategy: ConflictStrategy) -> Result<(AppConfig, ImportReport), BundleError> {
+        let incoming = self.settings;
+        let defaults = AppConfig::init();
+        let mut merged = app_conf.clone();
+        let mut report = ImportReport::default();
+
+        merge_value("index", &mut merged.index, &defaults.index, incoming.index, strategy, &mut report);
+        merge_value("indexing", &mut merged.indexing, &defaults.indexing, incoming.indexing, strategy, &mut report);
+        merge_value("search.host", &mut merged.search.host, &defaults.search.host, incoming.search.host, strategy, &mut report);
+        merge_value("search.port", &mut merged.search.port, &defaults.search.port, incoming.search.port, strategy, &mut report);
+        merge_map("profiles", &mut merged.profiles, incoming.profiles, strategy, &mut report);
+        merge_map("keybindings", &mut merged.keybindings, incoming.keybindings, strategy, &mut report);
+        merge_map("plugins_conf", &mut merged.plugins_conf, incoming.plugins_conf, strategy, &mut report);
+
+        let mut bookmarks: BTreeMap<String, Bookmark> = merged.bookmarks.drain(..).map(|b| (b.name.clone(), b)).collect();
+        let incoming_bookmarks = incoming.bookmarks.into_iter().map(|b| (b.name.clone(), b)).collect();
+        merge_map("bookmarks", &mut bookmarks, incoming_bookmarks, strategy, &mut report);
+        merged.bookmarks = bookmarks.into_values().collect();
+
+        merge_value("active_profile", &mut merged.active_profile, &defaults.active_profile, incoming.active_profile.map(Some), strategy, &mut report);
+
+        if strategy == ConflictStrategy::Abort && !report.conflicts.is_empty() {
+            return Err(BundleError::Conflicts(report.conflicts));
+        }
+
+        //Folders of the other machine may not exist here
+        report.skipped_roots.extend(retain_existing_roots(&mut merged.index));
+        for profile in merged.profiles.values_mut() {
+            if let Some(index) = profile.index.as_mut() {
+                report.skipped_roots.extend(retain_existing_roots(index));
+            }
+        }
+
+        let con = toml::to_string(&merged).map_err(|e| BundleError::Parse(e.to_string()))?;
+        if let Err(errors) = validator::validate(&con) {
+            return Err(BundleError::InvalidConfig(errors.into_iter().map(|e| e.to_string()).collect()));
+        }
+        Ok((merged, report))
+    }
+}
+
+fn display_value<T: Serialize>(value: &T) -> String {
+    toml::Value::try_from(value).map(|v| v.to_string()).unwrap_or_default()
+}
+
+//Existing value still at its default is replaced, `None` means bundle doesn't set the value
+fn merge_value<T: PartialEq + Serialize>(key: &str, existing: &mut T, default: &T, incoming: Option<T>, strategy: ConflictStrategy, report: &mut ImportReport) {
+    let incoming = match incoming {
+        Some(incoming) if incoming != *existing => incoming,
+        _ => return,
+    };
+    if existing == default {
+        *existing = incoming;
+        return;
+    }
+    report_conflict(key, existing, incoming, strategy, report);
+}
+
+fn report_conflict<T: Serialize>(key: &str, existing: &mut T, incoming: T, strategy: ConflictStrategy, report: &mut ImportReport) {
+    report.conflicts.push(ImportConflict {
+        key: key.to_string(),
+        existing: display_value(existing),
+        incoming: display_value(&incoming),
+    });
+    if strategy == ConflictStrategy::UseIncoming {
+        *existing = incoming;
+    }
+}
+
+fn merge_map<T: PartialEq + Serialize>(key: &str, existing: &mut BTreeMap<String, T>, incoming: BTreeMap<String, T>, strategy: ConflictStrategy, report: &mut ImportReport) {
+    for (name, value) in incoming {
+        match existing.get_mut(&name) {
+            Some(current) => {
+                if *current != value {
+                    report_conflict(&format!("{}.{}", key, name), current, value, strategy, report);
+                }
+            }
+            None => {
+                existing.insert(name, value);
+            }
+        }
+    }
+}
+
+fn retain_existing_roots(index: &mut IndexConfig) -> Vec<PathBuf> {
+    let (roots, skipped) = index.roots.drain(..).partition(|root| root.is_dir());
+    index.roots = roots;
+    skipped
+}
+
+#[derive(Serialize, Debug)]
+pub enum BundleError {
+    Io(String),
+    Parse(String),
+    UnsupportedVersion(i64),
+    Conflicts(Vec<ImportConflict>),
+    InvalidConfig(Vec<String>),
+    Save(ConfigSaveError),
+}
+
+impl Display for BundleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BundleError::Io(e) => write!(f, "Bundle io error: {}", e),
+            BundleError::Parse(e) => write!(f, "Bundle parse error: {}", e),
+            BundleError::UnsupportedVersion(v) => write!(f, "Bundle version {} is not supported, max supported is {}", v, BUNDLE_VERSION),
+            BundleError::Conflicts(conflicts) => write!(f, "Bundle conflicts with {} existing settings", conflicts.len()),
+            BundleError::InvalidConfig(errors) => write!(f, "Imported config is invalid: {}", errors.join("; ")),
+            BundleError::Save(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_manager::AppState;
+
+    fn app_conf() -> AppConfig {
+        let mut app_conf = AppConfig::init();
+        app_conf.state = AppState::Stable;
+        app_conf
+    }
+
+    #[test]
+    fn settings_changed_on_one_side_do_not_conflict() {
+        let root = tempfile::tempdir().unwrap();
+        let mut other = app_conf();
+        other.index.roots = vec![root.path().to_path_buf()];
+        let mut existing = app_conf();
+        existing.search.port = 7800;
+        existing.keybindings.insert("open".to_string(), "Ctrl+O".to_string());
+
+        let (merged, report) = ConfigBundle::from_app_conf(&other).merge_into(&existing, ConflictStrategy::Abort).unwrap();
+        assert!(report.conflicts.is_empty());
+        assert_eq!(merged.index.roots, vec![root.path().to_path_buf()]);
+        assert_eq!(merged.search.port, 7800);
+        assert_eq!(merged.keybindings["open"], "Ctrl+O");
+    }
+
+    #[test]
+    fn settings_changed_on_both_sides_conflict() {
+        let mut other = app_conf();
+        other.search.port = 7900;
+        let mut existing = app_conf();
+        existing.search.port = 7800;
+
+        let conflicts = match ConfigBundle::from_app_conf(&other).merge_into(&existing, ConflictStrategy::Abort) {
+            Err(BundleError::Conflicts(conflicts)) => conflicts,
+            _ => panic!("conflict expected"),
+        };
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].key, "search.port");
+
+        let (merged, _) = ConfigBundle::from_app_conf(&other).merge_into(&existing, ConflictStrategy::KeepExisting).unwrap();
+        assert_eq!(merged.search.port, 7800);
+        let (merged, _) = ConfigBundle::from_app_conf(&other).merge_into(&existing, ConflictStrategy::UseIncoming).unwrap();
+        assert_eq!(merged.search.port, 7900);
+    }
+
+    #[tokio::test]
+    async fn bundle_is_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("settings.bundle");
+        let mut app_conf = app_conf();
+        app_conf.search.master_key = Some("secret".to_string());
+        app_conf.keybindings.insert("open".to_string(), "Ctrl+O".to_string());
+        ConfigBundle::from_app_conf(&app_conf).write(&path).await.unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[..2], &[0x1f, 0x8b]);
+        assert!(!ConfigBundle::unpack(&data).unwrap().contains("secret"));
+        let bundle = ConfigBundle::read(&path).await.unwrap();
+        assert_eq!(bundle.settings.keybindings["open"], "Ctrl+O");
+
+        std::fs::write(&path, "bundle_version = 1").unwrap();
+        assert!(matches!(ConfigBundle::read(&path).await, Err(BundleError::Parse(_))));
+    }
+}
@@ -1,78 +1,585 @@
-use std::path::PathBuf;
-use serde::{Deserialize, Serialize};
-use tokio::fs::File;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+pub mod validator;
+pub mod profile;
+pub mod bundle;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use tokio::sync::{watch, Mutex};
+use tracing::{error, warn};
 use starship_plugin_api::plugin_config::PluginConfig;
+use crate::config_manager::bundle::{BundleError, ConfigBundle, ConflictStrategy, ImportReport};
+use crate::config_manager::profile::{IndexingConfig, Profile, ProfileError, ResolvedProfile, BASE_PROFILE};
+use crate::config_manager::validator::ConfigError;
+use crate::onboarding::OnboardingProgress;
+
+pub type SharedConfig = Arc<Mutex<ConfigManager>>;
+
 pub struct ConfigManager {
     app_conf: AppConfig,
-    conf_file: File
+    conf_path: PathBuf,
+    errors: Vec<ConfigError>,
+    //Problems that don't invalidate config, like index roots missing now
+    warnings: Vec<ConfigError>,
+    profile_tx: watch::Sender<ResolvedProfile>,
+}
+
+//`.conf.toml` next to executable
+pub fn default_conf_path() -> PathBuf {
+    std::env::current_exe().unwrap().parent().unwrap().join(".conf.toml")
 }
 
 impl ConfigManager {
-    pub async fn new(conf_file: PathBuf) -> Self {
-        let mut conf_file = File::open(conf_file).await.unwrap();
-        Self {
-            app_conf: AppConfig::init(),
-            conf_file
+    pub async fn new(conf_path: PathBuf) -> Self {
+        let app_conf = AppConfig::init();
+        let (profile_tx, _) = watch::channel(ResolvedProfile::base(&app_conf));
+        let mut manager = Self {
+            app_conf,
+            conf_path,
+            errors: vec![],
+            warnings: vec![],
+            profile_tx,
+        };
+        manager.load().await;
+        manager
+    }
+
+    //Read config from disk, on invalid config keep the file untouched and store errors for the UI
+    pub async fn load(&mut self) {
+        let con = tokio::fs::read_to_string(&self.conf_path).await.unwrap_or_default();
+        self.warnings.clear();
+        if con.trim().is_empty() {
+            self.errors.clear();
+            self.app_conf = AppConfig::init();
+            return;
+        }
+        match validator::validate(&con) {
+            Ok(app_conf) => {
+                self.errors.clear();
+                self.app_conf = app_conf;
+                self.warnings = validator::warnings(&con);
+                for e in &self.warnings {
+                    warn!(name: "Config warning", "{}", e);
+                }
+            }
+            Err(errors) => {
+                for e in &errors {
+                    error!(name: "Config error", "{}", e);
+                }
+                self.app_conf = AppConfig::init();
+                self.app_conf.state = AppConfig::lenient_state(&con);
+                self.errors = errors;
+            }
+        }
+        self.publish_profile();
+    }
+
+    //Send active profile to subsystems, broken profile falls back to base settings
+    pub fn publish_profile(&self) {
+        let profile = self.active_profile()
+            .unwrap_or_else(|_| ResolvedProfile::base(&self.app_conf));
+        self.profile_tx.send_if_modified(|current| {
+            if *current == profile {
+                return false;
+            }
+            *current = profile;
+            true
+        });
+    }
+
+    pub fn active_profile(&self) -> Result<ResolvedProfile, ProfileError> {
+        ResolvedProfile::resolve(&self.app_conf, self.app_conf.active_profile_name())
+    }
+
+    //Subsystems get a new value every time active profile changes
+    pub fn subscribe_profile(&self) -> watch::Receiver<ResolvedProfile> {
+        self.profile_tx.subscribe()
+    }
+
+    pub fn profile_names(&self) -> Vec<String> {
+        let mut names = vec![BASE_PROFILE.to_string()];
+        names.extend(self.app_conf.profiles.keys().cloned());
+        names
+    }
+
+    pub async fn switch_profile(&mut self, name: &str) -> Result<ResolvedProfile, ProfileError> {
+        let profile = ResolvedProfile::resolve(&self.app_conf, name)?;
+        let active_profile = if name == BASE_PROFILE {
+            None
+        } else {
+            Some(name.to_string())
+        };
+        self.update(|app_conf| app_conf.active_profile = active_profile).await.map_err(ProfileError::Save)?;
+        self.publish_profile();
+        Ok(profile)
+    }
+
+    //Config with errors is never written, it would overwrite what user has to fix
+    pub async fn save(&self) -> Result<(), ConfigSaveError> {
+        if !self.errors.is_empty() {
+            warn!("Config has errors, skip saving to keep user settings");
+            return Err(ConfigSaveError::InvalidConfig(self.errors.clone()));
+        }
+        let con = toml::to_string(&self.app_conf).map_err(|e| ConfigSaveError::Serialize(e.to_string()))?;
+        tokio::fs::write(&self.conf_path, con).await.map_err(|e| {
+            error!("{}", e);
+            ConfigSaveError::Io(e.to_string())
+        })
+    }
+
+    //Change config and save it, config in memory stays as it was when saving fails
+    pub async fn update<T>(&mut self, change: impl FnOnce(&mut AppConfig) -> T) -> Result<T, ConfigSaveError> {
+        let previous = self.app_conf.clone();
+        let result = change(&mut self.app_conf);
+        if let Err(e) = self.save().await {
+            self.app_conf = previous;
+            return Err(e);
+        }
+        Ok(result)
+    }
+
+    pub fn errors(&self) -> Vec<ConfigError> {
+        self.errors.clone()
+    }
+
+    pub fn warnings(&self) -> Vec<ConfigError> {
+        self.warnings.clone()
+    }
+
+    pub fn app_conf(&self) -> &AppConfig {
+        &self.app_conf
+    }
+
+    pub async fn export_bundle(&self, path: &Path) -> Result<(), BundleError> {
+        ConfigBundle::from_app_conf(&self.app_conf).write(path).await
+    }
+
+    pub async fn import_bundle(&mut self, path: &Path, strategy: ConflictStrategy) -> Result<ImportReport, BundleError> {
+        if !self.errors.is_empty() {
+            return Err(BundleError::InvalidConfig(self.errors.iter().map(|e| e.to_string()).collect()));
         }
+        let bundle = ConfigBundle::read(path).await?;
+        let (merged, report) = bundle.merge_into(&self.app_conf, strategy)?;
+        self.update(|app_conf| *app_conf = merged).await.map_err(BundleError::Save)?;
+        self.publish_profile();
+        Ok(report)
     }
 
     pub async fn get_state(&mut self) -> AppState {
         self.app_conf.state.clone()
     }
 
-    pub async fn set_state(&mut self, state: AppState) {
-        self.app_conf.set_state(state).await;
-        let con = toml::to_string(&self.app_conf).unwrap();
-        self.conf_file.write_all(con.as_bytes()).await.unwrap();
+    pub async fn set_state(&mut self, state: AppState) -> Result<(), ConfigSaveError> {
+        self.update(|app_conf| app_conf.state = state).await
     }
 
-    pub async fn setup(&mut self) {
-        self.app_conf.conf_first_setup(&mut self.conf_file).await;
+    pub async fn setup(&mut self) -> Result<(), ConfigSaveError> {
+        if self.app_conf.state == AppState::None && self.errors.is_empty() {
+            self.set_state(AppState::FirstRun).await?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct AppConfig {
     state: AppState,
-    plugins_conf: Vec<PluginConfig>
+    #[serde(default)]
+    pub index: IndexConfig,
+    #[serde(default)]
+    pub indexing: IndexingConfig,
+    #[serde(default)]
+    pub search: SearchConfig,
+    #[serde(default)]
+    pub active_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    #[serde(default)]
+    pub bookmarks: Vec<Bookmark>,
+    #[serde(default)]
+    pub keybindings: BTreeMap<String, String>,
+    #[serde(default)]
+    pub onboarding: OnboardingProgress,
+    #[serde(default)]
+    pub plugin_runtime: PluginRuntimeConfig,
+    #[serde(default)]
+    pub plugin_registry: PluginRegistryConfig,
+    #[serde(default)]
+    pub connector: ConnectorConfig,
+    //Plugins user switched off, they are loaded but not enabled on start
+    #[serde(default)]
+    pub disabled_plugins: BTreeSet<String>,
+    //Config section of every plugin, keyed by plugin id
+    #[serde(default, deserialize_with = "deserialize_plugins_conf")]
+    pub plugins_conf: BTreeMap<String, PluginConfig>
 }
 
 impl AppConfig {
     pub fn init() -> AppConfig {
         Self {
             state: AppState::None,
-            plugins_conf: vec![]
+            index: IndexConfig::default(),
+            indexing: IndexingConfig::default(),
+            search: SearchConfig::default(),
+            active_profile: None,
+            profiles: BTreeMap::new(),
+            bookmarks: vec![],
+            keybindings: BTreeMap::new(),
+            onboarding: OnboardingProgress::default(),
+            plugin_runtime: PluginRuntimeConfig::default(),
+            plugin_registry: PluginRegistryConfig::default(),
+            connector: ConnectorConfig::default(),
+            disabled_plugins: BTreeSet::new(),
+            plugins_conf: BTreeMap::new()
         }
     }
-    pub async fn conf_first_setup(&self, conf_file: &mut File) {
-        let app_conf = AppConfig {
-            state: AppState::FirstRun,
-            plugins_conf: vec![]
-        };
-        conf_file.write_all(toml::to_string(&app_conf).unwrap().as_bytes()).await.unwrap();
+    //Read only `state` key of broken config, so a typo elsewhere doesn't restart onboarding
+    fn lenient_state(con: &str) -> AppState {
+        toml::from_str::<toml::Table>(con).ok()
+            .and_then(|table| table.get("state").cloned())
+            .and_then(|state| state.try_into::<AppState>().ok())
+            .unwrap_or(AppState::None)
     }
 
-    pub async fn get_state(&self, conf_file: &mut File) -> AppState {
-        let mut con = "".to_string();
-        conf_file.read_to_string(&mut con).await.unwrap();
-        return if let Ok(app_conf) = toml::de::from_str::<AppConfig>(&con) {
-            app_conf.state
-        } else {
-            AppState::FirstRun
+    pub fn active_profile_name(&self) -> &str {
+        self.active_profile.as_deref().unwrap_or(BASE_PROFILE)
+    }
+
+    pub fn state(&self) -> AppState {
+        self.state.clone()
+    }
+}
+
+//Plugin configs were a list before they were keyed by id, entries of the list carry their `id`
+pub fn deserialize_plugins_conf<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, PluginConfig>, D::Error> {
+    match toml::Value::deserialize(deserializer)? {
+        toml::Value::Table(configs) => toml::Value::Table(configs).try_into().map_err(D::Error::custom),
+        toml::Value::Array(entries) => {
+            let mut configs = BTreeMap::new();
+            for entry in entries {
+                let mut entry = match entry {
+                    toml::Value::Table(entry) => entry,
+                    other => return Err(D::Error::custom(format!("plugin config must be a table, found {}", other.type_str()))),
+                };
+                let id = match entry.remove("id") {
+                    Some(toml::Value::String(id)) => id,
+                    _ => {
+                        warn!("Plugin config without `id` is dropped");
+                        continue;
+                    }
+                };
+                let config = toml::Value::Table(entry).try_into().map_err(D::Error::custom)?;
+                configs.insert(id, config);
+            }
+            Ok(configs)
+        }
+        other => Err(D::Error::custom(format!("invalid type: {}, expected a table of plugin configs", other.type_str()))),
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct IndexConfig {
+    #[serde(default)]
+    pub roots: Vec<PathBuf>,
+    #[serde(default)]
+    pub rules: Vec<IndexRule>,
+}
+
+impl IndexConfig {
+    //Last matching rule wins, path without matching rule follows its closest matched parent
+    //directory, paths no rule applies to are indexed
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        path.ancestors().find_map(|path| self.matching_action(path)) == Some(IndexRuleAction::Exclude)
+    }
+
+    fn matching_action(&self, path: &Path) -> Option<IndexRuleAction> {
+        self.rules.iter().rev()
+            .find(|rule| rule.pattern.matches_path(path))
+            .map(|rule| rule.action)
+    }
+
+    //Excluded directory is still walked when some include rule may match a path inside it
+    pub fn is_pruned(&self, dir: &Path) -> bool {
+        self.is_excluded(dir) && !self.rules.iter().any(|rule| rule.action == IndexRuleAction::Include && rule.may_match_inside(dir))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct IndexRule {
+    pub pattern: RulePattern,
+    pub action: IndexRuleAction,
+}
+
+//Glob of index rule, compiled once when config is read since it's matched for every walked path.
+//Invalid pattern matches nothing, validator reports it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(from = "String", into = "String")]
+pub struct RulePattern {
+    source: String,
+    compiled: Option<glob::Pattern>,
+}
+
+impl RulePattern {
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    fn matches_path(&self, path: &Path) -> bool {
+        self.compiled.as_ref().map_or(false, |pattern| pattern.matches_path(path))
+    }
+}
+
+impl From<String> for RulePattern {
+    fn from(source: String) -> Self {
+        let compiled = glob::Pattern::new(&source).ok();
+        Self { source, compiled }
+    }
+}
+
+impl From<&str> for RulePattern {
+    fn from(source: &str) -> Self {
+        Self::from(source.to_string())
+    }
+}
+
+impl From<RulePattern> for String {
+    fn from(pattern: RulePattern) -> Self {
+        pattern.source
+    }
+}
+
+impl PartialEq for RulePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl IndexRule {
+    //Compares `dir` with the part of pattern before its first wildcard, pattern starting
+    //with a wildcard may match anywhere
+    fn may_match_inside(&self, dir: &Path) -> bool {
+        let pattern = self.pattern.as_str();
+        let literal = &pattern[..pattern.find(['*', '?', '[']).unwrap_or(pattern.len())];
+        let prefix = Path::new(&literal[..literal.rfind(['/', '\\']).map_or(0, |i| i + 1)]);
+        prefix.starts_with(dir) || dir.starts_with(prefix)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum IndexRuleAction {
+    Include,
+    Exclude,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Bookmark {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SearchConfig {
+    pub host: String,
+    pub port: u16,
+    //Legacy plain text key, moved to secrets store on start
+    #[serde(default)]
+    pub master_key: Option<String>,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 7700,
+            master_key: None,
         }
+    }
+}
+
+//How plugin faults are contained, applied on app start
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct PluginRuntimeConfig {
+    //Plugin hook that runs longer is abandoned and plugin is stopped
+    pub call_timeout_ms: u64,
+    //Consecutive hook failures before plugin is disabled, 0 never disables. Plugin that hung or
+    //crashed its isolation is disabled on first failure anyway, it is never restarted
+    pub max_failures: u32,
+    //Ids of native plugins run in separate helper process, `*` for all of them
+    pub isolate: Vec<String>,
+    //Search returns what its sources found by then, late plugin providers are left out
+    pub search_deadline_ms: u64,
+}
 
+impl Default for PluginRuntimeConfig {
+    fn default() -> Self {
+        Self {
+            call_timeout_ms: 10000,
+            max_failures: 3,
+            isolate: vec![],
+            search_deadline_ms: 500,
+        }
     }
+}
+
+//Where plugins are installed from and whose signatures are trusted
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields, default)]
+pub struct PluginRegistryConfig {
+    //Directory with registry `index.toml`
+    pub index: Option<PathBuf>,
+    //Hex encoded ed25519 public keys of plugin publishers
+    pub trusted_keys: Vec<String>,
+    //Refuse unsigned archives even when no `trusted_keys` are set, with keys they are always refused
+    pub require_signature: bool,
+}
 
-    pub async fn set_state(&mut self, state: AppState) {
-        self.state = state;
+//How connections to local services are opened and restored after they drop
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct ConnectorConfig {
+    //Delay before first retry, doubled with every failed attempt
+    pub reconnect_initial_ms: u64,
+    pub reconnect_max_ms: u64,
+    //Failed attempts in a row before giving up, 0 retries forever
+    pub reconnect_attempts: u32,
+    //Outgoing messages kept while connection is down
+    pub queue_size: usize,
+    //Attempt that got no handshake by then fails
+    pub connect_timeout_ms: u64,
+    //Request without reply by then fails
+    pub request_timeout_ms: u64,
+    //Client pings every connection this often, 0 switches heartbeats off
+    pub heartbeat_interval_ms: u64,
+    //Connection without pong by then is dropped and reconnected
+    pub heartbeat_timeout_ms: u64,
+    //Trust CA certificates of the system for `wss://`
+    pub system_roots: bool,
+    //PEM files with extra CA certificates, e.g. of self-signed services
+    pub ca_certificates: Vec<PathBuf>,
+}
+
+impl Default for ConnectorConfig {
+    fn default() -> Self {
+        Self {
+            reconnect_initial_ms: 500,
+            reconnect_max_ms: 30000,
+            reconnect_attempts: 0,
+            queue_size: 256,
+            connect_timeout_ms: 10000,
+            request_timeout_ms: 30000,
+            heartbeat_interval_ms: 15000,
+            heartbeat_timeout_ms: 5000,
+            system_roots: true,
+            ca_certificates: vec![],
+        }
     }
+}
+
+#[derive(Serialize, Debug)]
+pub enum ConfigSaveError {
+    InvalidConfig(Vec<ConfigError>),
+    Serialize(String),
+    Io(String),
+}
 
+impl Display for ConfigSaveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSaveError::InvalidConfig(errors) => write!(f, "Config has {} errors, fix them before changing settings", errors.len()),
+            ConfigSaveError::Serialize(e) => write!(f, "Config can't be written: {}", e),
+            ConfigSaveError::Io(e) => write!(f, "Config can't be saved: {}", e),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 pub enum AppState {
     FirstRun,
     Stable,
     None,
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugins_conf_keyed_by_id() {
+        let app_conf: AppConfig = toml::from_str("state = \"Stable\"\n[plugins_conf.notes]\nsecrets = [\"token\"]\nfolder = \"docs\"\n").unwrap();
+        let notes = &app_conf.plugins_conf["notes"];
+        assert_eq!(notes.secrets, vec!["token".to_string()]);
+        assert_eq!(notes.value("folder").and_then(|v| v.as_str()), Some("docs"));
+    }
+
+    #[test]
+    fn legacy_plugins_conf_list() {
+        let app_conf: AppConfig = toml::from_str("state = \"Stable\"\n[[plugins_conf]]\nid = \"notes\"\nfolder = \"docs\"\n[[plugins_conf]]\nfolder = \"lost\"\n").unwrap();
+        assert_eq!(app_conf.plugins_conf.len(), 1);
+        assert_eq!(app_conf.plugins_conf["notes"].value("folder").and_then(|v| v.as_str()), Some("docs"));
+        assert!(app_conf.plugins_conf["notes"].value("id").is_none());
+
+        let empty: AppConfig = toml::from_str("state = \"Stable\"\nplugins_conf = []\n").unwrap();
+        assert!(empty.plugins_conf.is_empty());
+    }
+
+    #[test]
+    fn invalid_plugins_conf() {
+        assert!(toml::from_str::<AppConfig>("state = \"Stable\"\nplugins_conf = 1\n").is_err());
+        assert!(toml::from_str::<AppConfig>("state = \"Stable\"\nplugins_conf = [1]\n").is_err());
+    }
+
+    #[test]
+    fn include_rule_inside_excluded_dir() {
+        let rule = |pattern: &str, action| IndexRule { pattern: pattern.into(), action };
+        let index = IndexConfig {
+            roots: vec![],
+            rules: vec![
+                rule("**/node_modules", IndexRuleAction::Exclude),
+                rule("/home/me/app/node_modules/keep/**", IndexRuleAction::Include),
+            ],
+        };
+        assert!(index.is_excluded(Path::new("/home/me/app/node_modules")));
+        assert!(index.is_excluded(Path::new("/home/me/app/node_modules/other/index.js")));
+        assert!(!index.is_excluded(Path::new("/home/me/app/node_modules/keep/index.js")));
+        assert!(!index.is_excluded(Path::new("/home/me/app/src/main.js")));
+        assert!(!index.is_pruned(Path::new("/home/me/app/node_modules")));
+        assert!(index.is_pruned(Path::new("/home/me/web/node_modules")));
+    }
+
+    #[test]
+    fn rule_pattern_is_plain_string() {
+        let index: IndexConfig = toml::from_str("rules = [{ pattern = \"**/target\", action = \"Exclude\" }, { pattern = \"[\", action = \"Exclude\" }]").unwrap();
+        assert_eq!(index.rules[0].pattern.as_str(), "**/target");
+        assert!(index.is_excluded(Path::new("/home/me/app/target")));
+        assert!(!index.rules[1].pattern.matches_path(Path::new("[")));
+        assert_eq!(toml::Value::try_from(&index).unwrap()["rules"][0]["pattern"].as_str(), Some("**/target"));
+    }
+
+    #[tokio::test]
+    async fn failed_save_keeps_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = ConfigManager::new(dir.path().join("missing").join(".conf.toml")).await;
+        let result = config.update(|app_conf| app_conf.keybindings.insert("open".to_string(), "Ctrl+O".to_string())).await;
+        assert!(matches!(result, Err(ConfigSaveError::Io(_))));
+        assert!(config.app_conf().keybindings.is_empty());
+    }
+
+    #[tokio::test]
+    async fn invalid_config_is_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".conf.toml");
+        std::fs::write(&path, "state = \"Stable\"\nunknown = 1\n").unwrap();
+        let mut config = ConfigManager::new(path.clone()).await;
+        assert!(matches!(config.set_state(AppState::FirstRun).await, Err(ConfigSaveError::InvalidConfig(_))));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "state = \"Stable\"\nunknown = 1\n");
+    }
+}
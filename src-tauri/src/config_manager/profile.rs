@@ -0,0 +1,127 @@
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::config_manager::{AppConfig, ConfigSaveError, IndexConfig};
+
+pub const BASE_PROFILE: &str = "base";
+
+//Named set of overrides, sections not set here come from `inherits` profile or from base settings
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub inherits: Option<String>,
+    #[serde(default)]
+    pub index: Option<IndexConfig>,
+    #[serde(default)]
+    pub indexing: Option<IndexingConfig>,
+}
+
+//How hard indexer works, laptop setups usually want `Low`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum IndexingPriority {
+    Low,
+    Normal,
+    Aggressive,
+}
+
+impl Default for IndexingPriority {
+    fn default() -> Self {
+        IndexingPriority::Normal
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct IndexingConfig {
+    #[serde(default)]
+    pub priority: IndexingPriority,
+}
+
+impl IndexingConfig {
+    //Entries walked before indexer gives CPU back
+    pub fn batch_size(&self) -> u64 {
+        match self.priority {
+            IndexingPriority::Low => 200,
+            IndexingPriority::Normal => 2000,
+            IndexingPriority::Aggressive => u64::MAX,
+        }
+    }
+
+    pub fn pause_ms(&self) -> u64 {
+        match self.priority {
+            IndexingPriority::Low => 50,
+            IndexingPriority::Normal => 5,
+            IndexingPriority::Aggressive => 0,
+        }
+    }
+}
+
+//Profile with every inherited section filled in, what subsystems get on switch
+#[derive(Serialize, Clone, PartialEq, Debug)]
+pub struct ResolvedProfile {
+    pub name: String,
+    pub index: IndexConfig,
+    pub indexing: IndexingConfig,
+}
+
+impl ResolvedProfile {
+    pub fn base(app_conf: &AppConfig) -> Self {
+        ResolvedProfile {
+            name: BASE_PROFILE.to_string(),
+            index: app_conf.index.clone(),
+            indexing: app_conf.indexing.clone(),
+        }
+    }
+
+    pub fn resolve(app_conf: &AppConfig, name: &str) -> Result<Self, ProfileError> {
+        let mut resolved = Self::base(app_conf);
+        resolved.name = name.to_string();
+        if name == BASE_PROFILE {
+            return Ok(resolved);
+        }
+
+        let mut chain = vec![];
+        let mut current = Some(name.to_string());
+        while let Some(profile_name) = current {
+            if profile_name == BASE_PROFILE {
+                break;
+            }
+            if chain.contains(&profile_name) {
+                chain.push(profile_name);
+                return Err(ProfileError::Cycle(chain));
+            }
+            let profile = app_conf.profiles.get(&profile_name)
+                .ok_or_else(|| ProfileError::NotFound(profile_name.clone()))?;
+            chain.push(profile_name);
+            current = profile.inherits.clone();
+        }
+
+        //Apply from the most generic profile to the requested one
+        for profile_name in chain.iter().rev() {
+            let profile = &app_conf.profiles[profile_name];
+            if let Some(index) = &profile.index {
+                resolved.index = index.clone();
+            }
+            if let Some(indexing) = &profile.indexing {
+                resolved.indexing = indexing.clone();
+            }
+        }
+        Ok(resolved)
+    }
+}
+
+pub enum ProfileError {
+    NotFound(String),
+    Cycle(Vec<String>),
+    Save(ConfigSaveError),
+}
+
+impl Display for ProfileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProfileError::NotFound(name) => write!(f, "profile `{}` does not exist", name),
+            ProfileError::Cycle(chain) => write!(f, "profile inheritance cycle: {}", chain.join(" -> ")),
+            ProfileError::Save(e) => write!(f, "{}", e),
+        }
+    }
+}
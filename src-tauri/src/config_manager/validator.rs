@@ -0,0 +1,330 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+use crate::config_manager::AppConfig;
+use crate::config_manager::profile::{ResolvedProfile, BASE_PROFILE};
+
+//Error found while reading the config file, sent to the UI with `config-error` event.
+//Missing index roots are sent with `config-warning`, they leave config valid
+#[derive(Serialize, Clone, Debug)]
+pub struct ConfigError {
+    pub kind: ConfigErrorKind,
+    pub message: String,
+    pub key: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub enum ConfigErrorKind {
+    Syntax,
+    UnknownKey,
+    TypeMismatch,
+    MissingKey,
+    Semantic,
+    //Warning only, folder may be on a drive that isn't plugged in
+    MissingRoot,
+}
+
+impl ConfigError {
+    fn new(kind: ConfigErrorKind, message: String, source: &str, span: Option<Range<usize>>) -> Self {
+        let (line, column) = match span {
+            Some(span) => {
+                let (line, column) = line_col(source, span.start);
+                (Some(line), Some(column))
+            }
+            None => (None, None)
+        };
+        Self {
+            kind,
+            message,
+            key: None,
+            line,
+            column,
+        }
+    }
+
+    fn with_key(mut self, key: &str) -> Self {
+        self.key = Some(key.to_string());
+        self
+    }
+
+    fn from_toml(error: toml::de::Error, source: &str) -> Self {
+        let message = error.message().to_string();
+        let kind = if message.starts_with("unknown field") || message.starts_with("unknown variant") {
+            ConfigErrorKind::UnknownKey
+        } else if message.starts_with("invalid type") || message.starts_with("invalid value") || message.starts_with("invalid length") {
+            ConfigErrorKind::TypeMismatch
+        } else if message.starts_with("missing field") {
+            ConfigErrorKind::MissingKey
+        } else {
+            ConfigErrorKind::Syntax
+        };
+        let key = match kind {
+            ConfigErrorKind::UnknownKey | ConfigErrorKind::MissingKey => message.split('`').nth(1).map(|key| key.to_string()),
+            _ => None
+        };
+        let mut error = ConfigError::new(kind, message, source, error.span());
+        error.key = key;
+        error
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "{:?} at {}:{}: {}", self.kind, line, column, self.message),
+            _ => write!(f, "{:?}: {}", self.kind, self.message)
+        }
+    }
+}
+
+//1-based line and column of byte offset in source
+fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
+
+//Mirror of semantic fields with their positions in the file
+#[derive(Deserialize, Default)]
+struct SpannedConfig {
+    #[serde(default)]
+    index: Option<SpannedIndex>,
+    #[serde(default)]
+    search: Option<SpannedSearch>,
+    #[serde(default)]
+    active_profile: Option<Spanned<String>>,
+    #[serde(default)]
+    profiles: BTreeMap<String, SpannedProfile>,
+}
+
+#[derive(Deserialize)]
+struct SpannedProfile {
+    #[serde(default)]
+    inherits: Option<Spanned<String>>,
+    #[serde(default)]
+    index: Option<SpannedIndex>,
+}
+
+#[derive(Deserialize)]
+struct SpannedIndex {
+    #[serde(default)]
+    roots: Vec<Spanned<PathBuf>>,
+    #[serde(default)]
+    rules: Vec<SpannedRule>,
+}
+
+#[derive(Deserialize)]
+struct SpannedRule {
+    pattern: Spanned<String>,
+}
+
+#[derive(Deserialize)]
+struct SpannedSearch {
+    #[serde(default)]
+    host: Option<Spanned<String>>,
+    #[serde(default)]
+    port: Option<Spanned<u16>>,
+}
+
+fn check_index(index: SpannedIndex, key: &str, source: &str, errors: &mut Vec<ConfigError>) {
+    for rule in index.rules {
+        if let Err(e) = glob::Pattern::new(rule.pattern.get_ref()) {
+            errors.push(ConfigError::new(
+                ConfigErrorKind::Semantic,
+                format!("invalid index rule pattern `{}`: {}", rule.pattern.get_ref(), e),
+                source,
+                Some(rule.pattern.span()),
+            ).with_key(&format!("{}.rules", key)));
+        }
+    }
+}
+
+fn check_roots(index: SpannedIndex, key: &str, source: &str, warnings: &mut Vec<ConfigError>) {
+    for root in index.roots {
+        if !root.get_ref().is_dir() {
+            warnings.push(ConfigError::new(
+                ConfigErrorKind::MissingRoot,
+                format!("index root `{}` does not exist or is not a directory", root.get_ref().display()),
+                source,
+                Some(root.span()),
+            ).with_key(&format!("{}.roots", key)));
+        }
+    }
+}
+
+//Index roots that are missing now, of every profile. Config stays valid, indexing skips them
+pub fn warnings(source: &str) -> Vec<ConfigError> {
+    let spanned = toml::from_str::<SpannedConfig>(source).unwrap_or_default();
+    let mut warnings = vec![];
+    if let Some(index) = spanned.index {
+        check_roots(index, "index", source, &mut warnings);
+    }
+    for (name, profile) in spanned.profiles {
+        if let Some(index) = profile.index {
+            check_roots(index, &format!("profiles.{}.index", name), source, &mut warnings);
+        }
+    }
+    warnings
+}
+
+//Serde stops at the first error, the other ones are found by checking every other top-level key
+//on its own against default config. Those point at the start of their key's value
+fn type_errors(first: toml::de::Error, source: &str) -> Vec<ConfigError> {
+    let first = ConfigError::from_toml(first, source);
+    let first_line = first.line;
+    let keys = match toml::from_str::<BTreeMap<String, Spanned<toml::Value>>>(source) {
+        Ok(keys) => keys,
+        Err(_) => return vec![first],
+    };
+    let defaults = toml::Table::try_from(AppConfig::init()).unwrap_or_default();
+    let mut errors = vec![first];
+    for (key, value) in keys {
+        let span = value.span();
+        let (line, column) = line_col(source, span.start);
+        let mut config = defaults.clone();
+        config.insert(key.clone(), value.into_inner());
+        if let Err(e) = toml::Value::Table(config).try_into::<AppConfig>() {
+            let mut error = ConfigError::from_toml(e, source);
+            if error.message == errors[0].message && first_line.map_or(true, |first_line| first_line >= line) {
+                continue;
+            }
+            error.line = Some(line);
+            error.column = Some(column);
+            error.key = error.key.or(Some(key));
+            errors.push(error);
+        }
+    }
+    errors
+}
+
+//Parse and check config source, returns every error found instead of falling back to defaults.
+//Syntax error stops parsing, so it is the only one reported
+pub fn validate(source: &str) -> Result<AppConfig, Vec<ConfigError>> {
+    if let Err(e) = toml::from_str::<toml::Table>(source) {
+        return Err(vec![ConfigError::from_toml(e, source)]);
+    }
+    let app_conf = match toml::from_str::<AppConfig>(source) {
+        Ok(app_conf) => app_conf,
+        Err(e) => return Err(type_errors(e, source))
+    };
+    let spanned = toml::from_str::<SpannedConfig>(source).unwrap_or_default();
+
+    let mut errors = vec![];
+    if let Some(index) = spanned.index {
+        check_index(index, "index", source, &mut errors);
+    }
+    for (name, profile) in spanned.profiles {
+        if let Some(index) = profile.index {
+            check_index(index, &format!("profiles.{}.index", name), source, &mut errors);
+        }
+        if let Some(inherits) = profile.inherits {
+            if let Err(e) = ResolvedProfile::resolve(&app_conf, &name) {
+                errors.push(ConfigError::new(
+                    ConfigErrorKind::Semantic,
+                    e.to_string(),
+                    source,
+                    Some(inherits.span()),
+                ).with_key(&format!("profiles.{}.inherits", name)));
+            }
+        }
+    }
+    if let Some(active_profile) = spanned.active_profile {
+        let name = active_profile.get_ref();
+        if name != BASE_PROFILE && !app_conf.profiles.contains_key(name) {
+            errors.push(ConfigError::new(
+                ConfigErrorKind::Semantic,
+                format!("active profile `{}` does not exist", name),
+                source,
+                Some(active_profile.span()),
+            ).with_key("active_profile"));
+        }
+    }
+    if let Some(search) = spanned.search {
+        if let Some(port) = search.port {
+            if *port.get_ref() == 0 {
+                errors.push(ConfigError::new(
+                    ConfigErrorKind::Semantic,
+                    "search port must be between 1 and 65535".to_string(),
+                    source,
+                    Some(port.span()),
+                ).with_key("search.port"));
+            }
+        }
+        if let Some(host) = search.host {
+            if host.get_ref().trim().is_empty() {
+                errors.push(ConfigError::new(
+                    ConfigErrorKind::Semantic,
+                    "search host can't be empty".to_string(),
+                    source,
+                    Some(host.span()),
+                ).with_key("search.host"));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(app_conf)
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col_is_one_based() {
+        let source = "state = 1\nnäme = 2\n";
+        assert_eq!(line_col(source, 0), (1, 1));
+        assert_eq!(line_col(source, 10), (2, 1));
+        assert_eq!(line_col(source, source.rfind('=').unwrap()), (2, 6));
+        assert_eq!(line_col(source, 1000), (3, 1));
+    }
+
+    #[test]
+    fn every_broken_key_is_reported() {
+        let source = "state = \"Stable\"\nunknown = 1\n\n[index]\nroots = 1\n\n[search]\nhost = \"localhost\"\nport = \"fast\"\n";
+        let errors = validate(source).err().unwrap();
+        let found: Vec<(ConfigErrorKind, Option<&str>, Option<usize>)> = errors.iter().map(|e| (e.kind.clone(), e.key.as_deref(), e.line)).collect();
+        assert_eq!(found.len(), 3, "{:?}", errors);
+        assert!(found.contains(&(ConfigErrorKind::UnknownKey, Some("unknown"), Some(2))), "{:?}", found);
+        assert!(found.iter().any(|(kind, key, line)| *kind == ConfigErrorKind::TypeMismatch && *key == Some("index") && line.map_or(false, |line| (4..=5).contains(&line))), "{:?}", found);
+        assert!(found.iter().any(|(kind, key, line)| *kind == ConfigErrorKind::TypeMismatch && *key == Some("search") && line.map_or(false, |line| (7..=9).contains(&line))), "{:?}", found);
+    }
+
+    #[test]
+    fn semantic_errors_are_collected() {
+        let source = "state = \"Stable\"\nactive_profile = \"work\"\n\n[search]\nhost = \" \"\nport = 0\n";
+        let errors = validate(source).err().unwrap();
+        let keys: Vec<Option<&str>> = errors.iter().map(|e| e.key.as_deref()).collect();
+        assert_eq!(keys, vec![Some("active_profile"), Some("search.port"), Some("search.host")]);
+        assert_eq!(errors[0].line, Some(2));
+        assert_eq!(errors[1].line, Some(6));
+    }
+
+    #[test]
+    fn missing_root_is_only_warning() {
+        let source = "state = \"Stable\"\n\n[index]\nroots = [\"/no/such/root\"]\n\n[profiles.work.index]\nroots = [\"/no/such/work\"]\n";
+        assert!(validate(source).is_ok());
+        let warnings = warnings(source);
+        let keys: Vec<Option<&str>> = warnings.iter().map(|e| e.key.as_deref()).collect();
+        assert_eq!(keys, vec![Some("index.roots"), Some("profiles.work.index.roots")]);
+        assert!(warnings.iter().all(|e| e.kind == ConfigErrorKind::MissingRoot && e.line.is_some()));
+    }
+
+    #[test]
+    fn syntax_error_is_only_error() {
+        let errors = validate("state = \"Stable\"\n[index\nunknown = 1\n").err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ConfigErrorKind::Syntax);
+        assert_eq!(errors[0].line, Some(2));
+    }
+}
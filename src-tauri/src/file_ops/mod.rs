@@ -0,0 +1,235 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use tauri::{command, State};
+use tokio::sync::broadcast;
+use tracing::info;
+use walkdir::WalkDir;
+use starship_plugin_api::context::{FileEntry, HostEvent};
+use crate::file_ops::watcher::{FsWatcher, OwnChanges};
+
+pub mod watcher;
+
+pub type EventBus = broadcast::Sender<HostEvent>;
+pub type SharedFileOps = Arc<FileOps>;
+
+pub fn event_bus() -> EventBus {
+    broadcast::channel(256).0
+}
+
+//MIME type by extension, by content for files without known extension
+pub fn mime_type(path: &Path) -> String {
+    mime_guess::from_path(path).first_raw()
+        .map(|mime| mime.to_string())
+        .or_else(|| infer::get_from_path(path).ok().flatten().map(|kind| kind.mime_type().to_string()))
+        .unwrap_or_else(|| "application/octet-stream".to_string())
+}
+
+//File operations used by UI and plugins, every change is published to event bus.
+//Changes made outside the app are published by watcher from `watch`
+pub struct FileOps {
+    events: EventBus,
+    own: OwnChanges,
+}
+
+impl FileOps {
+    pub fn new(events: EventBus) -> Self {
+        Self { events, own: OwnChanges::default() }
+    }
+
+    //Watcher skips changes this instance already published
+    pub fn watch(&self) -> Result<FsWatcher, FileOpError> {
+        FsWatcher::new(self.events.clone(), self.own.clone())
+    }
+
+    fn publish(&self, event: HostEvent) {
+        self.own.record(&event);
+        //No subscribers is not an error
+        let _ = self.events.send(event);
+    }
+
+    fn entry(path: &Path) -> Result<FileEntry, FileOpError> {
+        let metadata = std::fs::metadata(path).map_err(|e| FileOpError::io(path, e))?;
+        let modified = metadata.modified().ok().map(|time| DateTime::<Local>::from(time).to_rfc3339());
+        Ok(FileEntry {
+            path: path.to_path_buf(),
+            name: path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified,
+        })
+    }
+
+    pub fn list_dir(&self, path: &Path) -> Result<Vec<FileEntry>, FileOpError> {
+        let mut entries = vec![];
+        for entry in std::fs::read_dir(path).map_err(|e| FileOpError::io(path, e))? {
+            let entry = entry.map_err(|e| FileOpError::io(path, e))?;
+            entries.push(Self::entry(&entry.path())?);
+        }
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        Ok(entries)
+    }
+
+    pub fn read(&self, path: &Path) -> Result<Vec<u8>, FileOpError> {
+        std::fs::read(path).map_err(|e| FileOpError::io(path, e))
+    }
+
+    pub fn write(&self, path: &Path, data: &[u8]) -> Result<(), FileOpError> {
+        let existed = path.exists();
+        std::fs::write(path, data).map_err(|e| FileOpError::io(path, e))?;
+        self.publish(if existed {
+            HostEvent::FileModified { path: path.to_path_buf() }
+        } else {
+            HostEvent::FileCreated { path: path.to_path_buf() }
+        });
+        Ok(())
+    }
+
+    //Directories are copied with their content, existing target is never overwritten
+    pub fn copy(&self, from: &Path, to: &Path) -> Result<(), FileOpError> {
+        if to.exists() {
+            return Err(FileOpError::AlreadyExists(to.to_path_buf()));
+        }
+        if from.is_dir() {
+            //Copy would walk into what it's writing
+            let parent = to.parent().and_then(|parent| parent.canonicalize().ok());
+            let source = from.canonicalize().map_err(|e| FileOpError::io(from, e))?;
+            if parent.map_or(false, |parent| parent.starts_with(&source)) {
+                return Err(FileOpError::InsideSource(to.to_path_buf()));
+            }
+            for entry in WalkDir::new(from) {
+                let entry = entry.map_err(|e| FileOpError::Io(from.to_path_buf(), e.to_string()))?;
+                let target = to.join(entry.path().strip_prefix(from).unwrap_or(entry.path()));
+                if entry.file_type().is_dir() {
+                    std::fs::create_dir_all(&target).map_err(|e| FileOpError::io(&target, e))?;
+                } else {
+                    std::fs::copy(entry.path(), &target).map_err(|e| FileOpError::io(entry.path(), e))?;
+                }
+            }
+        } else {
+            std::fs::copy(from, to).map_err(|e| FileOpError::io(from, e))?;
+        }
+        info!("Copied {} to {}", from.display(), to.display());
+        self.publish(HostEvent::FileCreated { path: to.to_path_buf() });
+        Ok(())
+    }
+
+    pub fn rename(&self, from: &Path, to: &Path) -> Result<(), FileOpError> {
+        if to.exists() {
+            return Err(FileOpError::AlreadyExists(to.to_path_buf()));
+        }
+        std::fs::rename(from, to).map_err(|e| FileOpError::io(from, e))?;
+        info!("Moved {} to {}", from.display(), to.display());
+        self.publish(HostEvent::FileMoved { from: from.to_path_buf(), to: to.to_path_buf() });
+        Ok(())
+    }
+
+    pub fn delete(&self, path: &Path) -> Result<(), FileOpError> {
+        if path.is_dir() {
+            std::fs::remove_dir_all(path).map_err(|e| FileOpError::io(path, e))?;
+        } else {
+            std::fs::remove_file(path).map_err(|e| FileOpError::io(path, e))?;
+        }
+        info!("Deleted {}", path.display());
+        self.publish(HostEvent::FileRemoved { path: path.to_path_buf() });
+        Ok(())
+    }
+
+    //Parent has to exist, like for files
+    pub fn create_dir(&self, path: &Path) -> Result<(), FileOpError> {
+        if path.exists() {
+            return Err(FileOpError::AlreadyExists(path.to_path_buf()));
+        }
+        if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty() && !parent.is_dir()) {
+            return Err(FileOpError::NotFound(parent.to_path_buf()));
+        }
+        std::fs::create_dir(path).map_err(|e| FileOpError::io(path, e))?;
+        self.publish(HostEvent::FileCreated { path: path.to_path_buf() });
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub enum FileOpError {
+    NotFound(PathBuf),
+    AlreadyExists(PathBuf),
+    InsideSource(PathBuf),
+    Io(PathBuf, String),
+    Watch(String),
+}
+
+impl FileOpError {
+    fn io(path: &Path, e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::NotFound => FileOpError::NotFound(path.to_path_buf()),
+            _ => FileOpError::Io(path.to_path_buf(), e.to_string()),
+        }
+    }
+}
+
+impl Display for FileOpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileOpError::NotFound(path) => write!(f, "{} not found", path.display()),
+            FileOpError::AlreadyExists(path) => write!(f, "{} already exists", path.display()),
+            FileOpError::InsideSource(path) => write!(f, "{} is inside copied directory", path.display()),
+            FileOpError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            FileOpError::Watch(e) => write!(f, "Can't watch filesystem: {}", e),
+        }
+    }
+}
+
+//Copy and delete walk whole trees, commands run them on a blocking thread
+async fn with_file_ops<T, F>(file_ops: &SharedFileOps, f: F) -> Result<T, FileOpError>
+where T: Send + 'static, F: FnOnce(&FileOps) -> Result<T, FileOpError> + Send + 'static {
+    let file_ops = file_ops.clone();
+    match tokio::task::spawn_blocking(move || f(&file_ops)).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+#[command]
+pub async fn list_dir(file_ops: State<'_, SharedFileOps>, path: PathBuf) -> Result<Vec<FileEntry>, FileOpError> {
+    with_file_ops(&file_ops, move |file_ops| file_ops.list_dir(&path)).await
+}
+
+#[command]
+pub async fn copy_path(file_ops: State<'_, SharedFileOps>, from: PathBuf, to: PathBuf) -> Result<(), FileOpError> {
+    with_file_ops(&file_ops, move |file_ops| file_ops.copy(&from, &to)).await
+}
+
+#[command]
+pub async fn move_path(file_ops: State<'_, SharedFileOps>, from: PathBuf, to: PathBuf) -> Result<(), FileOpError> {
+    with_file_ops(&file_ops, move |file_ops| file_ops.rename(&from, &to)).await
+}
+
+#[command]
+pub async fn delete_path(file_ops: State<'_, SharedFileOps>, path: PathBuf) -> Result<(), FileOpError> {
+    with_file_ops(&file_ops, move |file_ops| file_ops.delete(&path)).await
+}
+
+#[command]
+pub async fn create_dir(file_ops: State<'_, SharedFileOps>, path: PathBuf) -> Result<(), FileOpError> {
+    with_file_ops(&file_ops, move |file_ops| file_ops.create_dir(&path)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copy_into_own_subdirectory_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("notes");
+        std::fs::create_dir(&from).unwrap();
+        std::fs::write(from.join("todo.txt"), b"todo").unwrap();
+        let file_ops = FileOps::new(event_bus());
+        assert!(matches!(file_ops.copy(&from, &from.join("copy")), Err(FileOpError::InsideSource(_))));
+        assert!(!from.join("copy").exists());
+        file_ops.copy(&from, &dir.path().join("notes copy")).unwrap();
+        assert!(dir.path().join("notes copy/todo.txt").exists());
+    }
+}
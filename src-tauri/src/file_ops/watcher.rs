@@ -0,0 +1,132 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tracing::{error, warn};
+use starship_plugin_api::context::HostEvent;
+use crate::file_ops::{EventBus, FileOpError};
+
+//Changes done by app are published by `FileOps` right away, watcher skips them for this long
+const OWN_CHANGE_WINDOW: Duration = Duration::from_secs(2);
+
+//Paths changed through `FileOps` lately, changes inside them are already published
+#[derive(Clone, Default)]
+pub struct OwnChanges(Arc<Mutex<Vec<(PathBuf, Instant)>>>);
+
+impl OwnChanges {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<(PathBuf, Instant)>> {
+        self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn record(&self, event: &HostEvent) {
+        let now = Instant::now();
+        let mut changes = self.lock();
+        changes.retain(|(_, at)| now.duration_since(*at) < OWN_CHANGE_WINDOW);
+        match event {
+            HostEvent::FileCreated { path } | HostEvent::FileModified { path } | HostEvent::FileRemoved { path } => {
+                changes.push((path.clone(), now));
+            }
+            HostEvent::FileMoved { from, to } => {
+                changes.push((from.clone(), now));
+                changes.push((to.clone(), now));
+            }
+            _ => {}
+        }
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        let now = Instant::now();
+        self.lock().iter().any(|(changed, at)| now.duration_since(*at) < OWN_CHANGE_WINDOW && path.starts_with(changed))
+    }
+}
+
+//Publishes changes made outside the app under watched roots, e.g. index roots of active profile
+pub struct FsWatcher {
+    watcher: RecommendedWatcher,
+    roots: Vec<PathBuf>,
+}
+
+impl FsWatcher {
+    pub fn new(events: EventBus, own: OwnChanges) -> Result<Self, FileOpError> {
+        let watcher = notify::recommended_watcher(move |result: notify::Result<Event>| match result {
+            Ok(event) => {
+                for event in host_events(event) {
+                    let external = match &event {
+                        HostEvent::FileMoved { from, to } => !own.contains(from) && !own.contains(to),
+                        HostEvent::FileCreated { path } | HostEvent::FileModified { path } | HostEvent::FileRemoved { path } => !own.contains(path),
+                        _ => false,
+                    };
+                    if external {
+                        let _ = events.send(event);
+                    }
+                }
+            }
+            Err(e) => warn!("Filesystem watcher error: {}", e),
+        }).map_err(|e| FileOpError::Watch(e.to_string()))?;
+        Ok(Self { watcher, roots: vec![] })
+    }
+
+    //Replace watched roots, roots that can't be watched are logged and skipped
+    pub fn watch_roots(&mut self, roots: &[PathBuf]) {
+        for root in self.roots.drain(..) {
+            if let Err(e) = self.watcher.unwatch(&root) {
+                warn!("Can't stop watching {}: {}", root.display(), e);
+            }
+        }
+        for root in roots {
+            match self.watcher.watch(root, RecursiveMode::Recursive) {
+                Ok(_) => self.roots.push(root.clone()),
+                Err(e) => error!("Can't watch {}: {}", root.display(), e),
+            }
+        }
+    }
+}
+
+fn host_events(event: Event) -> Vec<HostEvent> {
+    let mut paths = event.paths.into_iter();
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            paths.map(|path| HostEvent::FileCreated { path }).collect()
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            paths.map(|path| HostEvent::FileRemoved { path }).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => match (paths.next(), paths.next()) {
+            (Some(from), Some(to)) => vec![HostEvent::FileMoved { from, to }],
+            _ => vec![],
+        },
+        EventKind::Modify(_) => paths.map(|path| HostEvent::FileModified { path }).collect(),
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{CreateKind, DataChange};
+
+    #[test]
+    fn rename_with_both_paths_is_move() {
+        let event = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::Both)))
+            .add_path(PathBuf::from("/notes/a"))
+            .add_path(PathBuf::from("/notes/b"));
+        assert_eq!(host_events(event), vec![HostEvent::FileMoved { from: PathBuf::from("/notes/a"), to: PathBuf::from("/notes/b") }]);
+    }
+
+    #[test]
+    fn created_and_modified_paths() {
+        let created = Event::new(EventKind::Create(CreateKind::File)).add_path(PathBuf::from("/notes/a"));
+        let modified = Event::new(EventKind::Modify(ModifyKind::Data(DataChange::Content))).add_path(PathBuf::from("/notes/a"));
+        assert_eq!(host_events(created), vec![HostEvent::FileCreated { path: PathBuf::from("/notes/a") }]);
+        assert_eq!(host_events(modified), vec![HostEvent::FileModified { path: PathBuf::from("/notes/a") }]);
+    }
+
+    #[test]
+    fn own_changes_cover_paths_inside() {
+        let own = OwnChanges::default();
+        own.record(&HostEvent::FileCreated { path: PathBuf::from("/notes/copy") });
+        assert!(own.contains(Path::new("/notes/copy/todo.txt")));
+        assert!(!own.contains(Path::new("/notes/other")));
+    }
+}
@@ -11,11 +11,30 @@ mod tasker;
 mod config_manager;
 mod ws_connector;
 mod blazzy_client;
+mod onboarding;
+mod secrets;
+mod plugin_manager;
+mod file_ops;
+mod search;
+mod rpc;
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
-    let mut app = App::init_conf(None).await;
+    //Same executable hosts isolated plugins, see `PluginRuntimeConfig::isolate`
+    if let Some(code) = starship_plugin_api::isolation::run_helper_from_args() {
+        std::process::exit(code);
+    }
+    if let Some(code) = plugin_manager::cli::run_cli_from_args().await {
+        std::process::exit(code);
+    }
+    let mut app = match App::init_conf(None).await {
+        Ok(app) => app,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
     if let AppState::FirstRun =  app.get_state().await {
         app.conf_first_setup().await;
     }
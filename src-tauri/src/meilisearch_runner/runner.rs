@@ -5,18 +5,27 @@ use std::os::windows::fs::MetadataExt;
 use std::os::windows::process::CommandExt;
 use std::path::{Path, PathBuf};
 use std::process::{Child, Command};
-use std::time::UNIX_EPOCH;
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
 use chrono::{DateTime, Local};
 use futures::StreamExt;
 use indicatif::{ProgressBar, ProgressStyle};
 use meilisearch_sdk::client::Client;
+use meilisearch_sdk::search::SearchResults;
+use meilisearch_sdk::task_info::TaskInfo;
 use passwords::PasswordGenerator;
 use serde::{Deserialize, Serialize};
 use tokio::io;
 use tokio::io::Error;
+use tokio::sync::Mutex;
 use tokio_stream::wrappers::ReadDirStream;
 use walkdir::WalkDir;
-use tracing::info;
+use tracing::{info, warn};
+use crate::config_manager::IndexConfig;
+use crate::config_manager::profile::IndexingConfig;
+use crate::secrets::{SecretError, Secrets, MASTER_KEY_SECRET};
+use starship_plugin_api::context::SearchHit;
+use starship_plugin_api::search::SearchItem;
 
 //Structure for send data about files to local meilisearch server
 #[derive(Serialize, Deserialize)]
@@ -24,7 +33,10 @@ pub struct DataFile {
     id: i32,
     file_path: String,
     file_name: String,
-    metadata: Option<Metadata>
+    metadata: Option<Metadata>,
+    //Extracted by plugins, keyed by plugin id
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    plugin_metadata: serde_json::Map<String, serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -40,6 +52,20 @@ pub struct Metadata {
     created: String
 }
 
+pub type SharedRunner = Arc<Mutex<Option<MeilisearchRunner>>>;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+//Adding documents of a large root takes a while on slow disks
+const INDEX_TASK_TIMEOUT: Duration = Duration::from_secs(600);
+
+//Progress of indexing, reported at most every `PROGRESS_INTERVAL` and after last entry of root
+#[derive(Serialize, Clone)]
+pub struct IndexProgress {
+    pub root: String,
+    pub done: u64,
+    pub total: u64,
+}
+
 //Structure for work with meilisearch server
 pub struct MeilisearchRunner {
     host: MeilisearchHost,
@@ -48,6 +74,7 @@ pub struct MeilisearchRunner {
     process: Option<Child>,
     data_dir: PathBuf,
     exe_path: Option<PathBuf>,
+    indexing: IndexingConfig,
 }
 
 impl MeilisearchRunner {
@@ -67,6 +94,7 @@ impl MeilisearchRunner {
             process: None,
             data_dir,
             exe_path: None,
+            indexing: IndexingConfig::default(),
         };
         let exe_path = runner.data_dir.clone().join("search_engine.exe");
 
@@ -126,36 +154,129 @@ impl MeilisearchRunner {
         info!("Client run");
     }
 
-    //Update information about file system in meilisearch
-    pub async fn update_fs_info(&self) {
-        info!("updating info");
+    //Change indexer load, used when active profile is switched
+    pub fn set_indexing(&mut self, indexing: IndexingConfig) {
+        info!("Indexing priority set to {:?}", indexing.priority);
+        self.indexing = indexing;
+    }
+
+    //Wait until server answers health checks
+    pub async fn wait_ready(&self, attempts: u32) -> Result<(), MeilisearchRunnerError> {
         if let Some(client) = self.client.clone() {
-            if let Err(_) = client.get_index("files").await {
-                client.create_index("files", None).await.unwrap();
+            for _ in 0..attempts {
+                if client.is_healthy().await {
+                    return Ok(());
+                }
+                tokio::time::sleep(Duration::from_millis(500)).await;
             }
-            let files = client.index("files");
-            let data = files.search().execute::<DataFile>().await.expect("Failed to execute search");
-            let mut id = 0;
-            if !data.hits.is_empty() {
-                id = data.hits.into_iter().last().unwrap().result.id + 1
+        }
+        Err(MeilisearchRunnerError::NotReady)
+    }
+
+    //Indexing works on its own copy of client, so plugins extracting metadata can search meanwhile
+    pub fn indexer(&self) -> Result<Indexer, MeilisearchRunnerError> {
+        Ok(Indexer {
+            client: self.client.clone().ok_or(MeilisearchRunnerError::NotReady)?,
+            indexing: self.indexing.clone(),
+        })
+    }
+
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, MeilisearchRunnerError> {
+        let results = self.search_files(query, limit).await?;
+        Ok(results.hits.into_iter().map(|hit| SearchHit {
+            path: hit.result.file_path,
+            name: hit.result.file_name,
+        }).collect())
+    }
+
+    //Files as items of app search, scored by meilisearch ranking
+    pub async fn search_items(&self, query: &str, limit: usize) -> Result<Vec<SearchItem>, MeilisearchRunnerError> {
+        let results = self.search_files(query, limit).await?;
+        Ok(results.hits.into_iter().map(|hit| SearchItem {
+            title: hit.result.file_name,
+            detail: hit.result.file_path.clone(),
+            path: Some(PathBuf::from(hit.result.file_path)),
+            score: hit.ranking_score,
+        }).collect())
+    }
+
+    async fn search_files(&self, query: &str, limit: usize) -> Result<SearchResults<DataFile>, MeilisearchRunnerError> {
+        let client = self.client.clone().ok_or(MeilisearchRunnerError::NotReady)?;
+        client.index("files")
+            .search()
+            .with_query(query)
+            .with_limit(limit)
+            .with_show_ranking_score(true)
+            .execute::<DataFile>()
+            .await
+            .map_err(MeilisearchRunnerError::Client)
+    }
+
+}
+
+//Walks index roots and sends entries to search engine
+pub struct Indexer {
+    client: Client,
+    indexing: IndexingConfig,
+}
+
+impl Indexer {
+    //Rebuild file index from index roots, `extract` adds metadata of plugins to every entry. Index is
+    //cleared first, so interrupted or repeated runs don't leave duplicates and ids count from 0
+    pub async fn update_fs_info<F, E>(&self, index: &IndexConfig, progress: F, extract: E) -> Result<(), MeilisearchRunnerError>
+    where F: Fn(IndexProgress), E: Fn(&Path) -> serde_json::Map<String, serde_json::Value> {
+        info!("updating info");
+        let client = &self.client;
+        if client.get_index("files").await.is_err() {
+            let task = client.create_index("files", None).await.map_err(MeilisearchRunnerError::Client)?;
+            self.wait_task(task).await?;
+        }
+        let files = client.index("files");
+        let task = files.delete_all_documents().await.map_err(MeilisearchRunnerError::Client)?;
+        self.wait_task(task).await?;
+        let mut id = 0;
+        for root in &index.roots {
+            if !root.is_dir() {
+                warn!("Index root {} is missing, skipped", root.display());
+                continue;
             }
-            let data_arr = self.walkdir(id, "C:\\").await;
-            files.add_documents(&data_arr, Some("id")).await.unwrap();
+            let data_arr = self.walkdir(id, root, index, &progress, &extract).await;
+            id += data_arr.len() as i32;
+            let task = files.add_documents(&data_arr, Some("id")).await.map_err(MeilisearchRunnerError::Client)?;
+            self.wait_task(task).await?;
+        }
+        Ok(())
+    }
+
+    //Index is only complete once search engine processed the task, not when it accepted it
+    async fn wait_task(&self, task: TaskInfo) -> Result<(), MeilisearchRunnerError> {
+        let task = task.wait_for_completion(&self.client, None, Some(INDEX_TASK_TIMEOUT)).await.map_err(MeilisearchRunnerError::Client)?;
+        if task.is_failure() {
+            return Err(MeilisearchRunnerError::Task(task.unwrap_failure().error_message));
         }
+        Ok(())
     }
 
-    async fn walkdir(&self, id: i32, path: &str) -> Vec<DataFile> {
+    async fn walkdir<F, E>(&self, id: i32, path: &Path, index: &IndexConfig, progress: &F, extract: &E) -> Vec<DataFile>
+    where F: Fn(IndexProgress), E: Fn(&Path) -> serde_json::Map<String, serde_json::Value> {
 
         let mut id = id;
         let walkdir = WalkDir::new(path);
         let mut data_arr = vec![];
 
-        let total_entries = WalkDir::new(path).into_iter().count();
+        //Excluded directories are walked only for paths include rules bring back
+        let total_entries = WalkDir::new(path).into_iter()
+            .filter_entry(|e| !index.is_pruned(e.path()))
+            .filter_map(|e| e.ok())
+            .filter(|e| !index.is_excluded(e.path()))
+            .count();
         let pb = ProgressBar::new(total_entries as u64);
+        let root = path.display().to_string();
+        let mut last_progress = Instant::now();
 
         info!("walking");
 
-        for entry in walkdir.into_iter().filter_map(|e| e.ok()) {
+        for entry in walkdir.into_iter().filter_entry(|e| !index.is_pruned(e.path())).filter_map(|e| e.ok()).filter(|e| !index.is_excluded(e.path())) {
             let path = entry.clone().into_path();
 
             let name = if let Some(path) = path.file_name() {
@@ -204,26 +325,44 @@ impl MeilisearchRunner {
                 id,
                 file_name: name,
                 file_path: path.display().to_string(),
-                metadata
+                metadata,
+                plugin_metadata: extract(&path),
             };
             id += 1;
             data_arr.push(data_file);
             pb.inc(1);
+            if self.indexing.pause_ms() > 0 && pb.position() % self.indexing.batch_size() == 0 {
+                tokio::time::sleep(Duration::from_millis(self.indexing.pause_ms())).await;
+            }
+            if last_progress.elapsed() >= PROGRESS_INTERVAL || pb.position() == total_entries as u64 {
+                last_progress = Instant::now();
+                progress(IndexProgress {
+                    root: root.clone(),
+                    done: pb.position(),
+                    total: total_entries as u64,
+                });
+            }
         }
         pb.finish();
         data_arr
     }
-
 }
 
 pub enum MeilisearchRunnerError {
     Error(Error),
+    Client(meilisearch_sdk::errors::Error),
+    NotReady,
+    //Search engine accepted task but failed to process it
+    Task(String),
 }
 
 impl Display for MeilisearchRunnerError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
             MeilisearchRunnerError::Error(e) => write!(f, " MeilisearchRunnerError: {}", e),
+            MeilisearchRunnerError::Client(e) => write!(f, " MeilisearchRunnerError: {}", e),
+            MeilisearchRunnerError::NotReady => write!(f, " MeilisearchRunnerError: search engine is not ready"),
+            MeilisearchRunnerError::Task(e) => write!(f, " MeilisearchRunnerError: task failed: {}", e),
         }
     }
 }
@@ -247,6 +386,18 @@ impl MeilisearchHost {
 pub struct MeilisearchMasterKey(String);
 
 impl MeilisearchMasterKey {
+    pub fn new(key: &str) -> Self {
+        MeilisearchMasterKey(key.to_string())
+    }
+
+    pub fn from_secrets(secrets: &Secrets) -> Result<Option<Self>, SecretError> {
+        Ok(secrets.get(MASTER_KEY_SECRET)?.map(MeilisearchMasterKey))
+    }
+
+    pub fn store(&self, secrets: &mut Secrets) -> Result<(), SecretError> {
+        secrets.set(MASTER_KEY_SECRET, &self.0)
+    }
+
     pub async fn gen() -> MeilisearchMasterKey {
         let pg = PasswordGenerator {
             length: 16,
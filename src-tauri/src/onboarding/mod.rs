@@ -0,0 +1,260 @@
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use tauri::{command, AppHandle, Manager, State};
+use tracing::{error, info};
+use crate::config_manager::{AppState, ConfigSaveError, SharedConfig};
+use crate::meilisearch_runner::runner::{MeilisearchHost, MeilisearchMasterKey, MeilisearchRunner, SharedRunner};
+use crate::plugin_manager::SharedPlugins;
+use crate::plugin_manager::files::MetadataExtractors;
+use crate::secrets::SharedSecrets;
+
+//Steps of first run setup, always passed in this order
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OnboardingStep {
+    DetectFirstRun,
+    ChooseIndexRoots,
+    GenerateCredentials,
+    InitialIndex,
+}
+
+impl OnboardingStep {
+    pub const ALL: [OnboardingStep; 4] = [
+        OnboardingStep::DetectFirstRun,
+        OnboardingStep::ChooseIndexRoots,
+        OnboardingStep::GenerateCredentials,
+        OnboardingStep::InitialIndex,
+    ];
+}
+
+//Saved in config after every finished step, so interrupted setup continues from the same place
+#[derive(Serialize, Deserialize, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OnboardingProgress {
+    #[serde(default)]
+    completed: Vec<OnboardingStep>,
+}
+
+impl OnboardingProgress {
+    pub fn is_completed(&self, step: OnboardingStep) -> bool {
+        self.completed.contains(&step)
+    }
+
+    pub fn next_step(&self) -> Option<OnboardingStep> {
+        OnboardingStep::ALL.into_iter().find(|step| !self.is_completed(*step))
+    }
+
+    fn complete(&mut self, step: OnboardingStep) {
+        if !self.is_completed(step) {
+            self.completed.push(step);
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct OnboardingStatus {
+    state: AppState,
+    completed: Vec<OnboardingStep>,
+    next: Option<OnboardingStep>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct OnboardingProgressEvent {
+    step: OnboardingStep,
+    root: String,
+    done: u64,
+    total: u64,
+}
+
+pub struct Onboarding;
+
+impl Onboarding {
+    pub async fn status(config: &SharedConfig) -> OnboardingStatus {
+        let config = config.lock().await;
+        let app_conf = config.app_conf();
+        OnboardingStatus {
+            state: app_conf.state(),
+            completed: app_conf.onboarding.completed.clone(),
+            next: app_conf.onboarding.next_step(),
+        }
+    }
+
+    //Check that step can run now, steps before it must be done
+    async fn ensure_step(config: &SharedConfig, step: OnboardingStep) -> Result<(), OnboardingError> {
+        let config = config.lock().await;
+        if !config.errors().is_empty() {
+            return Err(OnboardingError::InvalidConfig);
+        }
+        if config.app_conf().state() == AppState::Stable {
+            return Err(OnboardingError::AlreadyFinished);
+        }
+        match config.app_conf().onboarding.next_step() {
+            Some(next) if OnboardingStep::ALL.iter().position(|s| *s == step) > OnboardingStep::ALL.iter().position(|s| *s == next) => {
+                Err(OnboardingError::StepNotReady(step, next))
+            }
+            _ => Ok(())
+        }
+    }
+
+    async fn complete_step(config: &SharedConfig, step: OnboardingStep) -> Result<(), OnboardingError> {
+        config.lock().await
+            .update(|app_conf| app_conf.onboarding.complete(step)).await
+            .map_err(OnboardingError::Save)?;
+        info!("Onboarding step {:?} completed", step);
+        Ok(())
+    }
+
+    pub async fn detect_first_run(config: &SharedConfig) -> Result<bool, OnboardingError> {
+        let state = config.lock().await.get_state().await;
+        if state == AppState::Stable {
+            return Ok(false);
+        }
+        Self::ensure_step(config, OnboardingStep::DetectFirstRun).await?;
+        if state != AppState::FirstRun {
+            config.lock().await.set_state(AppState::FirstRun).await.map_err(OnboardingError::Save)?;
+        }
+        Self::complete_step(config, OnboardingStep::DetectFirstRun).await?;
+        Ok(true)
+    }
+
+    pub async fn set_index_roots(config: &SharedConfig, roots: Vec<PathBuf>) -> Result<(), OnboardingError> {
+        Self::ensure_step(config, OnboardingStep::ChooseIndexRoots).await?;
+        if roots.is_empty() {
+            return Err(OnboardingError::NoIndexRoots);
+        }
+        if let Some(root) = roots.iter().find(|root| !root.is_dir()) {
+            return Err(OnboardingError::InvalidIndexRoot(root.clone()));
+        }
+        {
+            let mut config = config.lock().await;
+            config.update(|app_conf| app_conf.index.roots = roots).await.map_err(OnboardingError::Save)?;
+            config.publish_profile();
+        }
+        Self::complete_step(config, OnboardingStep::ChooseIndexRoots).await?;
+        Ok(())
+    }
+
+    //Reuse key generated before interruption, search data is already protected with it
+    pub async fn generate_credentials(config: &SharedConfig, secrets: &SharedSecrets) -> Result<(), OnboardingError> {
+        Self::ensure_step(config, OnboardingStep::GenerateCredentials).await?;
+        {
+            let mut secrets = secrets.lock().await;
+            let existing = MeilisearchMasterKey::from_secrets(&secrets).map_err(|e| OnboardingError::Secrets(e.to_string()))?;
+            if existing.is_none() {
+                let master_key = MeilisearchMasterKey::gen().await;
+                master_key.store(&mut secrets).map_err(|e| OnboardingError::Secrets(e.to_string()))?;
+            }
+        }
+        Self::complete_step(config, OnboardingStep::GenerateCredentials).await?;
+        Ok(())
+    }
+
+    pub async fn run_initial_index(app: AppHandle, config: &SharedConfig, secrets: &SharedSecrets, runner: &SharedRunner, plugins: &SharedPlugins) -> Result<(), OnboardingError> {
+        Self::ensure_step(config, OnboardingStep::InitialIndex).await?;
+        let (search, profile) = {
+            let config = config.lock().await;
+            let profile = config.active_profile().map_err(|e| OnboardingError::Index(e.to_string()))?;
+            (config.app_conf().search.clone(), profile)
+        };
+        let master_key = MeilisearchMasterKey::from_secrets(&*secrets.lock().await)
+            .map_err(|e| OnboardingError::Secrets(e.to_string()))?
+            .ok_or(OnboardingError::StepNotReady(OnboardingStep::InitialIndex, OnboardingStep::GenerateCredentials))?;
+
+        let extractors = MetadataExtractors::new(plugins).await;
+        //Runner is not held while indexing, extractor plugins may search through host
+        let indexer = {
+            let mut runner = runner.lock().await;
+            if runner.is_none() {
+                let mut search_runner = MeilisearchRunner::new(MeilisearchHost::new(&search.host, search.port), master_key).await;
+                search_runner.safe_run().await.map_err(|e| OnboardingError::Index(e.to_string()))?;
+                search_runner.run_client().await;
+                search_runner.set_indexing(profile.indexing.clone());
+                *runner = Some(search_runner);
+            }
+            let search_runner = runner.as_ref().unwrap();
+            search_runner.wait_ready(20).await.map_err(|e| OnboardingError::Index(e.to_string()))?;
+            search_runner.indexer().map_err(|e| OnboardingError::Index(e.to_string()))?
+        };
+        indexer.update_fs_info(&profile.index, |progress| {
+            let event = OnboardingProgressEvent {
+                step: OnboardingStep::InitialIndex,
+                root: progress.root,
+                done: progress.done,
+                total: progress.total,
+            };
+            if let Err(e) = app.emit_all("onboarding-progress", event) {
+                error!("{}", e);
+            }
+        }, |path| extractors.extract(path)).await.map_err(|e| OnboardingError::Index(e.to_string()))?;
+
+        Self::complete_step(config, OnboardingStep::InitialIndex).await?;
+        Ok(())
+    }
+
+    //Config becomes stable only when every step is done
+    pub async fn finish(config: &SharedConfig) -> Result<(), OnboardingError> {
+        let mut config = config.lock().await;
+        if let Some(next) = config.app_conf().onboarding.next_step() {
+            return Err(OnboardingError::Unfinished(next));
+        }
+        config.set_state(AppState::Stable).await.map_err(OnboardingError::Save)
+    }
+}
+
+pub enum OnboardingError {
+    InvalidConfig,
+    AlreadyFinished,
+    StepNotReady(OnboardingStep, OnboardingStep),
+    Unfinished(OnboardingStep),
+    NoIndexRoots,
+    InvalidIndexRoot(PathBuf),
+    Secrets(String),
+    Index(String),
+    Save(ConfigSaveError),
+}
+
+impl Display for OnboardingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OnboardingError::InvalidConfig => write!(f, "Config file has errors, fix them before setup"),
+            OnboardingError::AlreadyFinished => write!(f, "Setup is already finished"),
+            OnboardingError::StepNotReady(step, next) => write!(f, "Step {:?} can't run before {:?}", step, next),
+            OnboardingError::Unfinished(next) => write!(f, "Setup is not finished, next step is {:?}", next),
+            OnboardingError::NoIndexRoots => write!(f, "Choose at least one folder to index"),
+            OnboardingError::InvalidIndexRoot(root) => write!(f, "{} is not a directory", root.display()),
+            OnboardingError::Secrets(e) => write!(f, "Can't save search credentials: {}", e),
+            OnboardingError::Index(e) => write!(f, "Initial index failed: {}", e),
+            OnboardingError::Save(e) => write!(f, "Can't save setup progress: {}", e),
+        }
+    }
+}
+
+#[command]
+pub async fn onboarding_status(config: State<'_, SharedConfig>) -> Result<OnboardingStatus, ()> {
+    Ok(Onboarding::status(&config).await)
+}
+
+#[command]
+pub async fn onboarding_detect_first_run(config: State<'_, SharedConfig>) -> Result<bool, String> {
+    Onboarding::detect_first_run(&config).await.map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn onboarding_set_index_roots(config: State<'_, SharedConfig>, roots: Vec<PathBuf>) -> Result<(), String> {
+    Onboarding::set_index_roots(&config, roots).await.map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn onboarding_generate_credentials(config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>) -> Result<(), String> {
+    Onboarding::generate_credentials(&config, &secrets).await.map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn onboarding_run_initial_index(app: AppHandle, config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>, runner: State<'_, SharedRunner>, plugins: State<'_, SharedPlugins>) -> Result<(), String> {
+    Onboarding::run_initial_index(app, &config, &secrets, &runner, &plugins).await.map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn onboarding_finish(config: State<'_, SharedConfig>) -> Result<(), String> {
+    Onboarding::finish(&config).await.map_err(|e| e.to_string())
+}
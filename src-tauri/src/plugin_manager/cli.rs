@@ -0,0 +1,126 @@
+use semver::Version;
+use tracing::{error, info};
+use crate::config_manager::{default_conf_path, ConfigManager};
+use crate::plugin_manager::registry::{InstalledPlugin, PluginStore};
+
+//First argument of `app plugin <command>`, manages plugins without starting the app.
+//Running app picks the changes up on next start
+pub const CLI_ARG: &str = "plugin";
+
+const USAGE: &str = "Usage: app plugin <command>
+  list                                      installed plugins and registry versions
+  install <archive.tar.gz> --sha256 <hex>   install from archive
+  install <id> [--version <version>]        install from registry index
+  rollback <id>                             switch to previously installed version
+  remove <id>
+  enable <id>
+  disable <id>";
+
+//Runs command and returns exit code when app was started with `plugin`, `None` otherwise
+pub async fn run_cli_from_args() -> Option<i32> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|arg| arg.as_str()) != Some(CLI_ARG) {
+        return None;
+    }
+    attach_console();
+    match run(&args[1..]).await {
+        Ok(()) => Some(0),
+        Err(e) => {
+            error!("{}", e);
+            Some(1)
+        }
+    }
+}
+
+async fn run(args: &[String]) -> Result<(), String> {
+    let mut config = ConfigManager::new(default_conf_path()).await;
+    let store = PluginStore::from_config(&config.app_conf().plugin_registry);
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+    match args.as_slice() {
+        ["list"] => {
+            for plugin in store.list().map_err(|e| e.to_string())? {
+                let installed = plugin.installed.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+                let latest = plugin.latest.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+                let disabled = if config.app_conf().disabled_plugins.contains(&plugin.id) { " (disabled)" } else { "" };
+                info!("{}\tinstalled {}\tlatest {}{}\t{}", plugin.id, installed, latest, disabled, plugin.description);
+            }
+            Ok(())
+        }
+        ["install", target, options @ ..] => {
+            let prepared = if std::path::Path::new(target).is_file() {
+                let sha256 = option(options, "--sha256")?.ok_or_else(|| USAGE.to_string())?;
+                store.prepare_archive(std::path::Path::new(target), sha256)
+            } else {
+                let version = option(options, "--version")?
+                    .map(Version::parse)
+                    .transpose()
+                    .map_err(|e| format!("invalid version: {}", e))?;
+                store.prepare_registry(target, version.as_ref())
+            };
+            let installed = prepared.and_then(|plugin| store.activate(plugin)).map_err(|e| e.to_string())?;
+            print_installed(&installed);
+            Ok(())
+        }
+        ["rollback", id] => {
+            let installed = store.rollback(id).map_err(|e| e.to_string())?;
+            print_installed(&installed);
+            Ok(())
+        }
+        ["remove", id] => {
+            store.remove(id).map_err(|e| e.to_string())?;
+            info!("Removed {}", id);
+            Ok(())
+        }
+        [command @ ("enable" | "disable"), id] => {
+            if store.active_dir(id).is_none() {
+                return Err(format!("Plugin {} is not installed", id));
+            }
+            config.update(|app_conf| {
+                if *command == "enable" {
+                    app_conf.disabled_plugins.remove(*id);
+                } else {
+                    app_conf.disabled_plugins.insert(id.to_string());
+                }
+            }).await.map_err(|e| e.to_string())?;
+            info!("{} {}d", id, command);
+            Ok(())
+        }
+        _ => Err(USAGE.to_string())
+    }
+}
+
+//Release build has no console of its own, output goes to terminal the command was run from
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+    //Fails when app already has a console or wasn't started from one, both are fine
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
+//Value of `--name value` option, other options are refused
+fn option<'a>(options: &[&'a str], name: &str) -> Result<Option<&'a str>, String> {
+    match options {
+        [] => Ok(None),
+        [option, value] if *option == name => Ok(Some(value)),
+        _ => Err(USAGE.to_string())
+    }
+}
+
+fn print_installed(installed: &InstalledPlugin) {
+    match &installed.previous {
+        Some(previous) => info!("{} {} is active, previous version {}", installed.id, installed.version, previous),
+        None => info!("{} {} is active", installed.id, installed.version),
+    }
+    if let Some(key) = &installed.signed_by {
+        info!("Signed by {}", key);
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::info;
+use starship_plugin_api::loader::discover;
+use crate::config_manager::SharedConfig;
+use crate::plugin_manager::{resolve_configs, with_plugins, SharedPlugins};
+use crate::secrets::SharedSecrets;
+
+//Set to `1` to reload plugins when their libraries are rebuilt
+pub const PLUGIN_DEV_ENV: &str = "SPACETRAVELER_PLUGIN_DEV";
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn is_dev_mode() -> bool {
+    matches!(std::env::var(PLUGIN_DEV_ENV).as_deref(), Ok("1") | Ok("true"))
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct LibraryState {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl LibraryState {
+    fn read(path: &Path) -> Option<Self> {
+        let metadata = std::fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+//Polls plugin libraries and reloads plugin once its library changed and stayed the same
+//for one more poll, so half written build is never loaded. New plugin directories are loaded too
+pub fn watch_libraries(plugins: SharedPlugins, config: SharedConfig, secrets: SharedSecrets) {
+    info!("Plugin dev mode, libraries are reloaded on change");
+    tauri::async_runtime::spawn(async move {
+        let mut known: BTreeMap<PathBuf, LibraryState> = BTreeMap::new();
+        let mut changed: BTreeMap<PathBuf, PathBuf> = BTreeMap::new();
+        let mut first_poll = true;
+        let (plugins_dir, host_version) = plugins.lock().await.discovery();
+        loop {
+            //Plugins are locked only to reload, scanning directories doesn't hold them up
+            let libraries = discover(&plugins_dir, &host_version).0.into_iter().map(|plugin| (plugin.dir, plugin.library));
+            for (dir, library) in libraries {
+                let state = match LibraryState::read(&library) {
+                    Some(state) => state,
+                    None => continue,
+                };
+                match known.insert(library.clone(), state) {
+                    Some(previous) if previous == state => {
+                        if changed.remove(&library).is_some() {
+                            let configs = resolve_configs(&config, &secrets).await;
+                            info!("Plugin library {} changed, reloading", library.display());
+                            let dir = dir.clone();
+                            let _ = with_plugins(&plugins, move |plugins| plugins.reload(&dir, &configs)).await;
+                        }
+                    }
+                    None if first_poll => {}
+                    _ => {
+                        changed.insert(library, dir);
+                    }
+                }
+            }
+            first_poll = false;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+}
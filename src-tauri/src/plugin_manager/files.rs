@@ -0,0 +1,142 @@
+use std::cmp::Reverse;
+use std::path::{Path, PathBuf};
+use tauri::{command, State};
+use tracing::warn;
+use starship_plugin_api::api::PluginError;
+use starship_plugin_api::files::{FileHandlerSchema, FileRequest, Preview};
+use crate::file_ops::mime_type;
+use crate::plugin_manager::{with_plugins, PluginManagerError, SharedPlugins};
+
+//Best fitting handler of every plugin handling the file, best fits first
+fn handlers_for(handlers: &[(String, FileHandlerSchema)], path: &Path, mime: &str) -> Vec<(String, String)> {
+    let mut fitting: Vec<(u8, &str, &str)> = vec![];
+    for (id, handler) in handlers {
+        let fit = handler.matches(path, mime);
+        if fit == 0 {
+            continue;
+        }
+        match fitting.iter_mut().find(|(_, other, _)| *other == id.as_str()) {
+            Some(best) if best.0 >= fit => {}
+            Some(best) => *best = (fit, id, &handler.action),
+            None => fitting.push((fit, id, &handler.action)),
+        }
+    }
+    //Stable sort keeps earlier loaded plugins first among equal fits
+    fitting.sort_by_key(|(fit, _, _)| Reverse(*fit));
+    fitting.into_iter().map(|(_, id, action)| (id.to_string(), action.to_string())).collect()
+}
+
+//Webview would run scripts of plugin HTML with access to app commands,
+//so only markup and styles are kept and images have to be images
+fn sanitize(preview: Preview) -> Result<Preview, String> {
+    match preview {
+        Preview::Html { html } => Ok(Preview::Html { html: ammonia::clean(&html) }),
+        Preview::Image { mime, .. } if !mime.starts_with("image/") => Err(format!("`{}` is not an image type", mime)),
+        image => Ok(image),
+    }
+}
+
+//Preview from the best fitting previewer, next ones are tried when it fails.
+//`None` when no plugin previews this type
+pub async fn preview(plugins: &SharedPlugins, path: &Path) -> Result<Option<Preview>, PluginManagerError> {
+    let request = FileRequest {
+        path: path.to_path_buf(),
+        mime: mime_type(path),
+    };
+    with_plugins(plugins, move |plugins| {
+        let mut result = Ok(None);
+        for (id, action) in handlers_for(&plugins.previewers(), &request.path, &request.mime) {
+            let preview = plugins.run_file_handler(&id, &action, &request).and_then(|preview| {
+                serde_json::from_value(preview).map_err(|e| e.to_string()).and_then(sanitize).map_err(|e| PluginManagerError::Hook {
+                    id: id.clone(),
+                    hook: "on_action",
+                    error: PluginError::new(format!("invalid preview: {}", e)),
+                })
+            });
+            match preview {
+                Ok(preview) => return Ok(Some(preview)),
+                Err(e) => {
+                    warn!("{}", e);
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }).await
+}
+
+//Extractors of enabled plugins as they were when indexing started,
+//plugins are locked only for files some extractor handles
+pub struct MetadataExtractors {
+    plugins: SharedPlugins,
+    extractors: Vec<(String, FileHandlerSchema)>,
+}
+
+impl MetadataExtractors {
+    pub async fn new(plugins: &SharedPlugins) -> Self {
+        Self {
+            extractors: plugins.lock().await.metadata_extractors(),
+            plugins: plugins.clone(),
+        }
+    }
+
+    //Metadata of file keyed by plugin id, failed extractors are left out.
+    //Blocks, called from async code walking the file tree
+    pub fn extract(&self, path: &Path) -> serde_json::Map<String, serde_json::Value> {
+        let mut metadata = serde_json::Map::new();
+        if self.extractors.is_empty() || !path.is_file() {
+            return metadata;
+        }
+        let request = FileRequest {
+            path: path.to_path_buf(),
+            mime: mime_type(path),
+        };
+        let extractors = handlers_for(&self.extractors, path, &request.mime);
+        if extractors.is_empty() {
+            return metadata;
+        }
+        tokio::task::block_in_place(|| {
+            let mut plugins = self.plugins.blocking_lock();
+            for (id, action) in extractors {
+                match plugins.run_file_handler(&id, &action, &request) {
+                    Ok(serde_json::Value::Null) => {}
+                    Ok(value) => {
+                        metadata.insert(id, value);
+                    }
+                    Err(e) => warn!("Metadata of {} is not extracted: {}", path.display(), e)
+                }
+            }
+        });
+        metadata
+    }
+}
+
+#[command]
+pub async fn preview_file(plugins: State<'_, SharedPlugins>, path: PathBuf) -> Result<Option<Preview>, PluginManagerError> {
+    preview(&plugins, &path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_are_removed_from_html() {
+        let preview = sanitize(Preview::Html { html: "<p onclick=\"invoke()\">note</p><script>window.__TAURI__.invoke('remove_plugin')</script><img src=x onerror=alert(1)>".to_string() }).unwrap();
+        match preview {
+            Preview::Html { html } => {
+                assert!(html.contains("<p>note</p>"));
+                assert!(!html.contains("script"));
+                assert!(!html.contains("onclick"));
+                assert!(!html.contains("onerror"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn image_must_have_image_type() {
+        assert!(sanitize(Preview::image("image/png", &[1, 2])).is_ok());
+        assert!(sanitize(Preview::image("text/html", b"<script></script>")).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use std::future::Future;
+use std::path::Path;
+use serde::Serialize;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc::UnboundedSender;
+use starship_plugin_api::context::{FileEntry, Notification, SearchHit};
+use starship_plugin_api::host::HostServices;
+use crate::file_ops::SharedFileOps;
+use crate::meilisearch_runner::runner::SharedRunner;
+
+//Notification posted by plugin, shown by frontend
+#[derive(Serialize, Clone, Debug)]
+pub struct PluginNotification {
+    pub plugin: String,
+    pub notification: Notification,
+}
+
+//Host services backed by the same search runner and file operations the UI uses
+pub struct AppHost {
+    search: SharedRunner,
+    file_ops: SharedFileOps,
+    notifications: UnboundedSender<PluginNotification>,
+    runtime: Handle,
+}
+
+impl AppHost {
+    //Must be created inside async runtime
+    pub fn new(search: SharedRunner, file_ops: SharedFileOps, notifications: UnboundedSender<PluginNotification>) -> Self {
+        Self {
+            search,
+            file_ops,
+            notifications,
+            runtime: Handle::current(),
+        }
+    }
+
+    //Plugins call host both from hooks running on runtime threads and from their own threads
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        match Handle::try_current() {
+            Ok(_) => tokio::task::block_in_place(|| self.runtime.block_on(future)),
+            Err(_) => self.runtime.block_on(future),
+        }
+    }
+}
+
+impl HostServices for AppHost {
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, String> {
+        self.block_on(async {
+            match self.search.lock().await.as_ref() {
+                Some(runner) => runner.search(query, limit).await.map_err(|e| e.to_string()),
+                None => Err("search engine is not running".to_string())
+            }
+        })
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<FileEntry>, String> {
+        self.file_ops.list_dir(path).map_err(|e| e.to_string())
+    }
+
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>, String> {
+        self.file_ops.read(path).map_err(|e| e.to_string())
+    }
+
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), String> {
+        self.file_ops.write(path, data).map_err(|e| e.to_string())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<(), String> {
+        self.file_ops.copy(from, to).map_err(|e| e.to_string())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), String> {
+        self.file_ops.rename(from, to).map_err(|e| e.to_string())
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), String> {
+        self.file_ops.delete(path).map_err(|e| e.to_string())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), String> {
+        self.file_ops.create_dir(path).map_err(|e| e.to_string())
+    }
+
+    fn notify(&self, plugin_id: &str, notification: Notification) -> Result<(), String> {
+        self.notifications.send(PluginNotification {
+            plugin: plugin_id.to_string(),
+            notification,
+        }).map_err(|e| e.to_string())
+    }
+}
@@ -0,0 +1,863 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use semver::Version;
+use serde::Serialize;
+use tauri::{command, State};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn, Span};
+use starship_plugin_api::api::PluginError;
+use starship_plugin_api::context::{HostContext, HostEvent};
+use starship_plugin_api::files::{FileHandlerSchema, FileRequest};
+use starship_plugin_api::host::{HostDispatcher, HostServices};
+use starship_plugin_api::isolation::{Isolation, IsolationPolicy};
+use starship_plugin_api::loader::{DiscoveredPlugin, LoadedPlugin, PluginKind, PluginLoadError, PluginLoader};
+use starship_plugin_api::manifest::{resolve_order, PluginManifest};
+use starship_plugin_api::plugin_config::PluginConfig;
+use starship_plugin_api::search::{SearchItem, SearchProviderSchema, SearchQuery};
+use starship_plugin_api::settings::{self, SettingError, SettingSchema};
+use starship_plugin_api::ui::{PanelContent, UiContributions};
+use crate::config_manager::{ConfigSaveError, PluginRuntimeConfig, SharedConfig};
+use crate::file_ops::EventBus;
+use crate::search::SourceResults;
+use crate::secrets::SharedSecrets;
+
+pub mod cli;
+pub mod dev;
+pub mod files;
+pub mod host;
+pub mod registry;
+
+pub type SharedPlugins = Arc<Mutex<PluginManager>>;
+
+//`plugins` next to executable, one directory per plugin
+pub fn plugins_dir() -> PathBuf {
+    std::env::current_exe().unwrap().parent().unwrap().join("plugins")
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum PluginStatus {
+    Enabled,
+    Disabled,
+    Failed,
+}
+
+//Plugin as shown in UI, failed plugins keep manifest when it was readable
+#[derive(Serialize, Clone)]
+pub struct PluginInfo {
+    id: Option<String>,
+    manifest: Option<PluginManifest>,
+    path: PathBuf,
+    kind: Option<PluginKind>,
+    isolation: Option<Isolation>,
+    status: PluginStatus,
+    error: Option<String>,
+}
+
+//UI contributed by plugin, tagged with plugin id so frontend knows whom to call
+#[derive(Serialize, Clone)]
+pub struct PluginUi {
+    plugin: String,
+    #[serde(flatten)]
+    ui: UiContributions,
+}
+
+//Settings form of plugin: what it accepts, current values with defaults and problems of saved values
+#[derive(Serialize, Clone)]
+pub struct PluginSettings {
+    schema: Vec<SettingSchema>,
+    values: toml::Table,
+    errors: Vec<SettingError>,
+}
+
+//Loaded plugin stays in memory while disabled, only hooks are called on switch
+struct ManagedPlugin {
+    //Own lock so search providers run without holding the manager, emptied on unload
+    plugin: Arc<std::sync::Mutex<Option<LoadedPlugin>>>,
+    manifest: PluginManifest,
+    host: Arc<HostDispatcher>,
+    enabled: bool,
+    //Last hook error, cleared by next successful enable
+    error: Option<String>,
+    //Hook failures in a row, plugin is disabled when it reaches `max_failures`
+    failures: u32,
+    //Config as last delivered to plugin, with defaults filled
+    config: Option<PluginConfig>,
+}
+
+//Plugin that panicked while locked is still usable, its hooks report their own errors
+fn lock_plugin(plugin: &std::sync::Mutex<Option<LoadedPlugin>>) -> std::sync::MutexGuard<'_, Option<LoadedPlugin>> {
+    plugin.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+struct PluginGuard<'a>(std::sync::MutexGuard<'a, Option<LoadedPlugin>>);
+
+impl Deref for PluginGuard<'_> {
+    type Target = LoadedPlugin;
+
+    fn deref(&self) -> &LoadedPlugin {
+        self.0.as_ref().expect("managed plugin is loaded")
+    }
+}
+
+impl DerefMut for PluginGuard<'_> {
+    fn deref_mut(&mut self) -> &mut LoadedPlugin {
+        self.0.as_mut().expect("managed plugin is loaded")
+    }
+}
+
+impl ManagedPlugin {
+    //Only `unload_plugin` empties the slot and it consumes managed plugin
+    fn plugin(&self) -> PluginGuard<'_> {
+        PluginGuard(lock_plugin(&self.plugin))
+    }
+}
+
+//Search providers of one plugin, called after the manager lock is released so a slow
+//provider doesn't block other plugin calls. Plugin unloaded in the meantime is skipped
+pub struct SearchHandle {
+    id: String,
+    plugin: Weak<std::sync::Mutex<Option<LoadedPlugin>>>,
+    span: Span,
+    providers: Vec<SearchProviderSchema>,
+}
+
+impl SearchHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    //Providers run in order, every one sends its items as soon as it answered
+    pub fn search(&self, query: &SearchQuery, results: &UnboundedSender<SourceResults>) -> Result<(), PluginManagerError> {
+        let plugin = match self.plugin.upgrade() {
+            Some(plugin) => plugin,
+            None => return Ok(()),
+        };
+        let mut plugin = lock_plugin(&plugin);
+        let plugin = match plugin.as_mut() {
+            Some(plugin) => plugin,
+            None => return Ok(()),
+        };
+        let _span = self.span.enter();
+        let args = serde_json::to_value(query).unwrap_or_default();
+        let mut result = Ok(());
+        for provider in &self.providers {
+            let items = plugin.on_action(&provider.action, &args)
+                .and_then(|items| serde_json::from_value::<Vec<SearchItem>>(items)
+                    .map_err(|e| PluginError::new(format!("invalid search results of {}: {}", provider.id, e))));
+            match items {
+                Ok(items) => {
+                    let _ = results.send(SourceResults {
+                        source: format!("{}/{}", self.id, provider.id),
+                        title: provider.title.clone(),
+                        items,
+                    });
+                }
+                Err(error) => {
+                    warn!("Search provider {} failed: {}", provider.id, error);
+                    result = Err(PluginManagerError::Hook { id: self.id.clone(), hook: "on_action", error });
+                }
+            }
+        }
+        result
+    }
+}
+
+//Plugin subscribed to an event, handler runs without manager lock so it can call back into host
+pub struct EventHandle {
+    id: String,
+    plugin: Weak<std::sync::Mutex<Option<LoadedPlugin>>>,
+    span: Span,
+}
+
+impl EventHandle {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn dispatch(&self, event: &HostEvent) -> Result<(), PluginManagerError> {
+        let plugin = match self.plugin.upgrade() {
+            Some(plugin) => plugin,
+            None => return Ok(()),
+        };
+        let mut plugin = lock_plugin(&plugin);
+        let plugin = match plugin.as_mut() {
+            Some(plugin) => plugin,
+            None => return Ok(()),
+        };
+        let _span = self.span.enter();
+        plugin.on_event(event).map_err(|error| {
+            warn!("on_event failed: {}", error);
+            PluginManagerError::Hook { id: self.id.clone(), hook: "on_event", error }
+        })
+    }
+}
+
+pub struct PluginManager {
+    loader: PluginLoader,
+    services: Arc<dyn HostServices>,
+    events: EventBus,
+    plugins: Vec<ManagedPlugin>,
+    errors: Vec<PluginLoadError>,
+    max_failures: u32,
+    //Ids switched off by user, loaded without enabling
+    disabled: BTreeSet<String>,
+}
+
+impl PluginManager {
+    pub fn new(dir: PathBuf, services: Arc<dyn HostServices>, events: EventBus) -> Self {
+        let host_version = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+        Self {
+            loader: PluginLoader::new(dir, host_version),
+            services,
+            events,
+            plugins: vec![],
+            errors: vec![],
+            max_failures: 0,
+            disabled: BTreeSet::new(),
+        }
+    }
+
+    pub fn with_disabled(mut self, disabled: BTreeSet<String>) -> Self {
+        self.disabled = disabled;
+        self
+    }
+
+    //Timeouts, failure limit and helper process for plugins listed in `runtime.isolate`.
+    //Helper is the app executable itself, see `run_helper_from_args`
+    pub fn with_runtime(mut self, runtime: &PluginRuntimeConfig, helper: Option<PathBuf>) -> Self {
+        self.loader = self.loader.with_isolation(IsolationPolicy {
+            call_timeout: Duration::from_millis(runtime.call_timeout_ms),
+            helper,
+            isolated: runtime.isolate.clone(),
+        });
+        self.max_failures = runtime.max_failures;
+        self
+    }
+
+    //Load libraries from shadow copies so they can be rebuilt and reloaded while app runs
+    pub fn with_hot_reload(mut self) -> Self {
+        self.loader = self.loader.with_shadow_dir(std::env::temp_dir().join("spacetraveler-plugins"));
+        self
+    }
+
+    //Load, initialize, configure and enable every plugin. Plugin failing `on_load` is dropped,
+    //plugin with invalid config or failing `on_enable` stays loaded but disabled
+    pub fn load_all(&mut self, configs: &BTreeMap<String, PluginConfig>) {
+        let dir = self.loader.dir().to_path_buf();
+        if !dir.exists() {
+            if let Err(e) = std::fs::create_dir(&dir) {
+                error!("{}", e);
+            }
+        }
+        let (plugins, mut errors) = self.loader.load_all();
+        for plugin in plugins {
+            let config = configs.get(&plugin.manifest().id).cloned().unwrap_or_default();
+            let enable = !self.disabled.contains(&plugin.manifest().id);
+            match self.init_plugin(plugin, config, enable) {
+                Ok(managed) => self.plugins.push(managed),
+                Err(e) => errors.push(e)
+            }
+        }
+        for e in &errors {
+            error!(name: "Plugin load error", "{}", e);
+        }
+        self.errors = errors;
+    }
+
+    //Only `on_load` failure drops the plugin, failed config or enable leave it loaded but disabled
+    fn init_plugin(&self, mut plugin: LoadedPlugin, config: PluginConfig, enable: bool) -> Result<ManagedPlugin, PluginLoadError> {
+        let host = Arc::new(HostDispatcher::new(plugin.manifest(), self.services.clone()));
+        let span = host.span().clone();
+        let _span = span.enter();
+        let context = HostContext::new(&plugin.manifest().id, &self.loader.host_version().to_string(), plugin.dir(), host.clone());
+        plugin.on_load(context).map_err(|e| PluginLoadError::hook(&plugin, e))?;
+        let manifest = plugin.manifest();
+        info!("Plugin {} {} loaded from {}", manifest.id, manifest.version, plugin.path().display());
+        let mut managed = ManagedPlugin {
+            manifest: plugin.manifest().clone(),
+            plugin: Arc::new(std::sync::Mutex::new(Some(plugin))),
+            host,
+            enabled: false,
+            error: None,
+            failures: 0,
+            config: None,
+        };
+        let mut result = Self::configure_plugin(&mut managed, config);
+        if enable {
+            result = result.and_then(|_| Self::enable_plugin(&mut managed));
+        }
+        if let Err(e) = result {
+            error!("{}", e);
+        }
+        Ok(managed)
+    }
+
+    pub fn errors(&self) -> Vec<PluginLoadError> {
+        self.errors.clone()
+    }
+
+    pub fn list(&self) -> Vec<PluginInfo> {
+        let loaded = self.plugins.iter().map(|managed| PluginInfo {
+            id: Some(managed.manifest.id.clone()),
+            manifest: Some(managed.manifest.clone()),
+            path: managed.plugin().path().to_path_buf(),
+            kind: Some(managed.plugin().kind()),
+            isolation: Some(managed.plugin().isolation()),
+            status: if managed.enabled { PluginStatus::Enabled } else { PluginStatus::Disabled },
+            error: managed.error.clone(),
+        });
+        let failed = self.errors.iter().map(|e| PluginInfo {
+            id: e.plugin_id().map(|id| id.to_string()),
+            manifest: e.manifest.as_deref().cloned(),
+            path: e.path.clone(),
+            kind: PluginKind::from_path(&e.path),
+            isolation: None,
+            status: PluginStatus::Failed,
+            error: Some(e.error.clone()),
+        });
+        loaded.chain(failed).collect()
+    }
+
+    fn find(&mut self, id: &str) -> Result<&mut ManagedPlugin, PluginManagerError> {
+        self.plugins.iter_mut()
+            .find(|managed| managed.manifest.id == id)
+            .ok_or_else(|| PluginManagerError::NotFound(id.to_string()))
+    }
+
+    fn hook_error(managed: &mut ManagedPlugin, hook: &'static str, error: PluginError) -> PluginManagerError {
+        managed.error = Some(error.message.clone());
+        PluginManagerError::Hook {
+            id: managed.manifest.id.clone(),
+            hook,
+            error,
+        }
+    }
+
+    fn publish(&self, event: HostEvent) {
+        let _ = self.events.send(event);
+    }
+
+    //Successful hook resets the count, hook that keeps failing gets plugin disabled
+    //so one broken plugin doesn't flood every event and action with errors. Plugin stopped
+    //by its isolation layer is disabled on its first failure. Disabling runs `on_disable`, so
+    //callers outside the manager go through `with_plugins`
+    pub fn track_failures<T>(&mut self, id: &str, result: &Result<T, PluginManagerError>) {
+        let error = match result {
+            Ok(_) => None,
+            Err(PluginManagerError::Hook { error, .. }) => Some(error.message.clone()),
+            Err(_) => return,
+        };
+        let max_failures = self.max_failures;
+        let managed = match self.find(id) {
+            Ok(managed) => managed,
+            Err(_) => return,
+        };
+        let error = match error {
+            Some(error) => error,
+            None => {
+                managed.failures = 0;
+                return;
+            }
+        };
+        managed.failures += 1;
+        //Isolation never restarts a plugin it stopped, it is disabled whatever the limit is
+        let stopped = managed.plugin().stopped();
+        let below_limit = max_failures == 0 || managed.failures < max_failures;
+        if !managed.enabled || (stopped.is_none() && below_limit) {
+            return;
+        }
+        managed.enabled = false;
+        match stopped {
+            Some(reason) => {
+                managed.error = Some(format!("stopped: {}", reason));
+                error!("Plugin {} stopped and disabled: {}", id, reason);
+            }
+            None => {
+                let span = managed.host.span().clone();
+                if let Err(e) = span.in_scope(|| managed.plugin().on_disable()) {
+                    warn!(parent: &span, "on_disable failed: {}", e);
+                }
+                managed.error = Some(format!("disabled after {} failures: {}", managed.failures, error));
+                error!("Plugin {} disabled after {} failures in a row: {}", id, managed.failures, error);
+            }
+        }
+        self.publish(HostEvent::PluginDisabled { id: id.to_string() });
+    }
+
+    fn enable_plugin(managed: &mut ManagedPlugin) -> Result<(), PluginManagerError> {
+        if managed.enabled {
+            return Ok(());
+        }
+        let span = managed.host.span().clone();
+        let _span = span.enter();
+        let result = managed.plugin().on_enable();
+        result.map_err(|e| Self::hook_error(managed, "on_enable", e))?;
+        managed.enabled = true;
+        managed.error = None;
+        managed.failures = 0;
+        Ok(())
+    }
+
+    pub fn enable(&mut self, id: &str) -> Result<(), PluginManagerError> {
+        Self::enable_plugin(self.find(id)?)?;
+        self.disabled.remove(id);
+        info!("Plugin {} enabled", id);
+        self.publish(HostEvent::PluginEnabled { id: id.to_string() });
+        Ok(())
+    }
+
+    //Plugin counts as disabled even if `on_disable` fails, host stops using it anyway
+    pub fn disable(&mut self, id: &str) -> Result<(), PluginManagerError> {
+        let managed = self.find(id)?;
+        if !managed.enabled {
+            self.disabled.insert(id.to_string());
+            return Ok(());
+        }
+        managed.enabled = false;
+        let span = managed.host.span().clone();
+        let result = span.in_scope(|| managed.plugin().on_disable()).map_err(|e| Self::hook_error(managed, "on_disable", e));
+        self.disabled.insert(id.to_string());
+        info!("Plugin {} disabled", id);
+        self.publish(HostEvent::PluginDisabled { id: id.to_string() });
+        result
+    }
+
+    //Check config against settings schema of plugin and fill defaults
+    fn prepare_config(manifest: &PluginManifest, mut config: PluginConfig) -> Result<PluginConfig, PluginManagerError> {
+        let errors = settings::validate(&manifest.settings, &config.settings);
+        if !errors.is_empty() {
+            return Err(PluginManagerError::InvalidSettings {
+                id: manifest.id.clone(),
+                errors,
+            });
+        }
+        config.settings = settings::with_defaults(&manifest.settings, &config.settings);
+        Ok(config)
+    }
+
+    //Delivered only when config differs from what plugin already has
+    fn configure_plugin(managed: &mut ManagedPlugin, config: PluginConfig) -> Result<(), PluginManagerError> {
+        let config = match Self::prepare_config(&managed.manifest, config) {
+            Ok(config) => config,
+            Err(e) => {
+                managed.error = Some(e.to_string());
+                return Err(e);
+            }
+        };
+        if managed.config.as_ref() == Some(&config) {
+            return Ok(());
+        }
+        let span = managed.host.span().clone();
+        span.in_scope(|| managed.plugin().on_config_changed(&config)).map_err(|e| Self::hook_error(managed, "on_config_changed", e))?;
+        managed.config = Some(config);
+        Ok(())
+    }
+
+    pub fn configure(&mut self, id: &str, config: PluginConfig) -> Result<(), PluginManagerError> {
+        let result = Self::configure_plugin(self.find(id)?, config);
+        self.track_failures(id, &result);
+        result
+    }
+
+    //Deliver configs after they changed outside of plugin settings, e.g. on import or secrets unlock
+    pub fn configure_all(&mut self, configs: &BTreeMap<String, PluginConfig>) {
+        let ids: Vec<String> = self.plugins.iter().map(|managed| managed.manifest.id.clone()).collect();
+        for id in ids {
+            let config = configs.get(&id).cloned().unwrap_or_default();
+            if let Err(e) = self.configure(&id, config) {
+                error!("{}", e);
+            }
+        }
+    }
+
+    pub fn validate_settings(&mut self, id: &str, values: &toml::Table) -> Result<(), PluginManagerError> {
+        let manifest = &self.find(id)?.manifest;
+        let errors = settings::validate(&manifest.settings, values);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(PluginManagerError::InvalidSettings { id: id.to_string(), errors })
+        }
+    }
+
+    pub fn settings(&mut self, id: &str, config: Option<&PluginConfig>) -> Result<PluginSettings, PluginManagerError> {
+        let manifest = &self.find(id)?.manifest;
+        let values = config.map(|config| config.settings.clone()).unwrap_or_default();
+        Ok(PluginSettings {
+            schema: manifest.settings.clone(),
+            errors: settings::validate(&manifest.settings, &values),
+            values: settings::with_defaults(&manifest.settings, &values),
+        })
+    }
+
+    //UI of enabled plugins, frontend builds menus, toolbar and sidebar from it
+    pub fn ui(&self) -> Vec<PluginUi> {
+        self.plugins.iter()
+            .filter(|managed| managed.enabled && !managed.manifest.ui.is_empty())
+            .map(|managed| PluginUi {
+                plugin: managed.manifest.id.clone(),
+                ui: managed.manifest.ui.clone(),
+            })
+            .collect()
+    }
+
+    //Failed action is reported to caller only, plugin stays enabled
+    pub fn run_action(&mut self, id: &str, action: &str, args: &serde_json::Value) -> Result<serde_json::Value, PluginManagerError> {
+        if self.find(id)?.manifest.ui.action(action).is_none() {
+            return Err(PluginManagerError::UnknownAction { id: id.to_string(), action: action.to_string() });
+        }
+        self.call_action(id, action, args)
+    }
+
+    fn call_action(&mut self, id: &str, action: &str, args: &serde_json::Value) -> Result<serde_json::Value, PluginManagerError> {
+        let managed = self.find(id)?;
+        if !managed.enabled {
+            return Err(PluginManagerError::Disabled(id.to_string()));
+        }
+        let span = managed.host.span().clone();
+        let result = span.in_scope(|| managed.plugin().on_action(action, args)).map_err(|error| PluginManagerError::Hook {
+            id: id.to_string(),
+            hook: "on_action",
+            error,
+        });
+        self.track_failures(id, &result);
+        result
+    }
+
+    //Previewers of enabled plugins with id of plugin
+    pub fn previewers(&self) -> Vec<(String, FileHandlerSchema)> {
+        self.file_handlers(|manifest| &manifest.previewers)
+    }
+
+    //Metadata extractors of enabled plugins with id of plugin
+    pub fn metadata_extractors(&self) -> Vec<(String, FileHandlerSchema)> {
+        self.file_handlers(|manifest| &manifest.metadata_extractors)
+    }
+
+    fn file_handlers(&self, handlers: fn(&PluginManifest) -> &Vec<FileHandlerSchema>) -> Vec<(String, FileHandlerSchema)> {
+        self.plugins.iter()
+            .filter(|managed| managed.enabled)
+            .flat_map(|managed| {
+                let id = &managed.manifest.id;
+                handlers(&managed.manifest).iter().map(move |handler| (id.clone(), handler.clone()))
+            })
+            .collect()
+    }
+
+    //Only actions declared as previewer or extractor can be called this way
+    pub fn run_file_handler(&mut self, id: &str, action: &str, request: &FileRequest) -> Result<serde_json::Value, PluginManagerError> {
+        let manifest = &self.find(id)?.manifest;
+        let declared = manifest.previewers.iter().chain(&manifest.metadata_extractors).any(|handler| handler.action == action);
+        if !declared {
+            return Err(PluginManagerError::UnknownAction { id: id.to_string(), action: action.to_string() });
+        }
+        let args = serde_json::to_value(request).unwrap_or_default();
+        self.call_action(id, action, &args)
+    }
+
+    pub fn render_panel(&mut self, id: &str, panel: &str) -> Result<PanelContent, PluginManagerError> {
+        let action = self.find(id)?.manifest.ui.panel(panel)
+            .map(|panel| panel.action.clone())
+            .ok_or_else(|| PluginManagerError::UnknownPanel { id: id.to_string(), panel: panel.to_string() })?;
+        let content = self.run_action(id, &action, &serde_json::Value::Null)?;
+        serde_json::from_value(content).map_err(|e| PluginManagerError::Hook {
+            id: id.to_string(),
+            hook: "on_action",
+            error: PluginError::new(format!("invalid panel content: {}", e)),
+        })
+    }
+
+    //Search providers of enabled plugins, caller runs them and reports outcome to `track_failures`
+    pub fn search_handles(&self) -> Vec<SearchHandle> {
+        self.plugins.iter()
+            .filter(|managed| managed.enabled && !managed.manifest.search_providers.is_empty())
+            .map(|managed| SearchHandle {
+                id: managed.manifest.id.clone(),
+                plugin: Arc::downgrade(&managed.plugin),
+                span: managed.host.span().clone(),
+                providers: managed.manifest.search_providers.clone(),
+            })
+            .collect()
+    }
+
+    //Enabled plugins subscribed to event kind, caller delivers it and reports outcome to `track_failures`
+    pub fn event_handles(&self, event: &HostEvent) -> Vec<EventHandle> {
+        self.plugins.iter()
+            .filter(|managed| managed.enabled && managed.host.is_subscribed(event))
+            .map(|managed| EventHandle {
+                id: managed.manifest.id.clone(),
+                plugin: Arc::downgrade(&managed.plugin),
+                span: managed.host.span().clone(),
+            })
+            .collect()
+    }
+
+    //Plugin is dropped before this returns, even while a search handle still refers to it,
+    //so its library and isolation thread are gone when caller replaces the files
+    fn unload_plugin(managed: ManagedPlugin) {
+        let id = managed.manifest.id.clone();
+        let span = managed.host.span().clone();
+        let _span = span.enter();
+        let mut plugin = match lock_plugin(&managed.plugin).take() {
+            Some(plugin) => plugin,
+            None => return,
+        };
+        if managed.enabled {
+            if let Err(e) = plugin.on_disable() {
+                error!("Plugin {} on_disable failed: {}", id, e);
+            }
+        }
+        if let Err(e) = plugin.on_unload() {
+            error!("Plugin {} on_unload failed: {}", id, e);
+        }
+        drop(plugin);
+        info!("Plugin {} unloaded", id);
+    }
+
+    //Dependents are unloaded before their dependencies
+    pub fn unload_all(&mut self) {
+        while let Some(managed) = self.plugins.pop() {
+            Self::unload_plugin(managed);
+        }
+    }
+
+    //Unload single plugin before its files are replaced or removed
+    pub fn unload(&mut self, id: &str) -> Result<(), PluginManagerError> {
+        let i = self.plugins.iter()
+            .position(|managed| managed.manifest.id == id)
+            .ok_or_else(|| PluginManagerError::NotFound(id.to_string()))?;
+        let managed = self.plugins.remove(i);
+        let enabled = managed.enabled;
+        Self::unload_plugin(managed);
+        if enabled {
+            self.publish(HostEvent::PluginDisabled { id: id.to_string() });
+        }
+        Ok(())
+    }
+
+    //Plugins directory and host version, enough to discover plugins without holding the manager
+    pub fn discovery(&self) -> (PathBuf, Version) {
+        (self.loader.dir().to_path_buf(), self.loader.host_version().clone())
+    }
+
+    //Same dependency checks `load_all` does, against plugins that stay loaded.
+    //Order covers loaded plugins followed by the reloaded one
+    fn resolve_reloaded(&self, plugin: &DiscoveredPlugin) -> Result<Vec<usize>, PluginLoadError> {
+        let mut manifests: Vec<PluginManifest> = self.plugins.iter().map(|managed| managed.manifest.clone()).collect();
+        manifests.push(plugin.manifest.clone());
+        let (order, errors) = resolve_order(&manifests);
+        match errors.into_iter().find(|(i, _)| *i == manifests.len() - 1) {
+            Some((_, e)) => Err(PluginLoadError::manifest(plugin, e)),
+            None => Ok(order)
+        }
+    }
+
+    //Plugins left out of `order` no longer fit the reloaded plugin, they go last so they're unloaded first
+    fn sort_plugins(&mut self, order: &[usize]) {
+        let mut plugins: Vec<Option<ManagedPlugin>> = std::mem::take(&mut self.plugins).into_iter().map(Some).collect();
+        let mut sorted: Vec<ManagedPlugin> = order.iter().filter_map(|i| plugins[*i].take()).collect();
+        sorted.extend(plugins.into_iter().flatten());
+        self.plugins = sorted;
+    }
+
+    //Replace plugin in `dir` with new build of its library. Config and enabled state of the old
+    //instance carry over, plugin that wasn't loaded before is loaded with config from `configs`
+    //and enabled unless user disabled it. Dependencies are checked like in `load_all`, reloaded
+    //plugin fails when one of them isn't loaded
+    pub fn reload(&mut self, dir: &Path, configs: &BTreeMap<String, PluginConfig>) -> Result<(), PluginManagerError> {
+        let position = self.plugins.iter().position(|managed| managed.plugin().dir() == dir);
+        let (enabled, config) = match position {
+            Some(i) => {
+                let managed = self.plugins.remove(i);
+                let id = managed.manifest.id.clone();
+                let state = (Some(managed.enabled), managed.config.clone());
+                Self::unload_plugin(managed);
+                if state.0 == Some(true) {
+                    self.publish(HostEvent::PluginDisabled { id });
+                }
+                state
+            }
+            None => (None, None)
+        };
+        self.errors.retain(|e| !e.path.starts_with(dir));
+
+        let result = self.loader.discover_dir(dir).and_then(|plugin| {
+            let order = self.resolve_reloaded(&plugin)?;
+            let plugin = self.loader.load(&plugin)?;
+            let config = config.or_else(|| configs.get(&plugin.manifest().id).cloned()).unwrap_or_default();
+            let enabled = enabled.unwrap_or_else(|| !self.disabled.contains(&plugin.manifest().id));
+            self.init_plugin(plugin, config, enabled).map(|managed| (managed, order))
+        });
+        match result {
+            Ok((managed, order)) => {
+                let id = managed.manifest.id.clone();
+                let enabled = managed.enabled;
+                self.plugins.push(managed);
+                self.sort_plugins(&order);
+                info!("Plugin {} reloaded", id);
+                if enabled {
+                    self.publish(HostEvent::PluginEnabled { id });
+                }
+                Ok(())
+            }
+            Err(e) => {
+                error!(name: "Plugin load error", "{}", e);
+                self.errors.push(e.clone());
+                Err(PluginManagerError::Load(e))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub enum PluginManagerError {
+    NotFound(String),
+    Load(PluginLoadError),
+    Disabled(String),
+    UnknownAction { id: String, action: String },
+    UnknownPanel { id: String, panel: String },
+    Hook { id: String, hook: &'static str, error: PluginError },
+    InvalidSettings { id: String, errors: Vec<SettingError> },
+    SaveConfig(ConfigSaveError),
+}
+
+impl Display for PluginManagerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PluginManagerError::NotFound(id) => write!(f, "Plugin {} is not loaded", id),
+            PluginManagerError::Load(e) => write!(f, "{}", e),
+            PluginManagerError::Disabled(id) => write!(f, "Plugin {} is disabled", id),
+            PluginManagerError::UnknownAction { id, action } => write!(f, "Plugin {} has no action {}", id, action),
+            PluginManagerError::UnknownPanel { id, panel } => write!(f, "Plugin {} has no panel {}", id, panel),
+            PluginManagerError::Hook { id, hook, error } => write!(f, "Plugin {} {} failed: {}", id, hook, error),
+            PluginManagerError::InvalidSettings { id, errors } => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "Plugin {} settings are invalid: {}", id, errors.join("; "))
+            }
+            PluginManagerError::SaveConfig(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+//Deliver event to subscribed plugins one by one, manager is locked only to find them and count failures
+pub async fn dispatch_event(plugins: &SharedPlugins, event: HostEvent) {
+    let event = Arc::new(event);
+    let handles = plugins.lock().await.event_handles(&event);
+    for handle in handles {
+        let id = handle.id().to_string();
+        let event = event.clone();
+        match tokio::task::spawn_blocking(move || handle.dispatch(&event)).await {
+            Ok(result) => with_plugins(plugins, move |plugins| plugins.track_failures(&id, &result)).await,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
+
+//Hooks block until plugin answers or its call times out, so manager is used on a blocking
+//thread and async workers keep running meanwhile
+pub async fn with_plugins<T, F>(plugins: &SharedPlugins, f: F) -> T
+where T: Send + 'static, F: FnOnce(&mut PluginManager) -> T + Send + 'static {
+    let mut plugins = plugins.clone().lock_owned().await;
+    match tokio::task::spawn_blocking(move || f(&mut plugins)).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+//Plugin configs from app config with secrets filled, locked secrets leave them empty
+pub async fn resolve_configs(config: &SharedConfig, secrets: &SharedSecrets) -> BTreeMap<String, PluginConfig> {
+    let mut configs = config.lock().await.app_conf().plugins_conf.clone();
+    let secrets = secrets.lock().await;
+    for (id, plugin_config) in configs.iter_mut() {
+        if let Err(e) = secrets.resolve_plugin_config(id, plugin_config) {
+            warn!("Secrets of plugin {} are not available: {}", id, e);
+        }
+    }
+    configs
+}
+
+#[command]
+pub async fn list_plugins(plugins: State<'_, SharedPlugins>) -> Result<Vec<PluginInfo>, ()> {
+    Ok(plugins.lock().await.list())
+}
+
+//Remember choice of user so plugin starts in the same state next time
+async fn save_disabled(config: &SharedConfig, id: &str, disabled: bool) -> Result<(), ConfigSaveError> {
+    let mut config = config.lock().await;
+    if config.app_conf().disabled_plugins.contains(id) == disabled {
+        return Ok(());
+    }
+    config.update(|app_conf| {
+        if disabled {
+            app_conf.disabled_plugins.insert(id.to_string());
+        } else {
+            app_conf.disabled_plugins.remove(id);
+        }
+    }).await
+}
+
+#[command]
+pub async fn enable_plugin(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, id: String) -> Result<(), String> {
+    {
+        let id = id.clone();
+        with_plugins(&plugins, move |plugins| plugins.enable(&id)).await.map_err(|e| e.to_string())?;
+    }
+    save_disabled(&config, &id, false).await.map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn disable_plugin(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, id: String) -> Result<(), String> {
+    let result = {
+        let id = id.clone();
+        with_plugins(&plugins, move |plugins| plugins.disable(&id)).await
+    };
+    //Failed `on_disable` still leaves plugin disabled
+    if !matches!(result, Err(PluginManagerError::NotFound(_))) {
+        save_disabled(&config, &id, true).await.map_err(|e| e.to_string())?;
+    }
+    result.map_err(|e| e.to_string())
+}
+
+#[command]
+pub async fn plugin_settings(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, id: String) -> Result<PluginSettings, String> {
+    let plugin_config = config.lock().await.app_conf().plugins_conf.get(&id).cloned();
+    plugins.lock().await.settings(&id, plugin_config.as_ref()).map_err(|e| e.to_string())
+}
+
+//Save settings from UI and deliver them to plugin
+#[command]
+pub async fn set_plugin_settings(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>, id: String, values: toml::Table) -> Result<(), PluginManagerError> {
+    plugins.lock().await.validate_settings(&id, &values)?;
+    let mut plugin_config = config.lock().await
+        .update(|app_conf| {
+            let plugin_config = app_conf.plugins_conf.entry(id.clone()).or_default();
+            plugin_config.settings = values;
+            plugin_config.clone()
+        }).await
+        .map_err(PluginManagerError::SaveConfig)?;
+    if let Err(e) = secrets.lock().await.resolve_plugin_config(&id, &mut plugin_config) {
+        warn!("Secrets of plugin {} are not available: {}", id, e);
+    }
+    with_plugins(&plugins, move |plugins| plugins.configure(&id, plugin_config)).await
+}
+
+#[command]
+pub async fn plugin_ui(plugins: State<'_, SharedPlugins>) -> Result<Vec<PluginUi>, ()> {
+    Ok(plugins.lock().await.ui())
+}
+
+//Generic entry point for actions declared by plugins, arguments and result are plain JSON
+#[command]
+pub async fn run_plugin_action(plugins: State<'_, SharedPlugins>, id: String, action: String, args: serde_json::Value) -> Result<serde_json::Value, PluginManagerError> {
+    with_plugins(&plugins, move |plugins| plugins.run_action(&id, &action, &args)).await
+}
+
+#[command]
+pub async fn render_plugin_panel(plugins: State<'_, SharedPlugins>, id: String, panel: String) -> Result<PanelContent, PluginManagerError> {
+    with_plugins(&plugins, move |plugins| plugins.render_panel(&id, &panel)).await
+}
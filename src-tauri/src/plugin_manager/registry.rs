@@ -0,0 +1,773 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ed25519_dalek::{Signature, VerifyingKey};
+use flate2::read::GzDecoder;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tauri::{command, State};
+use tracing::{error, info, warn};
+use starship_plugin_api::manifest::{PluginManifest, MANIFEST_FILE};
+use crate::config_manager::{PluginRegistryConfig, SharedConfig};
+use crate::plugin_manager::{plugins_dir, resolve_configs, with_plugins, SharedPlugins};
+use crate::secrets::SharedSecrets;
+
+//Registry directory layout: `index.toml` listing plugins, archives anywhere below it
+pub const INDEX_FILE: &str = "index.toml";
+//Detached signature of `<archive>`, hex encoded ed25519 signature of archive bytes
+pub const SIGNATURE_EXTENSION: &str = "sig";
+const STATE_FILE: &str = "installed.toml";
+
+static STAGING: AtomicUsize = AtomicUsize::new(0);
+
+//Unpacked versions are kept in `plugin-store/<id>/<version>` next to `plugins`
+pub fn store_dir() -> PathBuf {
+    std::env::current_exe().unwrap().parent().unwrap().join("plugin-store")
+}
+
+//Plugin version offered by registry index
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct IndexEntry {
+    pub id: String,
+    pub version: Version,
+    #[serde(default)]
+    pub description: String,
+    //Relative to registry directory
+    pub archive: PathBuf,
+    pub sha256: String,
+    #[serde(default)]
+    pub signature: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IndexFile {
+    #[serde(default)]
+    plugins: Vec<IndexEntry>,
+}
+
+pub struct RegistryIndex {
+    dir: PathBuf,
+    entries: Vec<IndexEntry>,
+}
+
+impl RegistryIndex {
+    pub fn read(dir: &Path) -> Result<Self, RegistryError> {
+        let con = std::fs::read_to_string(dir.join(INDEX_FILE)).map_err(|e| RegistryError::Io(e.to_string()))?;
+        let index: IndexFile = toml::from_str(&con).map_err(|e| RegistryError::InvalidIndex(e.to_string()))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            entries: index.plugins,
+        })
+    }
+
+    //Exact version or the newest one
+    pub fn find(&self, id: &str, version: Option<&Version>) -> Option<&IndexEntry> {
+        self.entries.iter()
+            .filter(|entry| entry.id == id && version.map_or(true, |version| entry.version == *version))
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InstallSource {
+    Archive { path: PathBuf },
+    Registry { index: PathBuf },
+}
+
+//Installed plugin with version it can roll back to
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstalledPlugin {
+    pub id: String,
+    pub version: Version,
+    #[serde(default)]
+    pub previous: Option<Version>,
+    pub source: InstallSource,
+    //Trusted key archive was signed with
+    #[serde(default)]
+    pub signed_by: Option<String>,
+}
+
+//Row of plugin list: what is installed and what registry offers
+#[derive(Serialize, Clone, Debug)]
+pub struct RegistryPlugin {
+    pub id: String,
+    pub description: String,
+    pub installed: Option<Version>,
+    pub previous: Option<Version>,
+    pub latest: Option<Version>,
+}
+
+//Verified plugin unpacked to staging directory, removed unless activated
+pub struct PreparedPlugin {
+    manifest: PluginManifest,
+    staging: PathBuf,
+    root: PathBuf,
+    source: InstallSource,
+    signed_by: Option<String>,
+}
+
+impl PreparedPlugin {
+    pub fn id(&self) -> &str {
+        &self.manifest.id
+    }
+}
+
+impl Drop for PreparedPlugin {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.staging);
+    }
+}
+
+//Installs plugin archives into `plugins` dir. Every installed version is unpacked to store first,
+//active and previous version stay there so upgrade can be rolled back without the archive
+pub struct PluginStore {
+    plugins_dir: PathBuf,
+    store_dir: PathBuf,
+    config: PluginRegistryConfig,
+    host_version: Version,
+}
+
+impl PluginStore {
+    pub fn new(plugins_dir: PathBuf, store_dir: PathBuf, config: PluginRegistryConfig) -> Self {
+        Self {
+            plugins_dir,
+            store_dir,
+            config,
+            host_version: Version::parse(env!("CARGO_PKG_VERSION")).unwrap(),
+        }
+    }
+
+    //Store with default directories
+    pub fn from_config(config: &PluginRegistryConfig) -> Self {
+        Self::new(plugins_dir(), store_dir(), config.clone())
+    }
+
+    pub fn plugins_dir(&self) -> &Path {
+        &self.plugins_dir
+    }
+
+    fn index(&self) -> Result<RegistryIndex, RegistryError> {
+        let dir = self.config.index.as_ref().ok_or(RegistryError::NoRegistry)?;
+        RegistryIndex::read(dir)
+    }
+
+    fn read_state(&self) -> Result<BTreeMap<String, InstalledPlugin>, RegistryError> {
+        match std::fs::read_to_string(self.store_dir.join(STATE_FILE)) {
+            Ok(con) => toml::from_str(&con).map_err(|e| RegistryError::Io(format!("{} is broken: {}", STATE_FILE, e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(RegistryError::Io(e.to_string())),
+        }
+    }
+
+    fn write_state(&self, state: &BTreeMap<String, InstalledPlugin>) -> Result<(), RegistryError> {
+        let con = toml::to_string(state).map_err(|e| RegistryError::Io(e.to_string()))?;
+        std::fs::create_dir_all(&self.store_dir).map_err(io_error)?;
+        std::fs::write(self.store_dir.join(STATE_FILE), con).map_err(io_error)
+    }
+
+    pub fn installed(&self) -> Result<Vec<InstalledPlugin>, RegistryError> {
+        Ok(self.read_state()?.into_values().collect())
+    }
+
+    //Installed plugins and plugins of registry index, registry is skipped when not configured
+    pub fn list(&self) -> Result<Vec<RegistryPlugin>, RegistryError> {
+        let mut plugins: BTreeMap<String, RegistryPlugin> = BTreeMap::new();
+        for installed in self.read_state()?.into_values() {
+            plugins.insert(installed.id.clone(), RegistryPlugin {
+                id: installed.id,
+                description: String::new(),
+                installed: Some(installed.version),
+                previous: installed.previous,
+                latest: None,
+            });
+        }
+        if self.config.index.is_some() {
+            for entry in self.index()?.entries {
+                let plugin = plugins.entry(entry.id.clone()).or_insert_with(|| RegistryPlugin {
+                    id: entry.id.clone(),
+                    description: String::new(),
+                    installed: None,
+                    previous: None,
+                    latest: None,
+                });
+                if plugin.latest.as_ref().map_or(true, |latest| entry.version > *latest) {
+                    plugin.latest = Some(entry.version);
+                    plugin.description = entry.description;
+                }
+            }
+        }
+        Ok(plugins.into_values().collect())
+    }
+
+    //Archive from disk, signature is read from `<archive>.sig` when present
+    pub fn prepare_archive(&self, path: &Path, sha256: &str) -> Result<PreparedPlugin, RegistryError> {
+        let data = std::fs::read(path).map_err(|e| RegistryError::Io(format!("{}: {}", path.display(), e)))?;
+        let signature = read_signature(path)?;
+        let signed_by = self.verify(&data, sha256, signature.as_deref())?;
+        self.unpack(&data, InstallSource::Archive { path: path.to_path_buf() }, signed_by)
+    }
+
+    //Newest or exact version from registry index, checksum of index entry is mandatory
+    pub fn prepare_registry(&self, id: &str, version: Option<&Version>) -> Result<PreparedPlugin, RegistryError> {
+        let index = self.index()?;
+        let entry = index.find(id, version).ok_or_else(|| match version {
+            Some(version) => RegistryError::NotFound(format!("{} {}", id, version)),
+            None => RegistryError::NotFound(id.to_string()),
+        })?;
+        let path = index.dir.join(&entry.archive);
+        let data = std::fs::read(&path).map_err(|e| RegistryError::Io(format!("{}: {}", path.display(), e)))?;
+        let signature = match &entry.signature {
+            Some(signature) => Some(signature.clone()),
+            None => read_signature(&path)?,
+        };
+        let signed_by = self.verify(&data, &entry.sha256, signature.as_deref())?;
+        let plugin = self.unpack(&data, InstallSource::Registry { index: index.dir.clone() }, signed_by)?;
+        if plugin.manifest.id != entry.id || plugin.manifest.version != entry.version {
+            return Err(RegistryError::InvalidArchive(format!(
+                "index lists {} {} but archive has {} {}", entry.id, entry.version, plugin.manifest.id, plugin.manifest.version
+            )));
+        }
+        Ok(plugin)
+    }
+
+    //Checks checksum and signature, returns trusted key that signed the archive. Checksum is
+    //always required, signature is once any trusted key is configured
+    fn verify(&self, data: &[u8], sha256: &str, signature: Option<&str>) -> Result<Option<String>, RegistryError> {
+        let expected = sha256.trim();
+        if expected.is_empty() {
+            return Err(RegistryError::NoChecksum);
+        }
+        let actual = hex::encode(Sha256::digest(data));
+        if !actual.eq_ignore_ascii_case(expected) {
+            return Err(RegistryError::Checksum {
+                expected: expected.to_string(),
+                actual,
+            });
+        }
+        let signature = match signature {
+            Some(signature) => signature,
+            None if self.config.require_signature || !self.config.trusted_keys.is_empty() => return Err(RegistryError::Unsigned),
+            None => return Ok(None),
+        };
+        let keys = self.trusted_keys()?;
+        if keys.is_empty() && !self.config.require_signature {
+            warn!("Plugin archive is signed but no trusted keys are configured, signature is not checked");
+            return Ok(None);
+        }
+        let signature = decode_hex::<64>(signature)
+            .map(|bytes| Signature::from_bytes(&bytes))
+            .ok_or_else(|| RegistryError::BadSignature("signature is not 64 hex encoded bytes".to_string()))?;
+        keys.iter()
+            .find(|(_, key)| key.verify_strict(data, &signature).is_ok())
+            .map(|(hex, _)| Some(hex.clone()))
+            .ok_or_else(|| RegistryError::BadSignature("archive is not signed by a trusted key".to_string()))
+    }
+
+    fn trusted_keys(&self) -> Result<Vec<(String, VerifyingKey)>, RegistryError> {
+        self.config.trusted_keys.iter()
+            .map(|hex| {
+                decode_hex::<32>(hex)
+                    .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                    .map(|key| (hex.trim().to_lowercase(), key))
+                    .ok_or_else(|| RegistryError::InvalidKey(hex.clone()))
+            })
+            .collect()
+    }
+
+    //Unpacks `.tar.gz` to staging directory in store. Manifest may be at archive root or in its only directory
+    fn unpack(&self, data: &[u8], source: InstallSource, signed_by: Option<String>) -> Result<PreparedPlugin, RegistryError> {
+        let staging = self.store_dir.join(format!(".staging-{}-{}", std::process::id(), STAGING.fetch_add(1, Ordering::Relaxed)));
+        match self.unpack_to(data, &staging) {
+            Ok((root, manifest)) => Ok(PreparedPlugin {
+                manifest,
+                staging,
+                root,
+                source,
+                signed_by,
+            }),
+            Err(e) => {
+                let _ = std::fs::remove_dir_all(&staging);
+                Err(e)
+            }
+        }
+    }
+
+    fn unpack_to(&self, data: &[u8], staging: &Path) -> Result<(PathBuf, PluginManifest), RegistryError> {
+        std::fs::create_dir_all(staging).map_err(io_error)?;
+        let mut archive = tar::Archive::new(GzDecoder::new(data));
+        let entries = archive.entries().map_err(|e| RegistryError::InvalidArchive(e.to_string()))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| RegistryError::InvalidArchive(e.to_string()))?;
+            let path = entry.path().map(|path| path.into_owned()).unwrap_or_default();
+            //Entries escaping archive root are refused, not skipped
+            if !entry.unpack_in(staging).map_err(|e| RegistryError::InvalidArchive(e.to_string()))? {
+                return Err(RegistryError::InvalidArchive(format!("entry {} points outside of archive", path.display())));
+            }
+        }
+        let root = plugin_root(staging)?;
+        let manifest = PluginManifest::read(&root).map_err(|e| RegistryError::InvalidManifest(e.to_string()))?;
+        if manifest.id.starts_with('.') {
+            return Err(RegistryError::InvalidManifest(format!("invalid plugin id {}", manifest.id)));
+        }
+        manifest.check_host(&self.host_version).map_err(|e| RegistryError::Incompatible(e.to_string()))?;
+        Ok((root, manifest))
+    }
+
+    fn version_dir(&self, id: &str, version: &Version) -> PathBuf {
+        self.store_dir.join(id).join(version.to_string())
+    }
+
+    //Directory plugin is loaded from, hand made directories may be named differently than the id
+    pub fn active_dir(&self, id: &str) -> Option<PathBuf> {
+        let dir = self.plugins_dir.join(id);
+        if dir.join(MANIFEST_FILE).is_file() {
+            return Some(dir);
+        }
+        std::fs::read_dir(&self.plugins_dir).ok()?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .find(|path| PluginManifest::read(path).map_or(false, |manifest| manifest.id == id))
+    }
+
+    //Makes prepared version active, version it replaced becomes rollback target.
+    //Plugin must be unloaded by caller, loaded libraries can't be replaced on every platform
+    pub fn activate(&self, plugin: PreparedPlugin) -> Result<InstalledPlugin, RegistryError> {
+        let mut state = self.read_state()?;
+        let id = plugin.manifest.id.clone();
+        let dest = self.version_dir(&id, &plugin.manifest.version);
+        if dest.exists() {
+            std::fs::remove_dir_all(&dest).map_err(io_error)?;
+        }
+        std::fs::create_dir_all(dest.parent().unwrap()).map_err(io_error)?;
+        std::fs::rename(&plugin.root, &dest).map_err(io_error)?;
+        let replaced = self.switch_to(&id, &plugin.manifest.version)?;
+        let previous = match (&replaced, state.get(&id)) {
+            (Some(replaced), _) if *replaced != plugin.manifest.version => Some(replaced.clone()),
+            (_, Some(installed)) => installed.previous.clone(),
+            _ => None,
+        };
+        let installed = InstalledPlugin {
+            id: id.clone(),
+            version: plugin.manifest.version.clone(),
+            previous,
+            source: plugin.source.clone(),
+            signed_by: plugin.signed_by.clone(),
+        };
+        state.insert(id.clone(), installed.clone());
+        self.write_state(&state)?;
+        self.prune(&installed);
+        info!("Plugin {} {} installed", id, installed.version);
+        Ok(installed)
+    }
+
+    //Swap active and previous version
+    pub fn rollback(&self, id: &str) -> Result<InstalledPlugin, RegistryError> {
+        let mut state = self.read_state()?;
+        let installed = state.get_mut(id).ok_or_else(|| RegistryError::NotInstalled(id.to_string()))?;
+        let previous = installed.previous.clone().ok_or_else(|| RegistryError::NoPreviousVersion(id.to_string()))?;
+        self.switch_to(id, &previous)?;
+        installed.previous = Some(std::mem::replace(&mut installed.version, previous));
+        let installed = installed.clone();
+        self.write_state(&state)?;
+        info!("Plugin {} rolled back to {}", id, installed.version);
+        Ok(installed)
+    }
+
+    //Deletes active directory and every stored version
+    pub fn remove(&self, id: &str) -> Result<(), RegistryError> {
+        let mut state = self.read_state()?;
+        let active = self.active_dir(id);
+        if active.is_none() && !state.contains_key(id) {
+            return Err(RegistryError::NotInstalled(id.to_string()));
+        }
+        if let Some(dir) = active {
+            std::fs::remove_dir_all(dir).map_err(io_error)?;
+        }
+        let stored = self.store_dir.join(id);
+        if stored.exists() {
+            std::fs::remove_dir_all(stored).map_err(io_error)?;
+        }
+        state.remove(id);
+        self.write_state(&state)?;
+        info!("Plugin {} removed", id);
+        Ok(())
+    }
+
+    //Replaces active directory with stored version, returns version that was active.
+    //New version is staged in a hidden directory next to the active one and swapped in with
+    //renames, old directory is put back when the swap fails.
+    //Hand made directory is copied to store first so it can be rolled back to
+    fn switch_to(&self, id: &str, version: &Version) -> Result<Option<Version>, RegistryError> {
+        let source = self.version_dir(id, version);
+        if !source.is_dir() {
+            return Err(RegistryError::NotFound(format!("{} {} in plugin store", id, version)));
+        }
+        let active = self.active_dir(id);
+        let replaced = match &active {
+            Some(dir) => match PluginManifest::read(dir) {
+                Ok(manifest) => {
+                    let backup = self.version_dir(id, &manifest.version);
+                    if !backup.exists() {
+                        copy_dir(dir, &backup).map_err(io_error)?;
+                    }
+                    Some(manifest.version)
+                }
+                Err(e) => {
+                    warn!("Replacing plugin {} with unreadable manifest: {}", dir.display(), e);
+                    None
+                }
+            },
+            None => None
+        };
+        std::fs::create_dir_all(&self.plugins_dir).map_err(io_error)?;
+        let incoming = self.plugins_dir.join(format!(".incoming-{}", id));
+        let outgoing = self.plugins_dir.join(format!(".outgoing-{}", id));
+        for dir in [&incoming, &outgoing] {
+            if dir.exists() {
+                std::fs::remove_dir_all(dir).map_err(io_error)?;
+            }
+        }
+        if let Err(e) = copy_dir(&source, &incoming) {
+            let _ = std::fs::remove_dir_all(&incoming);
+            return Err(io_error(e));
+        }
+        if let Some(dir) = &active {
+            if let Err(e) = std::fs::rename(dir, &outgoing) {
+                let _ = std::fs::remove_dir_all(&incoming);
+                return Err(io_error(e));
+            }
+        }
+        if let Err(e) = std::fs::rename(&incoming, self.plugins_dir.join(id)) {
+            let _ = std::fs::remove_dir_all(&incoming);
+            if let Some(dir) = &active {
+                if let Err(restore) = std::fs::rename(&outgoing, dir) {
+                    error!("Can't restore plugin {} from {}: {}", id, outgoing.display(), restore);
+                }
+            }
+            return Err(io_error(e));
+        }
+        if active.is_some() {
+            if let Err(e) = std::fs::remove_dir_all(&outgoing) {
+                warn!("Can't remove replaced plugin files {}: {}", outgoing.display(), e);
+            }
+        }
+        Ok(replaced)
+    }
+
+    //Only active and previous versions are kept
+    fn prune(&self, installed: &InstalledPlugin) {
+        let entries = match std::fs::read_dir(self.store_dir.join(&installed.id)) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let keep = Version::parse(&entry.file_name().to_string_lossy())
+                .map_or(false, |version| version == installed.version || Some(&version) == installed.previous.as_ref());
+            if !keep {
+                if let Err(e) = std::fs::remove_dir_all(entry.path()) {
+                    warn!("Can't remove old plugin version {}: {}", entry.path().display(), e);
+                }
+            }
+        }
+    }
+}
+
+fn io_error(e: std::io::Error) -> RegistryError {
+    RegistryError::Io(e.to_string())
+}
+
+fn read_signature(archive: &Path) -> Result<Option<String>, RegistryError> {
+    let mut path = archive.as_os_str().to_os_string();
+    path.push(".");
+    path.push(SIGNATURE_EXTENSION);
+    match std::fs::read_to_string(PathBuf::from(path)) {
+        Ok(signature) => Ok(Some(signature.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(RegistryError::Io(e.to_string())),
+    }
+}
+
+fn decode_hex<const N: usize>(value: &str) -> Option<[u8; N]> {
+    hex::decode(value.trim()).ok()?.try_into().ok()
+}
+
+fn plugin_root(dir: &Path) -> Result<PathBuf, RegistryError> {
+    if dir.join(MANIFEST_FILE).is_file() {
+        return Ok(dir.to_path_buf());
+    }
+    let entries: Vec<PathBuf> = std::fs::read_dir(dir).map_err(io_error)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .collect();
+    match entries.as_slice() {
+        [only] if only.join(MANIFEST_FILE).is_file() => Ok(only.clone()),
+        _ => Err(RegistryError::InvalidArchive(format!("archive has no {}", MANIFEST_FILE))),
+    }
+}
+
+fn copy_dir(from: &Path, to: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let dest = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            std::fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+pub enum RegistryError {
+    Io(String),
+    NoRegistry,
+    InvalidIndex(String),
+    InvalidArchive(String),
+    InvalidManifest(String),
+    Incompatible(String),
+    NoChecksum,
+    Checksum { expected: String, actual: String },
+    Unsigned,
+    BadSignature(String),
+    InvalidKey(String),
+    NotFound(String),
+    NotInstalled(String),
+    NoPreviousVersion(String),
+}
+
+impl Display for RegistryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RegistryError::Io(e) => write!(f, "Plugin store io error: {}", e),
+            RegistryError::NoRegistry => write!(f, "Plugin registry is not configured, set `plugin_registry.index`"),
+            RegistryError::InvalidIndex(e) => write!(f, "Invalid {}: {}", INDEX_FILE, e),
+            RegistryError::InvalidArchive(e) => write!(f, "Invalid plugin archive: {}", e),
+            RegistryError::InvalidManifest(e) => write!(f, "Invalid plugin manifest: {}", e),
+            RegistryError::Incompatible(e) => write!(f, "Plugin can't run in this app: {}", e),
+            RegistryError::NoChecksum => write!(f, "Plugin archive has no sha256 checksum to check it against"),
+            RegistryError::Checksum { expected, actual } => write!(f, "Plugin archive checksum is {}, expected {}", actual, expected),
+            RegistryError::Unsigned => write!(f, "Plugin archive is not signed, signatures are required once trusted keys are configured"),
+            RegistryError::BadSignature(e) => write!(f, "Plugin archive signature is invalid: {}", e),
+            RegistryError::InvalidKey(key) => write!(f, "Trusted key {} is not a hex encoded ed25519 public key", key),
+            RegistryError::NotFound(plugin) => write!(f, "Plugin {} is not available", plugin),
+            RegistryError::NotInstalled(id) => write!(f, "Plugin {} is not installed", id),
+            RegistryError::NoPreviousVersion(id) => write!(f, "Plugin {} has no previous version to roll back to", id),
+        }
+    }
+}
+
+//Unloads running plugin, swaps its files and loads the new version. When activation fails
+//old files are still in place and are loaded again
+async fn apply(plugins: &SharedPlugins, config: &SharedConfig, secrets: &SharedSecrets, store: PluginStore, id: String, change: impl FnOnce(&PluginStore) -> Result<InstalledPlugin, RegistryError> + Send + 'static) -> Result<InstalledPlugin, RegistryError> {
+    let configs = resolve_configs(config, secrets).await;
+    with_plugins(plugins, move |plugins| {
+        let _ = plugins.unload(&id);
+        let result = change(&store);
+        if let Some(dir) = store.active_dir(&id) {
+            if let Err(e) = plugins.reload(&dir, &configs) {
+                error!("{}", e);
+            }
+        }
+        result
+    }).await
+}
+
+//Reading, checking and unpacking archive is blocking IO, it runs on a blocking thread
+async fn prepare(store: PluginStore, f: impl FnOnce(&PluginStore) -> Result<PreparedPlugin, RegistryError> + Send + 'static) -> Result<(PluginStore, PreparedPlugin), RegistryError> {
+    match tokio::task::spawn_blocking(move || f(&store).map(|prepared| (store, prepared))).await {
+        Ok(result) => result,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+async fn store(config: &SharedConfig) -> PluginStore {
+    PluginStore::from_config(&config.lock().await.app_conf().plugin_registry)
+}
+
+#[command]
+pub async fn registry_plugins(config: State<'_, SharedConfig>) -> Result<Vec<RegistryPlugin>, RegistryError> {
+    store(&config).await.list()
+}
+
+#[command]
+pub async fn install_plugin_archive(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>, path: PathBuf, sha256: String) -> Result<InstalledPlugin, RegistryError> {
+    let (store, prepared) = prepare(store(&config).await, move |store| store.prepare_archive(&path, &sha256)).await?;
+    let id = prepared.id().to_string();
+    apply(&plugins, &config, &secrets, store, id, move |store| store.activate(prepared)).await
+}
+
+#[command]
+pub async fn install_registry_plugin(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>, id: String, version: Option<Version>) -> Result<InstalledPlugin, RegistryError> {
+    let registry_id = id.clone();
+    let (store, prepared) = prepare(store(&config).await, move |store| store.prepare_registry(&registry_id, version.as_ref())).await?;
+    apply(&plugins, &config, &secrets, store, id, move |store| store.activate(prepared)).await
+}
+
+#[command]
+pub async fn rollback_plugin(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, secrets: State<'_, SharedSecrets>, id: String) -> Result<InstalledPlugin, RegistryError> {
+    let store = store(&config).await;
+    let rollback = id.clone();
+    apply(&plugins, &config, &secrets, store, id, move |store| store.rollback(&rollback)).await
+}
+
+#[command]
+pub async fn remove_plugin(plugins: State<'_, SharedPlugins>, config: State<'_, SharedConfig>, id: String) -> Result<(), RegistryError> {
+    let store = store(&config).await;
+    with_plugins(&plugins, move |plugins| {
+        let _ = plugins.unload(&id);
+        store.remove(&id)
+    }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer, SigningKey};
+    use super::*;
+
+    const ARCHIVE: &[u8] = b"plugin archive bytes";
+
+    fn store(trusted_keys: Vec<String>) -> PluginStore {
+        let config = PluginRegistryConfig {
+            trusted_keys,
+            ..Default::default()
+        };
+        PluginStore::new(PathBuf::from("plugins"), PathBuf::from("plugin-store"), config)
+    }
+
+    fn public_hex(key: &SigningKey) -> String {
+        hex::encode(key.verifying_key().to_bytes())
+    }
+
+    fn sign(key: &SigningKey) -> String {
+        hex::encode(key.sign(ARCHIVE).to_bytes())
+    }
+
+    fn manifest(version: &str) -> String {
+        format!("id = \"notes\"\nversion = \"{}\"\nauthor = \"test\"\nmin_host_version = \"0.1.0\"\n", version)
+    }
+
+    //`.tar.gz` with given entries, names are written as is so they may escape archive root
+    fn archive(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(vec![], flate2::Compression::default()));
+        for (name, content) in entries {
+            let mut header = tar::Header::new_old();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn plugin_archive(version: &str) -> Vec<u8> {
+        archive(&[("notes/plugin.toml", &manifest(version)), ("notes/version.txt", version)])
+    }
+
+    fn local_store(dir: &Path) -> PluginStore {
+        PluginStore::new(dir.join("plugins"), dir.join("plugin-store"), PluginRegistryConfig::default())
+    }
+
+    fn install(store: &PluginStore, version: &str) -> InstalledPlugin {
+        let prepared = store.unpack(&plugin_archive(version), InstallSource::Archive { path: PathBuf::from("notes.tar.gz") }, None).unwrap();
+        store.activate(prepared).unwrap()
+    }
+
+    fn active_version(store: &PluginStore) -> String {
+        std::fs::read_to_string(store.plugins_dir().join("notes").join("version.txt")).unwrap()
+    }
+
+    #[test]
+    fn entry_outside_archive_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = local_store(dir.path());
+        let data = archive(&[("plugin.toml", &manifest("1.0.0")), ("../escaped.txt", "x")]);
+        let result = store.unpack(&data, InstallSource::Archive { path: PathBuf::from("notes.tar.gz") }, None);
+        assert!(matches!(result, Err(RegistryError::InvalidArchive(_))));
+        assert!(!dir.path().join("escaped.txt").exists());
+        assert_eq!(std::fs::read_dir(dir.path().join("plugin-store")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn upgrade_keeps_previous_version_for_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = local_store(dir.path());
+        let first = install(&store, "1.0.0");
+        assert_eq!((first.version.to_string(), first.previous), ("1.0.0".to_string(), None));
+        let upgraded = install(&store, "2.0.0");
+        assert_eq!(upgraded.previous, Some(Version::new(1, 0, 0)));
+        assert_eq!(active_version(&store), "2.0.0");
+
+        let rolled_back = store.rollback("notes").unwrap();
+        assert_eq!((rolled_back.version, rolled_back.previous), (Version::new(1, 0, 0), Some(Version::new(2, 0, 0))));
+        assert_eq!(active_version(&store), "1.0.0");
+        store.rollback("notes").unwrap();
+        assert_eq!(active_version(&store), "2.0.0");
+
+        //Only active and previous versions stay in store
+        install(&store, "3.0.0");
+        let mut stored: Vec<String> = std::fs::read_dir(dir.path().join("plugin-store").join("notes")).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        stored.sort();
+        assert_eq!(stored, vec!["2.0.0", "3.0.0"]);
+        assert_eq!(store.installed().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn hand_made_plugin_is_restored_by_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = local_store(dir.path());
+        let hand_made = dir.path().join("plugins").join("my-notes");
+        std::fs::create_dir_all(&hand_made).unwrap();
+        std::fs::write(hand_made.join("plugin.toml"), manifest("0.5.0")).unwrap();
+        std::fs::write(hand_made.join("version.txt"), "0.5.0").unwrap();
+
+        let installed = install(&store, "1.0.0");
+        assert_eq!(installed.previous, Some(Version::new(0, 5, 0)));
+        assert!(!hand_made.exists());
+        store.rollback("notes").unwrap();
+        assert_eq!(active_version(&store), "0.5.0");
+        assert!(matches!(store.rollback("other"), Err(RegistryError::NotInstalled(_))));
+    }
+
+    #[test]
+    fn rollback_needs_previous_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = local_store(dir.path());
+        install(&store, "1.0.0");
+        assert!(matches!(store.rollback("notes"), Err(RegistryError::NoPreviousVersion(_))));
+        assert_eq!(active_version(&store), "1.0.0");
+    }
+
+    #[test]
+    fn checksum_is_required() {
+        let store = store(vec![]);
+        let sha256 = hex::encode(Sha256::digest(ARCHIVE));
+        assert!(matches!(store.verify(ARCHIVE, &sha256.to_uppercase(), None), Ok(None)));
+        assert!(matches!(store.verify(ARCHIVE, &"0".repeat(64), None), Err(RegistryError::Checksum { .. })));
+        assert!(matches!(store.verify(ARCHIVE, " ", None), Err(RegistryError::NoChecksum)));
+    }
+
+    #[test]
+    fn signature_of_trusted_key_is_required() {
+        let trusted = SigningKey::from_bytes(&[1; 32]);
+        let other = SigningKey::from_bytes(&[2; 32]);
+        let store = store(vec![public_hex(&trusted)]);
+        let sha256 = hex::encode(Sha256::digest(ARCHIVE));
+        match store.verify(ARCHIVE, &sha256, Some(&sign(&trusted))) {
+            Ok(Some(key)) => assert_eq!(key, public_hex(&trusted)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(store.verify(ARCHIVE, &sha256, Some(&sign(&other))), Err(RegistryError::BadSignature(_))));
+        assert!(matches!(store.verify(ARCHIVE, &sha256, Some("not hex")), Err(RegistryError::BadSignature(_))));
+        assert!(matches!(store.verify(ARCHIVE, &sha256, None), Err(RegistryError::Unsigned)));
+    }
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use crate::rpc::{Notification, Protocol};
+
+pub struct BlazzyProtocol;
+
+impl Protocol for BlazzyProtocol {
+    const SERVICE: &'static str = "blazzy";
+    const VERSION: Version = Version::new(1, 0, 0);
+    const CONNECTION_KEY: &'static str = "blazzy";
+}
+
+//Start reporting changes under `path`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Watch {
+    pub path: PathBuf,
+}
+
+impl Notification for Watch {
+    type Protocol = BlazzyProtocol;
+    const METHOD: &'static str = "watch";
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.path.is_absolute() {
+            return Err(format!("{} is not absolute", self.path.display()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Unwatch {
+    pub path: PathBuf,
+}
+
+impl Notification for Unwatch {
+    type Protocol = BlazzyProtocol;
+    const METHOD: &'static str = "unwatch";
+}
+
+//Changes blazzy reports for watched paths
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BlazzyEvent {
+    Created { path: PathBuf },
+    Modified { path: PathBuf },
+    Removed { path: PathBuf },
+    Renamed { from: PathBuf, to: PathBuf },
+}
@@ -0,0 +1,251 @@
+pub mod blazzy;
+pub mod tasker;
+
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use semver::Version;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use crate::ws_connector::{WsConnector, WsConnectorError};
+
+pub const JSONRPC_VERSION: &str = "2.0";
+
+//Error codes of JSON-RPC 2.0, services use -32000 to -32099 for their own
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+//Protocol of one service. Service reports its version in `hello`, major versions have to match.
+//Messages of protocol always go over the connection service is reached through
+pub trait Protocol {
+    const SERVICE: &'static str;
+    const VERSION: Version;
+    //Key of the service connection in `WsConnector`
+    const CONNECTION_KEY: &'static str;
+}
+
+//Request with reply, struct fields are its params
+pub trait Method: Serialize {
+    type Protocol: Protocol;
+    type Result: DeserializeOwned;
+    const METHOD: &'static str;
+
+    //Checked before request is sent
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+//Message to service without reply
+pub trait Notification: Serialize {
+    type Protocol: Protocol;
+    const METHOD: &'static str;
+
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ErrorObject {
+    pub code: i64,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl Display for ErrorObject {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let kind = match self.code {
+            PARSE_ERROR => "parse error",
+            INVALID_REQUEST => "invalid request",
+            METHOD_NOT_FOUND => "method not found",
+            INVALID_PARAMS => "invalid params",
+            INTERNAL_ERROR => "internal error",
+            _ => "error",
+        };
+        write!(f, "{} {}: {}", kind, self.code, self.message)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Response {
+    jsonrpc: String,
+    #[allow(dead_code)]
+    id: Value,
+    #[serde(default)]
+    result: Option<Value>,
+    #[serde(default)]
+    error: Option<ErrorObject>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IncomingNotification {
+    jsonrpc: String,
+    method: String,
+    #[serde(default)]
+    params: Option<Value>,
+}
+
+//First request on every connection, service answers with its name and protocol version
+#[derive(Serialize)]
+struct Hello {
+    client: &'static str,
+    protocol: String,
+}
+
+#[derive(Deserialize)]
+struct HelloResult {
+    service: String,
+    protocol: Version,
+}
+
+//Send typed request over connection and parse its result.
+//Like `WsConnector::request`, the future doesn't borrow connector
+pub fn call<M: Method>(connector: &WsConnector, request: &M) -> impl Future<Output = Result<M::Result, RpcError>> {
+    let sent = request.validate()
+        .map_err(RpcError::InvalidParams)
+        .and_then(|_| message(M::METHOD, request))
+        .map(|payload| connector.request(<M::Protocol as Protocol>::CONNECTION_KEY, payload));
+    async move {
+        let reply = sent?.await.map_err(RpcError::Transport)?;
+        parse_response(reply)
+    }
+}
+
+pub async fn notify<N: Notification>(connector: &WsConnector, notification: &N) -> Result<(), RpcError> {
+    let message = notification_message(notification)?;
+    connector.send(<N::Protocol as Protocol>::CONNECTION_KEY, &message).await.map_err(RpcError::Transport)
+}
+
+//Text of notification, for connections not managed by `WsConnector`
+pub fn notification_message<N: Notification>(notification: &N) -> Result<String, RpcError> {
+    notification.validate().map_err(RpcError::InvalidParams)?;
+    message(N::METHOD, notification).map(|message| message.to_string())
+}
+
+//Check that service speaks compatible version of protocol `P`
+pub fn handshake<P: Protocol>(connector: &WsConnector) -> impl Future<Output = Result<Version, RpcError>> {
+    let hello = Hello {
+        client: "starship",
+        protocol: P::VERSION.to_string(),
+    };
+    let sent = message("hello", &hello).map(|payload| connector.request(P::CONNECTION_KEY, payload));
+    async move {
+        let reply = sent?.await.map_err(RpcError::Transport)?;
+        let hello: HelloResult = parse_response(reply)?;
+        let compatible = hello.service == P::SERVICE && hello.protocol.major == P::VERSION.major
+            && (P::VERSION.major > 0 || hello.protocol.minor == P::VERSION.minor);
+        if !compatible {
+            return Err(RpcError::IncompatibleVersion {
+                service: hello.service,
+                expected: format!("{} {}", P::SERVICE, P::VERSION),
+                actual: hello.protocol.to_string(),
+            });
+        }
+        Ok(hello.protocol)
+    }
+}
+
+//Params have to be object or array, params of unit struct are left out
+fn message<T: Serialize>(method: &str, params: &T) -> Result<Value, RpcError> {
+    let params = serde_json::to_value(params).map_err(|e| RpcError::InvalidParams(e.to_string()))?;
+    match params {
+        Value::Null => Ok(json!({ "jsonrpc": JSONRPC_VERSION, "method": method })),
+        Value::Object(_) | Value::Array(_) => Ok(json!({ "jsonrpc": JSONRPC_VERSION, "method": method, "params": params })),
+        _ => Err(RpcError::InvalidParams(format!("params of {} must be object or array", method))),
+    }
+}
+
+//Connector resolves request only with reply of the same id, it isn't checked again
+fn parse_response<T: DeserializeOwned>(reply: Value) -> Result<T, RpcError> {
+    let response: Response = serde_json::from_value(reply).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+    if response.jsonrpc != JSONRPC_VERSION {
+        return Err(RpcError::InvalidResponse(format!("unsupported jsonrpc version {}", response.jsonrpc)));
+    }
+    match (response.result, response.error) {
+        (_, Some(error)) => Err(RpcError::Remote(error)),
+        (result, None) => serde_json::from_value(result.unwrap_or(Value::Null)).map_err(|e| RpcError::InvalidResponse(e.to_string())),
+    }
+}
+
+//Notification pushed by service, `N` is enum of its notifications tagged by `method` with `params` as content
+pub fn parse_notification<N: DeserializeOwned>(text: &str) -> Result<N, RpcError> {
+    let notification: IncomingNotification = serde_json::from_str(text).map_err(|e| RpcError::InvalidNotification(e.to_string()))?;
+    if notification.jsonrpc != JSONRPC_VERSION {
+        return Err(RpcError::InvalidNotification(format!("unsupported jsonrpc version {}", notification.jsonrpc)));
+    }
+    let tagged = match notification.params {
+        Some(params) => json!({ "method": notification.method, "params": params }),
+        None => json!({ "method": notification.method }),
+    };
+    serde_json::from_value(tagged).map_err(|e| RpcError::InvalidNotification(format!("{}: {}", notification.method, e)))
+}
+
+#[derive(Serialize, Debug)]
+pub enum RpcError {
+    Transport(WsConnectorError),
+    //Error object service replied with
+    Remote(ErrorObject),
+    InvalidParams(String),
+    InvalidResponse(String),
+    InvalidNotification(String),
+    IncompatibleVersion { service: String, expected: String, actual: String },
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Transport(e) => write!(f, "{}", e),
+            RpcError::Remote(e) => write!(f, "service error: {}", e),
+            RpcError::InvalidParams(e) => write!(f, "invalid params: {}", e),
+            RpcError::InvalidResponse(e) => write!(f, "invalid response: {}", e),
+            RpcError::InvalidNotification(e) => write!(f, "invalid notification: {}", e),
+            RpcError::IncompatibleVersion { service, expected, actual } => write!(f, "{} speaks protocol {}, expected {}", service, actual, expected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn result_is_parsed() {
+        let result: Vec<u32> = parse_response(json!({"jsonrpc": "2.0", "id": 1, "result": [1, 2]})).unwrap();
+        assert_eq!(result, vec![1, 2]);
+        parse_response::<()>(json!({"jsonrpc": "2.0", "id": 1})).unwrap();
+    }
+
+    #[test]
+    fn error_object_is_remote_error() {
+        let reply = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": METHOD_NOT_FOUND, "message": "no such method", "data": "scan"}});
+        match parse_response::<Value>(reply) {
+            Err(RpcError::Remote(error)) => {
+                assert_eq!(error, ErrorObject { code: METHOD_NOT_FOUND, message: "no such method".to_string(), data: Some(json!("scan")) });
+                assert_eq!(error.to_string(), "method not found -32601: no such method");
+            }
+            _ => panic!("expected remote error"),
+        }
+    }
+
+    #[test]
+    fn invalid_responses_are_refused() {
+        let replies = [
+            json!({"jsonrpc": "1.0", "id": 1, "result": true}),
+            json!({"jsonrpc": "2.0", "result": true}),
+            json!({"jsonrpc": "2.0", "id": 1, "result": true, "extra": 1}),
+            json!({"jsonrpc": "2.0", "id": 1, "result": "yes"}),
+            json!({"jsonrpc": "2.0", "id": 1, "error": {"code": "bad"}}),
+        ];
+        for reply in replies {
+            assert!(matches!(parse_response::<bool>(reply.clone()), Err(RpcError::InvalidResponse(_))), "{}", reply);
+        }
+    }
+}
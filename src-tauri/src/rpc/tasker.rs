@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use crate::rpc::{Method, Protocol};
+
+pub struct TaskerProtocol;
+
+impl Protocol for TaskerProtocol {
+    const SERVICE: &'static str = "tasker";
+    const VERSION: Version = Version::new(1, 0, 0);
+    const CONNECTION_KEY: &'static str = "tasker";
+}
+
+//Watch main app and its helper apps, `safe` tells what happens to helpers when main app exits
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Observe {
+    pub main_app: String,
+    pub sub_apps: Vec<String>,
+    pub safe: String,
+}
+
+impl Method for Observe {
+    type Protocol = TaskerProtocol;
+    type Result = ();
+    const METHOD: &'static str = "observe";
+
+    fn validate(&self) -> Result<(), String> {
+        if self.main_app.is_empty() {
+            return Err("main_app is empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RunApp {
+    pub app: PathBuf,
+    pub args: Vec<String>,
+    //Show console window of the app
+    pub window: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct AppStarted {
+    pub pid: u32,
+}
+
+impl Method for RunApp {
+    type Protocol = TaskerProtocol;
+    type Result = AppStarted;
+    const METHOD: &'static str = "run_app";
+
+    fn validate(&self) -> Result<(), String> {
+        if self.app.as_os_str().is_empty() {
+            return Err("app is empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+//Pushed by task observer
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TaskerEvent {
+    AppExited { app: String, code: Option<i32> },
+}
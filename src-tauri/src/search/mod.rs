@@ -0,0 +1,193 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+use serde::Serialize;
+use tauri::{command, State};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::time::{timeout_at, Instant};
+use tracing::warn;
+use starship_plugin_api::search::{SearchItem, SearchQuery};
+use crate::config_manager::SharedConfig;
+use crate::meilisearch_runner::runner::SharedRunner;
+use crate::plugin_manager::{with_plugins, SharedPlugins};
+
+//Source id of the file index, plugin providers are `<plugin>/<provider>`
+pub const FILES_SOURCE: &str = "files";
+
+//Items of one source in the order source ranked them
+#[derive(Clone, Debug)]
+pub struct SourceResults {
+    pub source: String,
+    pub title: String,
+    pub items: Vec<SearchItem>,
+}
+
+//Merged result as shown by frontend, tagged with source it came from
+#[derive(Serialize, Clone, Debug)]
+pub struct SearchResult {
+    pub source: String,
+    pub source_title: String,
+    pub title: String,
+    pub detail: String,
+    pub path: Option<PathBuf>,
+    pub score: f64,
+}
+
+//Query file index and plugin search providers at the same time. Sources that didn't answer
+//before `deadline` are left out, plugin providers still running finish in background
+pub async fn search_sources(runner: &SharedRunner, plugins: &SharedPlugins, query: &str, limit: usize, deadline: Duration) -> Vec<SearchResult> {
+    let deadline = Instant::now() + deadline;
+    let (results_tx, mut results) = mpsc::unbounded_channel();
+    {
+        let runner = runner.clone();
+        let query = query.to_string();
+        let results_tx = results_tx.clone();
+        tokio::spawn(async move { search_files(&runner, &query, limit, &results_tx).await });
+    }
+    let query = SearchQuery { query: query.to_string(), limit };
+    let handles = plugins.lock().await.search_handles();
+    for handle in handles {
+        let plugins = plugins.clone();
+        let query = query.clone();
+        let results_tx = results_tx.clone();
+        tokio::spawn(async move {
+            let id = handle.id().to_string();
+            if let Ok(result) = tokio::task::spawn_blocking(move || handle.search(&query, &results_tx)).await {
+                with_plugins(&plugins, move |plugins| plugins.track_failures(&id, &result)).await;
+            }
+        });
+    }
+    drop(results_tx);
+    let mut sources = vec![];
+    while let Ok(Some(source)) = timeout_at(deadline, results.recv()).await {
+        sources.push(source);
+    }
+    merge(sources, limit)
+}
+
+async fn search_files(runner: &SharedRunner, query: &str, limit: usize, results: &UnboundedSender<SourceResults>) {
+    let items = match runner.lock().await.as_ref() {
+        Some(runner) => runner.search_items(query, limit).await.map_err(|e| e.to_string()),
+        None => Err("search engine is not running".to_string())
+    };
+    match items {
+        Ok(items) => {
+            let _ = results.send(SourceResults {
+                source: FILES_SOURCE.to_string(),
+                title: "Files".to_string(),
+                items,
+            });
+        }
+        Err(e) => warn!("File search failed: {}", e)
+    }
+}
+
+//Scores of one source scaled so its best item has 1, sources rank their items on different
+//scales and only order within a source is comparable. Items without score use their position
+fn normalized_scores(items: &[SearchItem]) -> Vec<f64> {
+    let scores: Vec<Option<f64>> = items.iter()
+        .map(|item| item.score.filter(|score| score.is_finite()).map(|score| score.max(0.0)))
+        .collect();
+    let best = scores.iter().flatten().fold(0.0, |best: f64, score| best.max(*score));
+    let count = items.len();
+    scores.into_iter().enumerate().map(|(position, score)| match score {
+        Some(score) if best > 0.0 => score / best,
+        Some(_) => 0.0,
+        None => 1.0 - position as f64 / count as f64,
+    }).collect()
+}
+
+//Rank items of all sources by score normalized per source, so best hit of every source is equal
+//and files win ties. Same path found by several sources is kept once with the best score
+pub fn merge(mut sources: Vec<SourceResults>, limit: usize) -> Vec<SearchResult> {
+    //Sources come in order they answered, files go first and plugins by id for equal scores
+    sources.sort_by(|a, b| (a.source != FILES_SOURCE).cmp(&(b.source != FILES_SOURCE)).then_with(|| a.source.cmp(&b.source)));
+    let mut results: Vec<SearchResult> = vec![];
+    let mut by_path: HashMap<PathBuf, usize> = HashMap::new();
+    for source in sources {
+        let scores = normalized_scores(&source.items);
+        for (item, score) in source.items.into_iter().zip(scores) {
+            let result = SearchResult {
+                source: source.source.clone(),
+                source_title: source.title.clone(),
+                title: item.title,
+                detail: item.detail,
+                path: item.path,
+                score,
+            };
+            match result.path.as_ref().and_then(|path| by_path.get(path)) {
+                Some(i) if results[*i].score >= score => {}
+                Some(i) => results[*i] = result,
+                None => {
+                    if let Some(path) = &result.path {
+                        by_path.insert(path.clone(), results.len());
+                    }
+                    results.push(result);
+                }
+            }
+        }
+    }
+    results.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap_or(Ordering::Equal));
+    results.truncate(limit);
+    results
+}
+
+#[command]
+pub async fn search(config: State<'_, SharedConfig>, runner: State<'_, SharedRunner>, plugins: State<'_, SharedPlugins>, query: String, limit: usize) -> Result<Vec<SearchResult>, ()> {
+    let deadline = Duration::from_millis(config.lock().await.app_conf().plugin_runtime.search_deadline_ms);
+    Ok(search_sources(&runner, &plugins, &query, limit, deadline).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(title: &str, path: Option<&str>, score: Option<f64>) -> SearchItem {
+        SearchItem {
+            title: title.to_string(),
+            detail: String::new(),
+            path: path.map(PathBuf::from),
+            score,
+        }
+    }
+
+    fn source(source: &str, items: Vec<SearchItem>) -> SourceResults {
+        SourceResults {
+            source: source.to_string(),
+            title: source.to_string(),
+            items,
+        }
+    }
+
+    fn titles(results: &[SearchResult]) -> Vec<&str> {
+        results.iter().map(|result| result.title.as_str()).collect()
+    }
+
+    #[test]
+    fn unscored_plugin_hit_does_not_outrank_files() {
+        let files = source(FILES_SOURCE, vec![item("a.txt", Some("/a.txt"), Some(0.8)), item("b.txt", Some("/b.txt"), Some(0.4))]);
+        let plugin = source("bookmarks/web", vec![item("site", None, None), item("other site", None, None)]);
+        let results = merge(vec![plugin, files], 10);
+        assert_eq!(titles(&results), ["a.txt", "site", "b.txt", "other site"]);
+        assert_eq!(results[0].score, 1.0);
+        assert_eq!(results[2].score, 0.5);
+    }
+
+    #[test]
+    fn same_path_is_kept_once_with_best_score() {
+        let files = source(FILES_SOURCE, vec![item("a.txt", Some("/a.txt"), Some(0.9)), item("b.txt", Some("/b.txt"), Some(0.3))]);
+        let plugin = source("recent/files", vec![item("b (recent)", Some("/b.txt"), Some(1.0))]);
+        let results = merge(vec![files, plugin], 10);
+        assert_eq!(titles(&results), ["a.txt", "b (recent)"]);
+        assert_eq!(results[1].source, "recent/files");
+    }
+
+    #[test]
+    fn invalid_scores_and_limit() {
+        let plugin = source("calc/eval", vec![item("negative", None, Some(-2.0)), item("best", None, Some(3.0)), item("nan", None, Some(f64::NAN))]);
+        let results = merge(vec![plugin], 2);
+        assert_eq!(titles(&results), ["best", "nan"]);
+        assert_eq!(results[0].score, 1.0);
+    }
+}
@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::sync::Arc;
+use argon2::Argon2;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng, Payload};
+use chacha20poly1305::aead::rand_core::RngCore;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+use starship_plugin_api::plugin_config::PluginConfig;
+
+pub type SharedSecrets = Arc<Mutex<Secrets>>;
+
+const KEYRING_SERVICE: &str = "space.traveler";
+const PASSPHRASE_ENV: &str = "SPACETRAVELER_SECRETS_PASSPHRASE";
+pub const MASTER_KEY_SECRET: &str = "search.master_key";
+
+//Storage for credentials, values never go to config file
+pub trait SecretStore: Send + Sync {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError>;
+    fn set(&mut self, name: &str, value: &str) -> Result<(), SecretError>;
+    fn delete(&mut self, name: &str) -> Result<(), SecretError>;
+    fn backend(&self) -> SecretBackend;
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+pub enum SecretBackend {
+    Keyring,
+    EncryptedFile,
+}
+
+//System keyring (Secret Service, Windows Credential Manager, macOS Keychain)
+pub struct KeyringStore;
+
+impl KeyringStore {
+    //Keyring counts as available when lookup of missing entry fails only with `NoEntry`
+    pub fn open() -> Option<Self> {
+        let entry = keyring::Entry::new(KEYRING_SERVICE, "probe").ok()?;
+        match entry.get_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Some(KeyringStore),
+            Err(e) => {
+                warn!("Keyring is not available: {}", e);
+                None
+            }
+        }
+    }
+
+    fn entry(name: &str) -> Result<keyring::Entry, SecretError> {
+        keyring::Entry::new(KEYRING_SERVICE, name).map_err(|e| SecretError::Backend(e.to_string()))
+    }
+}
+
+impl SecretStore for KeyringStore {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        match Self::entry(name)?.get_password() {
+            Ok(value) => Ok(Some(value)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(e) => Err(SecretError::Backend(e.to_string()))
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), SecretError> {
+        Self::entry(name)?.set_password(value).map_err(|e| SecretError::Backend(e.to_string()))
+    }
+
+    fn delete(&mut self, name: &str) -> Result<(), SecretError> {
+        match Self::entry(name)?.delete_password() {
+            Ok(_) | Err(keyring::Error::NoEntry) => Ok(()),
+            Err(e) => Err(SecretError::Backend(e.to_string()))
+        }
+    }
+
+    fn backend(&self) -> SecretBackend {
+        SecretBackend::Keyring
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct SecretsFile {
+    salt: String,
+    check: EncryptedValue,
+    entries: BTreeMap<String, EncryptedValue>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct EncryptedValue {
+    nonce: String,
+    data: String,
+}
+
+//Fallback when keyring is missing, every value encrypted with key derived from passphrase
+pub struct EncryptedFileStore {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+    file: SecretsFile,
+}
+
+impl EncryptedFileStore {
+    const CHECK_VALUE: &'static str = "space.traveler";
+    //Associated data of check value, entries use their names
+    const CHECK_NAME: &'static str = "";
+
+    pub fn open(path: PathBuf, passphrase: &str) -> Result<Self, SecretError> {
+        if path.exists() {
+            let con = std::fs::read_to_string(&path).map_err(|e| SecretError::Io(e.to_string()))?;
+            let file: SecretsFile = toml::from_str(&con).map_err(|e| SecretError::Io(e.to_string()))?;
+            let salt = hex::decode(&file.salt).map_err(|e| SecretError::Crypto(e.to_string()))?;
+            let cipher = Self::cipher(passphrase, &salt)?;
+            let store = Self { path, cipher, file };
+            //Wrong passphrase fails to decrypt known value
+            if store.decrypt(Self::CHECK_NAME, &store.file.check).ok().as_deref() != Some(Self::CHECK_VALUE) {
+                return Err(SecretError::WrongPassphrase);
+            }
+            Ok(store)
+        } else {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            let cipher = Self::cipher(passphrase, &salt)?;
+            let mut store = Self {
+                path,
+                cipher,
+                file: SecretsFile {
+                    salt: hex::encode(salt),
+                    ..SecretsFile::default()
+                },
+            };
+            store.file.check = store.encrypt(Self::CHECK_NAME, Self::CHECK_VALUE)?;
+            store.save()?;
+            Ok(store)
+        }
+    }
+
+    fn cipher(passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, SecretError> {
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| SecretError::Crypto(e.to_string()))?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    //Name is bound as associated data, value moved to another entry fails to decrypt
+    fn encrypt(&self, name: &str, value: &str) -> Result<EncryptedValue, SecretError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let data = self.cipher.encrypt(&nonce, Payload { msg: value.as_bytes(), aad: name.as_bytes() }).map_err(|e| SecretError::Crypto(e.to_string()))?;
+        Ok(EncryptedValue {
+            nonce: hex::encode(nonce),
+            data: hex::encode(data),
+        })
+    }
+
+    fn decrypt(&self, name: &str, value: &EncryptedValue) -> Result<String, SecretError> {
+        let nonce = hex::decode(&value.nonce).map_err(|e| SecretError::Crypto(e.to_string()))?;
+        let data = hex::decode(&value.data).map_err(|e| SecretError::Crypto(e.to_string()))?;
+        if nonce.len() != 12 {
+            return Err(SecretError::Crypto("invalid nonce".to_string()));
+        }
+        let plain = self.cipher.decrypt(Nonce::from_slice(&nonce), Payload { msg: &data, aad: name.as_bytes() }).map_err(|e| SecretError::Crypto(e.to_string()))?;
+        String::from_utf8(plain).map_err(|e| SecretError::Crypto(e.to_string()))
+    }
+
+    fn save(&self) -> Result<(), SecretError> {
+        let con = toml::to_string(&self.file).map_err(|e| SecretError::Io(e.to_string()))?;
+        std::fs::write(&self.path, con).map_err(|e| SecretError::Io(e.to_string()))
+    }
+}
+
+impl SecretStore for EncryptedFileStore {
+    fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        match self.file.entries.get(name) {
+            Some(value) => Ok(Some(self.decrypt(name, value)?)),
+            None => Ok(None)
+        }
+    }
+
+    fn set(&mut self, name: &str, value: &str) -> Result<(), SecretError> {
+        let value = self.encrypt(name, value)?;
+        self.file.entries.insert(name.to_string(), value);
+        self.save()
+    }
+
+    fn delete(&mut self, name: &str) -> Result<(), SecretError> {
+        if self.file.entries.remove(name).is_some() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn backend(&self) -> SecretBackend {
+        SecretBackend::EncryptedFile
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct SecretsStatus {
+    backend: Option<SecretBackend>,
+    unlocked: bool,
+}
+
+//Picks keyring when it works, otherwise waits for passphrase to open encrypted file
+pub struct Secrets {
+    store: Option<Box<dyn SecretStore>>,
+    file_path: PathBuf,
+}
+
+impl Secrets {
+    pub fn init(file_path: PathBuf) -> Self {
+        let mut secrets = Self {
+            store: None,
+            file_path,
+        };
+        if let Some(store) = KeyringStore::open() {
+            info!("Secrets stored in system keyring");
+            secrets.store = Some(Box::new(store));
+        } else if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+            if let Err(e) = secrets.unlock(&passphrase) {
+                warn!("Failed to unlock secrets file: {}", e);
+            }
+        }
+        secrets
+    }
+
+    pub fn unlock(&mut self, passphrase: &str) -> Result<(), SecretError> {
+        if self.store.is_some() {
+            return Ok(());
+        }
+        let store = EncryptedFileStore::open(self.file_path.clone(), passphrase)?;
+        info!("Secrets stored in encrypted file {}", self.file_path.display());
+        self.store = Some(Box::new(store));
+        Ok(())
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.store.is_some()
+    }
+
+    pub fn status(&self) -> SecretsStatus {
+        SecretsStatus {
+            backend: self.store.as_ref().map(|store| store.backend()),
+            unlocked: self.store.is_some(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<String>, SecretError> {
+        self.store.as_ref().ok_or(SecretError::Locked)?.get(name)
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), SecretError> {
+        self.store.as_mut().ok_or(SecretError::Locked)?.set(name, value)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), SecretError> {
+        self.store.as_mut().ok_or(SecretError::Locked)?.delete(name)
+    }
+
+    pub fn plugin_secret_name(plugin: &str, name: &str) -> String {
+        format!("plugin.{}.{}", plugin, name)
+    }
+
+    //Fill secrets requested by plugin config, plugins only see their own namespace
+    pub fn resolve_plugin_config(&self, plugin: &str, config: &mut PluginConfig) -> Result<(), SecretError> {
+        for name in config.secrets.clone() {
+            if let Some(value) = self.get(&Self::plugin_secret_name(plugin, &name))? {
+                config.set_secret(&name, &value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    Locked,
+    WrongPassphrase,
+    Backend(String),
+    Crypto(String),
+    Io(String),
+}
+
+impl Display for SecretError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SecretError::Locked => write!(f, "Secrets are locked, enter passphrase to unlock"),
+            SecretError::WrongPassphrase => write!(f, "Wrong secrets passphrase"),
+            SecretError::Backend(e) => write!(f, "Keyring error: {}", e),
+            SecretError::Crypto(e) => write!(f, "Secrets encryption error: {}", e),
+            SecretError::Io(e) => write!(f, "Secrets file error: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn value_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".secrets.toml");
+        EncryptedFileStore::open(path.clone(), "passphrase").unwrap().set("token", "secret").unwrap();
+        let store = EncryptedFileStore::open(path, "passphrase").unwrap();
+        assert_eq!(store.get("token").unwrap().as_deref(), Some("secret"));
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".secrets.toml");
+        EncryptedFileStore::open(path.clone(), "passphrase").unwrap();
+        assert!(matches!(EncryptedFileStore::open(path, "other"), Err(SecretError::WrongPassphrase)));
+    }
+
+    #[test]
+    fn missing_entry_is_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = EncryptedFileStore::open(dir.path().join(".secrets.toml"), "passphrase").unwrap();
+        assert_eq!(store.get("token").unwrap(), None);
+        store.set("token", "secret").unwrap();
+        store.delete("token").unwrap();
+        assert_eq!(store.get("token").unwrap(), None);
+    }
+
+    #[test]
+    fn swapped_values_fail_to_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = EncryptedFileStore::open(dir.path().join(".secrets.toml"), "passphrase").unwrap();
+        store.set("token", "secret").unwrap();
+        store.set("other", "public").unwrap();
+        let token = store.file.entries.remove("token").unwrap();
+        let other = store.file.entries.insert("other".to_string(), token).unwrap();
+        store.file.entries.insert("token".to_string(), other);
+        assert!(matches!(store.get("token"), Err(SecretError::Crypto(_))));
+        assert!(matches!(store.get("other"), Err(SecretError::Crypto(_))));
+    }
+}
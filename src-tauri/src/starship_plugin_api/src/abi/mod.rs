@@ -0,0 +1,333 @@
+use std::any::Any;
+use std::ffi::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::Arc;
+use crate::api::{PluginError, PluginResult, StarShipPluginAPI};
+use crate::context::{HostCall, HostContext, HostEvent};
+use crate::plugin_config::PluginConfig;
+
+//Version of the C interface below. Bump on any change of `PluginVTable` layout or call semantics
+pub const API_VERSION: u32 = 5;
+//Oldest plugin interface the host still can load
+pub const MIN_SUPPORTED_API_VERSION: u32 = 5;
+
+pub const API_VERSION_SYMBOL: &[u8] = b"STARSHIP_PLUGIN_API_VERSION";
+pub const EXPORT_SYMBOL: &[u8] = b"starship_plugin_export";
+//Symbol of plugins built before the vtable interface existed
+pub const LEGACY_EXPORT_SYMBOL: &[u8] = b"export_plugin";
+
+//Borrowed utf-8 string, valid while the plugin instance lives
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct FfiStr {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl FfiStr {
+    pub fn new(value: &str) -> Self {
+        Self {
+            ptr: value.as_ptr(),
+            len: value.len(),
+        }
+    }
+
+    /// # Safety
+    /// `ptr` and `len` must point to valid utf-8 that outlives returned str
+    pub unsafe fn as_str<'a>(&self) -> &'a str {
+        std::str::from_utf8_unchecked(std::slice::from_raw_parts(self.ptr, self.len))
+    }
+}
+
+//Result of a hook, error message is owned by plugin instance and valid until its next call
+#[repr(C)]
+pub struct FfiResult {
+    pub ok: bool,
+    pub error: FfiStr,
+}
+
+impl FfiResult {
+    /// # Safety
+    /// Must be called before next call to the instance that returned it
+    pub unsafe fn into_result(self) -> PluginResult {
+        if self.ok {
+            Ok(())
+        } else {
+            Err(PluginError::new(self.error.as_str()))
+        }
+    }
+}
+
+//Receives host response into memory owned by plugin, so no allocation crosses library boundary
+pub type FfiWrite = unsafe extern "C" fn(out: *mut c_void, data: FfiStr);
+
+//Host context borrowed for the duration of `on_load`, plugin copies what it needs.
+//`host` stays valid until plugin instance is dropped
+#[repr(C)]
+pub struct FfiHostContext {
+    pub plugin_id: FfiStr,
+    pub host_version: FfiStr,
+    pub plugin_dir: FfiStr,
+    pub host: *const c_void,
+    pub call: unsafe extern "C" fn(host: *const c_void, request: FfiStr, out: *mut c_void, write: FfiWrite),
+}
+
+//Host side of `FfiHostContext::call`, `host` points to `Arc<dyn HostCall>` kept by `FfiPlugin`
+unsafe extern "C" fn host_call(host: *const c_void, request: FfiStr, out: *mut c_void, write: FfiWrite) {
+    let host = &*(host as *const Arc<dyn HostCall>);
+    let response = host.call(request.as_str());
+    write(out, FfiStr::new(&response));
+}
+
+unsafe extern "C" fn write_string(out: *mut c_void, data: FfiStr) {
+    *(out as *mut String) = data.as_str().to_string();
+}
+
+//Plugin side of host calls
+struct FfiHostCall {
+    host: *const c_void,
+    call: unsafe extern "C" fn(host: *const c_void, request: FfiStr, out: *mut c_void, write: FfiWrite),
+}
+
+//Host end is `HostCall`, which is `Send + Sync`
+unsafe impl Send for FfiHostCall {}
+unsafe impl Sync for FfiHostCall {}
+
+impl HostCall for FfiHostCall {
+    fn call(&self, request: &str) -> String {
+        let mut response = String::new();
+        unsafe { (self.call)(self.host, FfiStr::new(request), &mut response as *mut String as *mut c_void, write_string) };
+        response
+    }
+}
+
+//Only C types cross library boundary, so host and plugin may be built by different compilers.
+//Plugin trait object never leaves plugin library, host calls it through these functions
+#[repr(C)]
+pub struct PluginVTable {
+    pub api_version: u32,
+    pub instance: *mut c_void,
+    pub name: unsafe extern "C" fn(instance: *const c_void) -> FfiStr,
+    pub execute: unsafe extern "C" fn(instance: *mut c_void) -> FfiResult,
+    pub on_load: unsafe extern "C" fn(instance: *mut c_void, host: *const FfiHostContext) -> FfiResult,
+    pub on_enable: unsafe extern "C" fn(instance: *mut c_void) -> FfiResult,
+    pub on_disable: unsafe extern "C" fn(instance: *mut c_void) -> FfiResult,
+    //Config is passed as json, see `PluginConfig::to_transfer`
+    pub on_config_changed: unsafe extern "C" fn(instance: *mut c_void, config: FfiStr) -> FfiResult,
+    //Event is passed as json `HostEvent`
+    pub on_event: unsafe extern "C" fn(instance: *mut c_void, event: FfiStr) -> FfiResult,
+    //Arguments are json, result json is given to `write` on success
+    pub on_action: unsafe extern "C" fn(instance: *mut c_void, action: FfiStr, args: FfiStr, out: *mut c_void, write: FfiWrite) -> FfiResult,
+    pub on_unload: unsafe extern "C" fn(instance: *mut c_void) -> FfiResult,
+    pub drop: unsafe extern "C" fn(instance: *mut c_void),
+}
+
+//Plugin with the last error message, so message outlives the hook call
+struct PluginInstance {
+    plugin: Box<dyn StarShipPluginAPI>,
+    last_error: String,
+}
+
+impl PluginVTable {
+    //Called inside plugin library by `export_plugin!`
+    pub fn new<P: StarShipPluginAPI + 'static>(plugin: P) -> Self {
+        let instance = Box::new(PluginInstance {
+            plugin: Box::new(plugin),
+            last_error: String::new(),
+        });
+        Self {
+            api_version: API_VERSION,
+            instance: Box::into_raw(instance) as *mut c_void,
+            name: plugin_name,
+            execute: plugin_execute,
+            on_load: plugin_on_load,
+            on_enable: plugin_on_enable,
+            on_disable: plugin_on_disable,
+            on_config_changed: plugin_on_config_changed,
+            on_event: plugin_on_event,
+            on_action: plugin_on_action,
+            on_unload: plugin_on_unload,
+            drop: plugin_drop,
+        }
+    }
+}
+
+unsafe fn plugin_mut<'a>(instance: *mut c_void) -> &'a mut PluginInstance {
+    &mut *(instance as *mut PluginInstance)
+}
+
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => panic.downcast_ref::<String>().cloned().unwrap_or_else(|| "unknown panic".to_string())
+    }
+}
+
+fn ffi_result(instance: &mut PluginInstance, result: PluginResult) -> FfiResult {
+    match result {
+        Ok(_) => FfiResult {
+            ok: true,
+            error: FfiStr::new(""),
+        },
+        Err(e) => {
+            instance.last_error = e.message;
+            FfiResult {
+                ok: false,
+                error: FfiStr::new(&instance.last_error),
+            }
+        }
+    }
+}
+
+//Unwinding out of `extern "C"` function aborts the host, so every call into plugin code
+//is guarded and panic becomes hook error. Plugins built with `panic = "abort"` still abort
+unsafe fn guarded(instance: *mut c_void, call: impl FnOnce(&mut dyn StarShipPluginAPI) -> PluginResult) -> FfiResult {
+    let instance = plugin_mut(instance);
+    let result = catch_unwind(AssertUnwindSafe(|| call(instance.plugin.as_mut())))
+        .unwrap_or_else(|panic| Err(PluginError::new(format!("plugin panicked: {}", panic_message(panic.as_ref())))));
+    ffi_result(instance, result)
+}
+
+unsafe extern "C" fn plugin_name(instance: *const c_void) -> FfiStr {
+    let instance = &*(instance as *const PluginInstance);
+    match catch_unwind(AssertUnwindSafe(|| instance.plugin.name())) {
+        Ok(name) => FfiStr::new(name),
+        Err(_) => FfiStr::new("")
+    }
+}
+
+unsafe extern "C" fn plugin_execute(instance: *mut c_void) -> FfiResult {
+    guarded(instance, |plugin| plugin.execute())
+}
+
+unsafe extern "C" fn plugin_on_load(instance: *mut c_void, host: *const FfiHostContext) -> FfiResult {
+    let host = &*host;
+    let call = Arc::new(FfiHostCall {
+        host: host.host,
+        call: host.call,
+    });
+    let context = HostContext::new(host.plugin_id.as_str(), host.host_version.as_str(), Path::new(host.plugin_dir.as_str()), call);
+    guarded(instance, |plugin| plugin.on_load(context))
+}
+
+unsafe extern "C" fn plugin_on_enable(instance: *mut c_void) -> FfiResult {
+    guarded(instance, |plugin| plugin.on_enable())
+}
+
+unsafe extern "C" fn plugin_on_disable(instance: *mut c_void) -> FfiResult {
+    guarded(instance, |plugin| plugin.on_disable())
+}
+
+unsafe extern "C" fn plugin_on_config_changed(instance: *mut c_void, config: FfiStr) -> FfiResult {
+    guarded(instance, |plugin| {
+        let config = PluginConfig::from_transfer(config.as_str())
+            .map_err(|e| PluginError::new(format!("invalid config: {}", e)))?;
+        plugin.on_config_changed(&config)
+    })
+}
+
+unsafe extern "C" fn plugin_on_event(instance: *mut c_void, event: FfiStr) -> FfiResult {
+    guarded(instance, |plugin| {
+        let event = serde_json::from_str::<HostEvent>(event.as_str())
+            .map_err(|e| PluginError::new(format!("invalid event: {}", e)))?;
+        plugin.on_event(&event)
+    })
+}
+
+unsafe extern "C" fn plugin_on_action(instance: *mut c_void, action: FfiStr, args: FfiStr, out: *mut c_void, write: FfiWrite) -> FfiResult {
+    guarded(instance, |plugin| {
+        let args = serde_json::from_str::<serde_json::Value>(args.as_str())
+            .map_err(|e| PluginError::new(format!("invalid action arguments: {}", e)))?;
+        let value = plugin.on_action(action.as_str(), &args)?;
+        let value = serde_json::to_string(&value).map_err(|e| PluginError::new(e.to_string()))?;
+        write(out, FfiStr::new(&value));
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn plugin_on_unload(instance: *mut c_void) -> FfiResult {
+    guarded(instance, |plugin| plugin.on_unload())
+}
+
+unsafe extern "C" fn plugin_drop(instance: *mut c_void) {
+    let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(instance as *mut PluginInstance))));
+}
+
+//Host side of the vtable, behaves like plugin implemented in host itself
+pub struct FfiPlugin {
+    vtable: PluginVTable,
+    //Boxed so pointer given to plugin in `on_load` doesn't move
+    host: Option<Box<Arc<dyn HostCall>>>,
+}
+
+//Plugin trait requires `Send`, instance behind the vtable is a plugin type
+unsafe impl Send for FfiPlugin {}
+
+impl FfiPlugin {
+    /// # Safety
+    /// `vtable` must come from `PluginVTable::new` of a library that stays loaded while `FfiPlugin` lives
+    pub unsafe fn from_vtable(vtable: PluginVTable) -> Self {
+        Self { vtable, host: None }
+    }
+}
+
+impl StarShipPluginAPI for FfiPlugin {
+    fn name(&self) -> &str {
+        unsafe { (self.vtable.name)(self.vtable.instance).as_str() }
+    }
+
+    fn execute(&self) -> PluginResult {
+        unsafe { (self.vtable.execute)(self.vtable.instance).into_result() }
+    }
+
+    fn on_load(&mut self, host: HostContext) -> PluginResult {
+        let plugin_dir = host.plugin_dir().to_string_lossy();
+        let call: &Arc<dyn HostCall> = self.host.insert(Box::new(host.host().clone()));
+        let context = FfiHostContext {
+            plugin_id: FfiStr::new(host.plugin_id()),
+            host_version: FfiStr::new(host.host_version()),
+            plugin_dir: FfiStr::new(&plugin_dir),
+            host: call as *const Arc<dyn HostCall> as *const c_void,
+            call: host_call,
+        };
+        unsafe { (self.vtable.on_load)(self.vtable.instance, &context).into_result() }
+    }
+
+    fn on_enable(&mut self) -> PluginResult {
+        unsafe { (self.vtable.on_enable)(self.vtable.instance).into_result() }
+    }
+
+    fn on_disable(&mut self) -> PluginResult {
+        unsafe { (self.vtable.on_disable)(self.vtable.instance).into_result() }
+    }
+
+    fn on_config_changed(&mut self, config: &PluginConfig) -> PluginResult {
+        let config = config.to_transfer();
+        unsafe { (self.vtable.on_config_changed)(self.vtable.instance, FfiStr::new(&config)).into_result() }
+    }
+
+    fn on_event(&mut self, event: &HostEvent) -> PluginResult {
+        let event = serde_json::to_string(event).map_err(|e| PluginError::new(e.to_string()))?;
+        unsafe { (self.vtable.on_event)(self.vtable.instance, FfiStr::new(&event)).into_result() }
+    }
+
+    fn on_action(&mut self, action: &str, args: &serde_json::Value) -> PluginResult<serde_json::Value> {
+        let args = serde_json::to_string(args).map_err(|e| PluginError::new(e.to_string()))?;
+        let mut value = String::new();
+        unsafe {
+            (self.vtable.on_action)(self.vtable.instance, FfiStr::new(action), FfiStr::new(&args), &mut value as *mut String as *mut c_void, write_string).into_result()?;
+        }
+        serde_json::from_str(&value).map_err(|e| PluginError::new(format!("invalid action result: {}", e)))
+    }
+
+    fn on_unload(&mut self) -> PluginResult {
+        unsafe { (self.vtable.on_unload)(self.vtable.instance).into_result() }
+    }
+}
+
+impl Drop for FfiPlugin {
+    fn drop(&mut self) {
+        unsafe { (self.vtable.drop)(self.vtable.instance) }
+    }
+}
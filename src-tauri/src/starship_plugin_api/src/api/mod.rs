@@ -1,14 +1,89 @@
-pub trait StarShipPluginAPI {
-    fn name(&self) -> &'static str;
-    fn execute(&self);
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::context::{HostContext, HostEvent};
+use crate::plugin_config::PluginConfig;
+
+//Error returned by plugin hooks, only message crosses library boundary
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct PluginError {
+    pub message: String,
+}
+
+impl PluginError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self { message: message.into() }
+    }
+}
+
+impl Display for PluginError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl From<String> for PluginError {
+    fn from(message: String) -> Self {
+        Self::new(message)
+    }
+}
+
+impl From<&str> for PluginError {
+    fn from(message: &str) -> Self {
+        Self::new(message)
+    }
+}
+
+pub type PluginResult<T = ()> = Result<T, PluginError>;
+
+//Host calls hooks in order: `on_load`, `on_enable`, then any number of `on_disable`/`on_enable`
+//and `on_config_changed`, and `on_unload` right before instance is dropped.
+//Instance stays alive while plugin is disabled, so its state survives re-enabling
+pub trait StarShipPluginAPI: Send {
+    fn name(&self) -> &str;
+    fn execute(&self) -> PluginResult;
+
+    //Context is valid until `on_unload`, keep it to call host later
+    fn on_load(&mut self, _host: HostContext) -> PluginResult {
+        Ok(())
+    }
+
+    fn on_enable(&mut self) -> PluginResult {
+        Ok(())
+    }
+
+    fn on_disable(&mut self) -> PluginResult {
+        Ok(())
+    }
+
+    fn on_config_changed(&mut self, _config: &PluginConfig) -> PluginResult {
+        Ok(())
+    }
+
+    //Called only for event kinds plugin subscribed to with `HostContext::subscribe`
+    fn on_event(&mut self, _event: &HostEvent) -> PluginResult {
+        Ok(())
+    }
+
+    //Runs action declared in `[ui]` section of manifest, result is returned to frontend
+    fn on_action(&mut self, action: &str, _args: &serde_json::Value) -> PluginResult<serde_json::Value> {
+        Err(PluginError::new(format!("unknown action `{}`", action)))
+    }
+
+    fn on_unload(&mut self) -> PluginResult {
+        Ok(())
+    }
 }
 
+//Exports plugin type with C interface and version of the API it was built with
 #[macro_export]
 macro_rules! export_plugin {
     ($plugin_type:ty) => {
         #[no_mangle]
-        pub extern "C" fn export_plugin() -> Box<dyn $crate::StarShipPluginAPI> {
-            Box::new($plugin_type)
+        pub static STARSHIP_PLUGIN_API_VERSION: u32 = $crate::abi::API_VERSION;
+
+        #[no_mangle]
+        pub extern "C" fn starship_plugin_export() -> $crate::abi::PluginVTable {
+            $crate::abi::PluginVTable::new(<$plugin_type>::default())
         }
     };
-}
\ No newline at end of file
+}
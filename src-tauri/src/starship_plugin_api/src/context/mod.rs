@@ -0,0 +1,276 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::api::{PluginError, PluginResult};
+use crate::manifest::Capability;
+
+//Transport of host calls, request and response are json encoded `HostRequest` and `HostResponse`
+pub trait HostCall: Send + Sync {
+    fn call(&self, request: &str) -> String;
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SearchHit {
+    pub path: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    //RFC 3339, missing when platform doesn't report it
+    pub modified: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Fs,
+    App,
+}
+
+//Events plugins can subscribe to, filesystem events come from operations done through the app
+//and from changes made by others under index roots of active profile
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum HostEvent {
+    FileCreated { path: PathBuf },
+    FileModified { path: PathBuf },
+    FileRemoved { path: PathBuf },
+    FileMoved { from: PathBuf, to: PathBuf },
+    ProfileChanged { name: String },
+    PluginEnabled { id: String },
+    PluginDisabled { id: String },
+}
+
+impl HostEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            HostEvent::FileCreated { .. }
+            | HostEvent::FileModified { .. }
+            | HostEvent::FileRemoved { .. }
+            | HostEvent::FileMoved { .. } => EventKind::Fs,
+            HostEvent::ProfileChanged { .. }
+            | HostEvent::PluginEnabled { .. }
+            | HostEvent::PluginDisabled { .. } => EventKind::App,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationLevel {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Notification {
+    pub title: String,
+    pub body: String,
+    pub level: NotificationLevel,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "method", content = "params", rename_all = "snake_case")]
+pub enum HostRequest {
+    Search { query: String, limit: usize },
+    ListDir { path: PathBuf },
+    ReadFile { path: PathBuf },
+    WriteFile { path: PathBuf, data: Vec<u8> },
+    Copy { from: PathBuf, to: PathBuf },
+    Move { from: PathBuf, to: PathBuf },
+    Delete { path: PathBuf },
+    CreateDir { path: PathBuf },
+    Subscribe { events: Vec<EventKind> },
+    Log { level: LogLevel, message: String },
+    Notify { notification: Notification },
+}
+
+impl HostRequest {
+    //Method name as sent by plugin, safe to log unlike the params
+    pub fn method(&self) -> &'static str {
+        match self {
+            HostRequest::Search { .. } => "search",
+            HostRequest::ListDir { .. } => "list_dir",
+            HostRequest::ReadFile { .. } => "read_file",
+            HostRequest::WriteFile { .. } => "write_file",
+            HostRequest::Copy { .. } => "copy",
+            HostRequest::Move { .. } => "move",
+            HostRequest::Delete { .. } => "delete",
+            HostRequest::CreateDir { .. } => "create_dir",
+            HostRequest::Subscribe { .. } => "subscribe",
+            HostRequest::Log { .. } => "log",
+            HostRequest::Notify { .. } => "notify",
+        }
+    }
+
+    //Capability plugin must declare in manifest to make this request
+    pub fn capability(&self) -> Option<Capability> {
+        match self {
+            HostRequest::Search { .. } => Some(Capability::Search),
+            HostRequest::ListDir { .. } | HostRequest::ReadFile { .. } => Some(Capability::FsRead),
+            HostRequest::WriteFile { .. }
+            | HostRequest::Copy { .. }
+            | HostRequest::Move { .. }
+            | HostRequest::Delete { .. }
+            | HostRequest::CreateDir { .. } => Some(Capability::FsWrite),
+            HostRequest::Subscribe { .. } => Some(Capability::Events),
+            HostRequest::Notify { .. } => Some(Capability::Notifications),
+            HostRequest::Log { .. } => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum HostError {
+    PermissionDenied(Capability),
+    InvalidRequest(String),
+    Failed(String),
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostError::PermissionDenied(capability) => write!(f, "capability {:?} is not declared in plugin manifest", capability),
+            HostError::InvalidRequest(e) => write!(f, "invalid host request: {}", e),
+            HostError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+pub type HostResponse = Result<serde_json::Value, HostError>;
+
+//Response for request that never reached the host, e.g. when plugin calls it before `on_load`
+#[cfg(feature = "host")]
+pub(crate) fn failed_response(message: &str) -> String {
+    let response: HostResponse = Err(HostError::Failed(message.to_string()));
+    serde_json::to_string(&response).unwrap_or_default()
+}
+
+//What plugin gets from host on load, cheap to clone and safe to use from any thread
+#[derive(Clone)]
+pub struct HostContext {
+    plugin_id: String,
+    host_version: String,
+    plugin_dir: PathBuf,
+    host: Arc<dyn HostCall>,
+}
+
+impl HostContext {
+    pub fn new(plugin_id: &str, host_version: &str, plugin_dir: &Path, host: Arc<dyn HostCall>) -> Self {
+        Self {
+            plugin_id: plugin_id.to_string(),
+            host_version: host_version.to_string(),
+            plugin_dir: plugin_dir.to_path_buf(),
+            host,
+        }
+    }
+
+    //Id from plugin manifest
+    pub fn plugin_id(&self) -> &str {
+        &self.plugin_id
+    }
+
+    pub fn host_version(&self) -> &str {
+        &self.host_version
+    }
+
+    //Directory with plugin library and manifest
+    pub fn plugin_dir(&self) -> &Path {
+        &self.plugin_dir
+    }
+
+    pub(crate) fn host(&self) -> &Arc<dyn HostCall> {
+        &self.host
+    }
+
+    pub fn request<T: DeserializeOwned>(&self, request: &HostRequest) -> PluginResult<T> {
+        let request = serde_json::to_string(request).map_err(|e| PluginError::new(e.to_string()))?;
+        let response = self.host.call(&request);
+        let response: Result<T, HostError> = serde_json::from_str(&response)
+            .map_err(|e| PluginError::new(format!("invalid host response: {}", e)))?;
+        response.map_err(|e| PluginError::new(e.to_string()))
+    }
+
+    pub fn search(&self, query: &str, limit: usize) -> PluginResult<Vec<SearchHit>> {
+        self.request(&HostRequest::Search { query: query.to_string(), limit })
+    }
+
+    pub fn list_dir(&self, path: &Path) -> PluginResult<Vec<FileEntry>> {
+        self.request(&HostRequest::ListDir { path: path.to_path_buf() })
+    }
+
+    pub fn read_file(&self, path: &Path) -> PluginResult<Vec<u8>> {
+        self.request(&HostRequest::ReadFile { path: path.to_path_buf() })
+    }
+
+    pub fn write_file(&self, path: &Path, data: &[u8]) -> PluginResult {
+        self.request(&HostRequest::WriteFile { path: path.to_path_buf(), data: data.to_vec() })
+    }
+
+    pub fn copy(&self, from: &Path, to: &Path) -> PluginResult {
+        self.request(&HostRequest::Copy { from: from.to_path_buf(), to: to.to_path_buf() })
+    }
+
+    pub fn rename(&self, from: &Path, to: &Path) -> PluginResult {
+        self.request(&HostRequest::Move { from: from.to_path_buf(), to: to.to_path_buf() })
+    }
+
+    pub fn delete(&self, path: &Path) -> PluginResult {
+        self.request(&HostRequest::Delete { path: path.to_path_buf() })
+    }
+
+    //Fails when `path` exists or its parent doesn't
+    pub fn create_dir(&self, path: &Path) -> PluginResult {
+        self.request(&HostRequest::CreateDir { path: path.to_path_buf() })
+    }
+
+    //Subscribed events are delivered to `StarShipPluginAPI::on_event`
+    pub fn subscribe(&self, events: &[EventKind]) -> PluginResult {
+        self.request(&HostRequest::Subscribe { events: events.to_vec() })
+    }
+
+    //Written by host `tracing` subscriber inside span of this plugin
+    pub fn log(&self, level: LogLevel, message: &str) {
+        let _ = self.request::<()>(&HostRequest::Log { level, message: message.to_string() });
+    }
+
+    pub fn info(&self, message: &str) {
+        self.log(LogLevel::Info, message)
+    }
+
+    pub fn warn(&self, message: &str) {
+        self.log(LogLevel::Warn, message)
+    }
+
+    pub fn error(&self, message: &str) {
+        self.log(LogLevel::Error, message)
+    }
+
+    pub fn notify(&self, title: &str, body: &str, level: NotificationLevel) -> PluginResult {
+        self.request(&HostRequest::Notify {
+            notification: Notification {
+                title: title.to_string(),
+                body: body.to_string(),
+                level,
+            },
+        })
+    }
+}
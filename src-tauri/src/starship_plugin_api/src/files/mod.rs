@@ -0,0 +1,88 @@
+use std::path::{Path, PathBuf};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use crate::api::{PluginError, PluginResult};
+
+//Previewer or metadata extractor for some file types. App calls `action` with `FileRequest`,
+//previewer returns `Preview`, extractor returns JSON object that is indexed with the file
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FileHandlerSchema {
+    //`type/subtype` or `type/*`
+    #[serde(default)]
+    pub mime_types: Vec<String>,
+    //For formats app doesn't know MIME type of, without dot
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    pub action: String,
+}
+
+impl FileHandlerSchema {
+    //How well handler fits the file: 2 for exact type or extension, 1 for `type/*`, 0 doesn't handle it
+    pub fn matches(&self, path: &Path, mime: &str) -> u8 {
+        let extension = path.extension().map(|ext| ext.to_string_lossy().to_string());
+        let by_extension = extension.is_some_and(|ext| self.extensions.iter().any(|other| other.eq_ignore_ascii_case(&ext)));
+        if by_extension || self.mime_types.iter().any(|other| other.eq_ignore_ascii_case(mime)) {
+            return 2;
+        }
+        let top_level = mime.split('/').next().unwrap_or_default();
+        let by_type = self.mime_types.iter()
+            .filter_map(|other| other.strip_suffix("/*"))
+            .any(|other| other.eq_ignore_ascii_case(top_level));
+        if by_type {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if self.mime_types.is_empty() && self.extensions.is_empty() {
+            return Err(format!("handler `{}` needs `mime_types` or `extensions`", self.action));
+        }
+        for mime in &self.mime_types {
+            let valid = mime.split_once('/').is_some_and(|(top, sub)| !top.is_empty() && !sub.is_empty() && top != "*");
+            if !valid {
+                return Err(format!("invalid MIME type `{}`", mime));
+            }
+        }
+        Ok(())
+    }
+}
+
+//Arguments of previewer and extractor actions
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct FileRequest {
+    pub path: PathBuf,
+    pub mime: String,
+}
+
+impl FileRequest {
+    pub fn parse(args: &serde_json::Value) -> PluginResult<Self> {
+        serde_json::from_value(args.clone()).map_err(|e| PluginError::new(format!("invalid file request: {}", e)))
+    }
+}
+
+//What previewer returns. Host strips scripts and event handlers from HTML before frontend shows it
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Preview {
+    Html { html: String },
+    //Base64 encoded image of `mime` type
+    Image { mime: String, data: String },
+}
+
+impl Preview {
+    pub fn image(mime: &str, bytes: &[u8]) -> Self {
+        Preview::Image {
+            mime: mime.to_string(),
+            data: STANDARD.encode(bytes),
+        }
+    }
+}
+
+//`previewers` and `metadata_extractors` sections of manifest, require `fs-read` capability
+pub fn check_handlers(handlers: &[FileHandlerSchema]) -> Result<(), String> {
+    handlers.iter().try_for_each(|handler| handler.check())
+}
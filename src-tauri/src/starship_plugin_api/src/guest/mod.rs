@@ -0,0 +1,100 @@
+use std::marker::PhantomData;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::api::StarShipPluginAPI;
+use crate::context::{HostCall, HostContext, HostEvent};
+use crate::plugin_config::PluginConfig;
+
+#[doc(hidden)]
+pub mod bindings {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "starship-plugin",
+        pub_export_macro: true,
+        export_macro_name: "__export_starship_wasm_plugin",
+        default_bindings_module: "starship_plugin_api::guest::bindings",
+    });
+}
+
+use bindings::exports::starship::plugin::plugin::{Guest, HostInfo};
+
+struct WasmHostCall;
+
+impl HostCall for WasmHostCall {
+    fn call(&self, request: &str) -> String {
+        bindings::starship::plugin::host::call(request)
+    }
+}
+
+//Component has exactly one plugin instance, created on first call
+static PLUGIN: Mutex<Option<Box<dyn StarShipPluginAPI>>> = Mutex::new(None);
+
+fn plugin<P: StarShipPluginAPI + Default + 'static>() -> MutexGuard<'static, Option<Box<dyn StarShipPluginAPI>>> {
+    let mut plugin = PLUGIN.lock().unwrap_or_else(|e| e.into_inner());
+    if plugin.is_none() {
+        *plugin = Some(Box::new(P::default()));
+    }
+    plugin
+}
+
+fn with_plugin<P: StarShipPluginAPI + Default + 'static, T>(f: impl FnOnce(&mut dyn StarShipPluginAPI) -> T) -> T {
+    let mut plugin = plugin::<P>();
+    f(plugin.as_deref_mut().unwrap())
+}
+
+//Component side of the WIT interface, use `export_wasm_plugin!` instead of this type
+#[doc(hidden)]
+pub struct WasmGuest<P>(PhantomData<P>);
+
+impl<P: StarShipPluginAPI + Default + 'static> Guest for WasmGuest<P> {
+    fn name() -> String {
+        with_plugin::<P, _>(|plugin| plugin.name().to_string())
+    }
+
+    fn execute() -> Result<(), String> {
+        with_plugin::<P, _>(|plugin| plugin.execute()).map_err(|e| e.message)
+    }
+
+    fn on_load(info: HostInfo) -> Result<(), String> {
+        let host = HostContext::new(&info.plugin_id, &info.host_version, Path::new(&info.plugin_dir), Arc::new(WasmHostCall));
+        with_plugin::<P, _>(|plugin| plugin.on_load(host)).map_err(|e| e.message)
+    }
+
+    fn on_enable() -> Result<(), String> {
+        with_plugin::<P, _>(|plugin| plugin.on_enable()).map_err(|e| e.message)
+    }
+
+    fn on_disable() -> Result<(), String> {
+        with_plugin::<P, _>(|plugin| plugin.on_disable()).map_err(|e| e.message)
+    }
+
+    fn on_config_changed(config: String) -> Result<(), String> {
+        let config = PluginConfig::from_transfer(&config)?;
+        with_plugin::<P, _>(|plugin| plugin.on_config_changed(&config)).map_err(|e| e.message)
+    }
+
+    fn on_event(event: String) -> Result<(), String> {
+        let event: HostEvent = serde_json::from_str(&event).map_err(|e| e.to_string())?;
+        with_plugin::<P, _>(|plugin| plugin.on_event(&event)).map_err(|e| e.message)
+    }
+
+    fn on_action(action: String, args: String) -> Result<String, String> {
+        let args: serde_json::Value = serde_json::from_str(&args).map_err(|e| e.to_string())?;
+        let value = with_plugin::<P, _>(|plugin| plugin.on_action(&action, &args)).map_err(|e| e.message)?;
+        serde_json::to_string(&value).map_err(|e| e.to_string())
+    }
+
+    fn on_unload() -> Result<(), String> {
+        with_plugin::<P, _>(|plugin| plugin.on_unload()).map_err(|e| e.message)
+    }
+}
+
+//Exports plugin type as WASM component implementing `wit/plugin.wit`,
+//build with `cargo build --target wasm32-wasip2`
+#[macro_export]
+macro_rules! export_wasm_plugin {
+    ($plugin_type:ty) => {
+        type StarshipWasmPlugin = $crate::guest::WasmGuest<$plugin_type>;
+        $crate::__export_starship_wasm_plugin!(StarshipWasmPlugin with_types_in $crate::guest::bindings);
+    };
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::{debug, error, info, info_span, trace, warn, Span};
+use crate::context::{EventKind, FileEntry, HostCall, HostError, HostEvent, HostRequest, HostResponse, LogLevel, Notification, SearchHit};
+use crate::manifest::{Capability, PluginManifest};
+
+//Host functionality exposed to plugins, implemented by the app and by test hosts
+pub trait HostServices: Send + Sync {
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, String>;
+    fn list_dir(&self, path: &Path) -> Result<Vec<FileEntry>, String>;
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>, String>;
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), String>;
+    fn copy(&self, from: &Path, to: &Path) -> Result<(), String>;
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), String>;
+    fn delete(&self, path: &Path) -> Result<(), String>;
+    fn create_dir(&self, path: &Path) -> Result<(), String>;
+    fn notify(&self, plugin_id: &str, notification: Notification) -> Result<(), String>;
+}
+
+//Host end of one plugin's `HostContext`, checks capabilities before calling services
+pub struct HostDispatcher {
+    plugin_id: String,
+    capabilities: Vec<Capability>,
+    services: Arc<dyn HostServices>,
+    subscriptions: Mutex<Vec<EventKind>>,
+    span: Span,
+}
+
+impl HostDispatcher {
+    pub fn new(manifest: &PluginManifest, services: Arc<dyn HostServices>) -> Self {
+        Self {
+            plugin_id: manifest.id.clone(),
+            capabilities: manifest.capabilities.clone(),
+            services,
+            subscriptions: Mutex::new(vec![]),
+            span: info_span!("plugin", id = %manifest.id),
+        }
+    }
+
+    //Span for everything done by or for this plugin
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn is_subscribed(&self, event: &HostEvent) -> bool {
+        self.subscriptions.lock().is_ok_and(|subscriptions| subscriptions.contains(&event.kind()))
+    }
+
+    pub fn dispatch(&self, request: HostRequest) -> HostResponse {
+        if let Some(capability) = request.capability() {
+            if !self.capabilities.contains(&capability) {
+                warn!(parent: &self.span, "Denied {}, capability {:?} is not declared", request.method(), capability);
+                return Err(HostError::PermissionDenied(capability));
+            }
+        }
+        match request {
+            HostRequest::Search { query, limit } => to_value(self.services.search(&query, limit)),
+            HostRequest::ListDir { path } => to_value(self.services.list_dir(&path)),
+            HostRequest::ReadFile { path } => to_value(self.services.read_file(&path)),
+            HostRequest::WriteFile { path, data } => to_value(self.services.write_file(&path, &data)),
+            HostRequest::Copy { from, to } => to_value(self.services.copy(&from, &to)),
+            HostRequest::Move { from, to } => to_value(self.services.rename(&from, &to)),
+            HostRequest::Delete { path } => to_value(self.services.delete(&path)),
+            HostRequest::CreateDir { path } => to_value(self.services.create_dir(&path)),
+            HostRequest::Notify { notification } => to_value(self.services.notify(&self.plugin_id, notification)),
+            HostRequest::Subscribe { events } => {
+                let mut subscriptions = self.subscriptions.lock().map_err(|e| HostError::Failed(e.to_string()))?;
+                for event in events {
+                    if !subscriptions.contains(&event) {
+                        subscriptions.push(event);
+                    }
+                }
+                Ok(serde_json::Value::Null)
+            }
+            HostRequest::Log { level, message } => {
+                let _span = self.span.enter();
+                match level {
+                    LogLevel::Trace => trace!("{}", message),
+                    LogLevel::Debug => debug!("{}", message),
+                    LogLevel::Info => info!("{}", message),
+                    LogLevel::Warn => warn!("{}", message),
+                    LogLevel::Error => error!("{}", message),
+                }
+                Ok(serde_json::Value::Null)
+            }
+        }
+    }
+}
+
+fn to_value<T: serde::Serialize>(result: Result<T, String>) -> HostResponse {
+    let value = result.map_err(HostError::Failed)?;
+    serde_json::to_value(value).map_err(|e| HostError::Failed(e.to_string()))
+}
+
+impl HostCall for HostDispatcher {
+    fn call(&self, request: &str) -> String {
+        let response = match serde_json::from_str::<HostRequest>(request) {
+            Ok(request) => self.dispatch(request),
+            Err(e) => Err(HostError::InvalidRequest(e.to_string())),
+        };
+        serde_json::to_string(&response).unwrap_or_default()
+    }
+}
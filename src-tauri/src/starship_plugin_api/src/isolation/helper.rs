@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{RecvHalf, SendHalf, Stream};
+use crate::api::{PluginError, PluginResult, StarShipPluginAPI};
+use crate::context::{failed_response, HostCall, HostContext};
+use crate::isolation::process::socket_name;
+use crate::isolation::protocol::{self, HelperMessage, HookCall, HostMessage};
+use crate::loader::PluginLoader;
+use crate::plugin_config::PluginConfig;
+
+//First argument of helper process, followed by socket name and plugin library
+pub const HELPER_ARG: &str = "--plugin-host";
+
+type Pending = Arc<Mutex<HashMap<u64, Sender<String>>>>;
+
+//Call at the start of app `main` after tracing is set up, runs helper and returns its exit code
+//when process was started as plugin helper, `None` otherwise. Failed helper exits with 1 and
+//host reports the status as reason plugin stopped
+pub fn run_helper_from_args() -> Option<i32> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [arg, socket, library] if arg == HELPER_ARG => match run_helper(socket, &PathBuf::from(library)) {
+            Ok(()) => Some(0),
+            Err(e) => {
+                tracing::error!("Plugin helper for {} failed: {}", library, e);
+                Some(1)
+            }
+        },
+        _ => None,
+    }
+}
+
+//Loads plugin library and serves hook calls from app until it disconnects
+pub fn run_helper(socket: &str, library: &Path) -> Result<(), String> {
+    let (plugin, library) = PluginLoader::load_library(library, library).map_err(|e| e.error)?;
+    let stream = Stream::connect(socket_name(socket).map_err(|e| e.to_string())?)
+        .map_err(|e| format!("can't connect to app: {}", e))?;
+    let (recv, send) = stream.split();
+    let send = Arc::new(Mutex::new(send));
+    let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+    let (calls_tx, calls) = mpsc::channel();
+    {
+        let pending = pending.clone();
+        std::thread::spawn(move || read_messages(recv, pending, calls_tx));
+    }
+    let host: Arc<dyn HostCall> = Arc::new(HelperHost {
+        send: send.clone(),
+        pending,
+        next_id: AtomicU64::new(0),
+    });
+    let mut plugin = plugin;
+    let mut result = Ok(());
+    for call in calls {
+        let message = HelperMessage::Result { result: run_hook(&mut plugin, call, &host).map_err(|e| e.message) };
+        if let Err(e) = protocol::send(&mut *send.lock().unwrap_or_else(|e| e.into_inner()), &message) {
+            result = Err(e.to_string());
+            break;
+        }
+    }
+    drop(plugin);
+    drop(library);
+    result
+}
+
+fn read_messages(recv: RecvHalf, pending: Pending, calls: Sender<HookCall>) {
+    let mut recv = BufReader::new(recv);
+    while let Ok(Some(message)) = protocol::receive::<HostMessage>(&mut recv) {
+        match message {
+            HostMessage::Call { call } => {
+                if calls.send(call).is_err() {
+                    break;
+                }
+            }
+            HostMessage::HostResponse { id, response } => {
+                if let Some(waiting) = pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&id) {
+                    let _ = waiting.send(response);
+                }
+            }
+        }
+    }
+    //Wakes up host calls still waiting for response
+    pending.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+fn run_hook(plugin: &mut dyn StarShipPluginAPI, call: HookCall, host: &Arc<dyn HostCall>) -> PluginResult<serde_json::Value> {
+    let done = |result: PluginResult| result.map(|_| serde_json::Value::Null);
+    match call {
+        HookCall::Name => Ok(serde_json::Value::String(plugin.name().to_string())),
+        HookCall::Execute => done(plugin.execute()),
+        HookCall::OnLoad { plugin_id, host_version, plugin_dir } => {
+            done(plugin.on_load(HostContext::new(&plugin_id, &host_version, &plugin_dir, host.clone())))
+        }
+        HookCall::OnEnable => done(plugin.on_enable()),
+        HookCall::OnDisable => done(plugin.on_disable()),
+        HookCall::OnConfigChanged { config } => {
+            let config = PluginConfig::from_transfer(&config).map_err(PluginError::new)?;
+            done(plugin.on_config_changed(&config))
+        }
+        HookCall::OnEvent { event } => done(plugin.on_event(&event)),
+        HookCall::OnAction { action, args } => plugin.on_action(&action, &args),
+        HookCall::OnUnload => done(plugin.on_unload()),
+    }
+}
+
+//Forwards host calls of the plugin to app, may be called from any plugin thread
+struct HelperHost {
+    send: Arc<Mutex<SendHalf>>,
+    pending: Pending,
+    next_id: AtomicU64,
+}
+
+impl HostCall for HelperHost {
+    fn call(&self, request: &str) -> String {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (response_tx, response) = mpsc::channel();
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).insert(id, response_tx);
+        let message = HelperMessage::HostCall { id, request: request.to_string() };
+        if protocol::send(&mut *self.send.lock().unwrap_or_else(|e| e.into_inner()), &message).is_err() {
+            self.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+            return failed_response("app closed connection");
+        }
+        response.recv().unwrap_or_else(|_| failed_response("app closed connection"))
+    }
+}
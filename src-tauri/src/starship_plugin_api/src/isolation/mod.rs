@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use crate::manifest::PluginManifest;
+
+mod helper;
+mod process;
+mod protocol;
+mod thread;
+
+pub use helper::{run_helper, run_helper_from_args, HELPER_ARG};
+pub use process::ProcessPlugin;
+pub use thread::ThreadedPlugin;
+
+//Why isolation layer stopped its plugin. Stopped plugin is never called or restarted again,
+//loaded plugin keeps a clone so host can tell it apart from a plugin that only failed a call
+#[derive(Clone, Default, Debug)]
+pub struct StopReason(Arc<Mutex<Option<String>>>);
+
+impl StopReason {
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set(&self, reason: String) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(reason);
+    }
+}
+
+//Where plugin code runs
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Isolation {
+    //Own thread in app process, hung call is abandoned after timeout
+    Thread,
+    //Helper process connected over local socket, crash or hang kills only the helper
+    Process,
+}
+
+//Decides how loader runs plugins. WASM plugins are sandboxed already and always run in a thread
+#[derive(Clone, Debug)]
+pub struct IsolationPolicy {
+    //Hook that doesn't return in time stops the plugin
+    pub call_timeout: Duration,
+    //Executable started with `HELPER_ARG` to host isolated plugins, usually the app itself
+    pub helper: Option<PathBuf>,
+    //Ids of native plugins run in helper process, `*` isolates every native plugin
+    pub isolated: Vec<String>,
+}
+
+impl Default for IsolationPolicy {
+    fn default() -> Self {
+        Self {
+            call_timeout: Duration::from_secs(10),
+            helper: None,
+            isolated: vec![],
+        }
+    }
+}
+
+impl IsolationPolicy {
+    //Helper to run plugin with, `None` when plugin runs in app process
+    pub fn helper_for(&self, manifest: &PluginManifest) -> Option<&Path> {
+        let isolated = self.isolated.iter().any(|id| id == "*" || *id == manifest.id);
+        self.helper.as_deref().filter(|_| isolated)
+    }
+}
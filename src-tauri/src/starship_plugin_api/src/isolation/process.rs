@@ -0,0 +1,231 @@
+use std::io::{BufReader, ErrorKind};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use interprocess::local_socket::prelude::*;
+use interprocess::local_socket::{GenericFilePath, GenericNamespaced, ListenerNonblockingMode, ListenerOptions, Name, RecvHalf, SendHalf, Stream};
+use crate::api::{PluginError, PluginResult, StarShipPluginAPI};
+use crate::context::{failed_response, HostCall, HostContext, HostEvent};
+use crate::isolation::helper::HELPER_ARG;
+use crate::isolation::StopReason;
+use crate::isolation::protocol::{self, HelperMessage, HookCall, HostMessage};
+use crate::plugin_config::PluginConfig;
+
+static SOCKETS: AtomicUsize = AtomicUsize::new(0);
+
+type SharedHost = Arc<Mutex<Option<Arc<dyn HostCall>>>>;
+
+//Abstract socket or named pipe where supported, socket file in temp dir otherwise
+pub(crate) fn socket_name(name: &str) -> std::io::Result<Name<'static>> {
+    if GenericNamespaced::is_supported() {
+        name.to_string().to_ns_name::<GenericNamespaced>()
+    } else {
+        std::env::temp_dir().join(name).to_fs_name::<GenericFilePath>()
+    }
+}
+
+//Plugin loaded by helper process. Hooks are forwarded over local socket, host calls of the plugin
+//come back over the same socket. Helper is killed when call times out or plugin is dropped
+pub struct ProcessPlugin {
+    name: String,
+    timeout: Duration,
+    child: Mutex<Child>,
+    send: Arc<Mutex<SendHalf>>,
+    results: Mutex<Receiver<Result<serde_json::Value, String>>>,
+    host: SharedHost,
+    stopped: StopReason,
+}
+
+impl ProcessPlugin {
+    pub fn spawn(helper: &Path, library: &Path, timeout: Duration) -> Result<Self, String> {
+        let socket = format!("starship-plugin-{}-{}.sock", std::process::id(), SOCKETS.fetch_add(1, Ordering::Relaxed));
+        let listener = ListenerOptions::new()
+            .name(socket_name(&socket).map_err(|e| e.to_string())?)
+            .nonblocking(ListenerNonblockingMode::Accept)
+            .reclaim_name(true)
+            .create_sync()
+            .map_err(|e| format!("can't create plugin socket: {}", e))?;
+        let mut child = Command::new(helper)
+            .arg(HELPER_ARG)
+            .arg(&socket)
+            .arg(library)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("can't start plugin helper {}: {}", helper.display(), e))?;
+        let stream = match Self::accept(&listener, &mut child, timeout) {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+        let (recv, send) = stream.split();
+        let send = Arc::new(Mutex::new(send));
+        let host: SharedHost = Arc::new(Mutex::new(None));
+        let (results_tx, results_rx) = mpsc::channel();
+        {
+            let send = send.clone();
+            let host = host.clone();
+            std::thread::spawn(move || Self::read_messages(recv, send, host, results_tx));
+        }
+        let mut plugin = Self {
+            name: String::new(),
+            timeout,
+            child: Mutex::new(child),
+            send,
+            results: Mutex::new(results_rx),
+            host,
+            stopped: StopReason::default(),
+        };
+        plugin.name = match plugin.call("name", HookCall::Name).map_err(|e| e.message)? {
+            serde_json::Value::String(name) => name,
+            _ => return Err("plugin helper returned invalid name".to_string()),
+        };
+        Ok(plugin)
+    }
+
+    fn accept(listener: &LocalSocketListener, child: &mut Child, timeout: Duration) -> Result<Stream, String> {
+        let started = Instant::now();
+        loop {
+            match listener.accept() {
+                Ok(stream) => {
+                    stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+                    return Ok(stream);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if let Ok(Some(status)) = child.try_wait() {
+                        return Err(format!("plugin helper exited with {}", status));
+                    }
+                    if started.elapsed() > timeout {
+                        return Err("plugin helper didn't connect in time".to_string());
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    //Ends when helper disconnects, dropped `results` wakes up waiting call
+    fn read_messages(recv: RecvHalf, send: Arc<Mutex<SendHalf>>, host: SharedHost, results: Sender<Result<serde_json::Value, String>>) {
+        let mut recv = BufReader::new(recv);
+        while let Ok(Some(message)) = protocol::receive::<HelperMessage>(&mut recv) {
+            match message {
+                HelperMessage::HostCall { id, request } => {
+                    let host = host.lock().unwrap_or_else(|e| e.into_inner()).clone();
+                    let response = match host {
+                        Some(host) => host.call(&request),
+                        None => failed_response("host is not available before on_load"),
+                    };
+                    let mut send = send.lock().unwrap_or_else(|e| e.into_inner());
+                    if protocol::send(&mut *send, &HostMessage::HostResponse { id, response }).is_err() {
+                        return;
+                    }
+                }
+                HelperMessage::Result { result } => {
+                    if results.send(result).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn stopped(&self) -> Option<String> {
+        self.stopped.get()
+    }
+
+    pub fn stop_reason(&self) -> StopReason {
+        self.stopped.clone()
+    }
+
+    fn stop(&self, reason: String) -> PluginError {
+        let mut child = self.child.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = child.kill();
+        let _ = child.wait();
+        self.stopped.set(reason.clone());
+        PluginError::new(reason)
+    }
+
+    fn exit_reason(&self) -> String {
+        match self.child.lock().unwrap_or_else(|e| e.into_inner()).try_wait() {
+            Ok(Some(status)) => format!("plugin helper exited with {}", status),
+            _ => "plugin helper closed connection".to_string(),
+        }
+    }
+
+    fn call(&self, hook: &str, call: HookCall) -> PluginResult<serde_json::Value> {
+        if let Some(reason) = self.stopped() {
+            return Err(PluginError::new(format!("plugin is stopped: {}", reason)));
+        }
+        let results = self.results.lock().unwrap_or_else(|e| e.into_inner());
+        let sent = protocol::send(&mut *self.send.lock().unwrap_or_else(|e| e.into_inner()), &HostMessage::Call { call });
+        if sent.is_err() {
+            return Err(self.stop(self.exit_reason()));
+        }
+        match results.recv_timeout(self.timeout) {
+            Ok(result) => result.map_err(PluginError::new),
+            Err(RecvTimeoutError::Timeout) => Err(self.stop(format!("{} didn't return in {:?}", hook, self.timeout))),
+            Err(RecvTimeoutError::Disconnected) => Err(self.stop(self.exit_reason())),
+        }
+    }
+
+    fn call_hook(&self, hook: &str, call: HookCall) -> PluginResult {
+        self.call(hook, call).map(|_| ())
+    }
+}
+
+impl StarShipPluginAPI for ProcessPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn execute(&self) -> PluginResult {
+        self.call_hook("execute", HookCall::Execute)
+    }
+
+    fn on_load(&mut self, host: HostContext) -> PluginResult {
+        *self.host.lock().unwrap_or_else(|e| e.into_inner()) = Some(host.host().clone());
+        self.call_hook("on_load", HookCall::OnLoad {
+            plugin_id: host.plugin_id().to_string(),
+            host_version: host.host_version().to_string(),
+            plugin_dir: host.plugin_dir().to_path_buf(),
+        })
+    }
+
+    fn on_enable(&mut self) -> PluginResult {
+        self.call_hook("on_enable", HookCall::OnEnable)
+    }
+
+    fn on_disable(&mut self) -> PluginResult {
+        self.call_hook("on_disable", HookCall::OnDisable)
+    }
+
+    fn on_config_changed(&mut self, config: &PluginConfig) -> PluginResult {
+        self.call_hook("on_config_changed", HookCall::OnConfigChanged { config: config.to_transfer() })
+    }
+
+    fn on_event(&mut self, event: &HostEvent) -> PluginResult {
+        self.call_hook("on_event", HookCall::OnEvent { event: event.clone() })
+    }
+
+    fn on_action(&mut self, action: &str, args: &serde_json::Value) -> PluginResult<serde_json::Value> {
+        self.call("on_action", HookCall::OnAction { action: action.to_string(), args: args.clone() })
+    }
+
+    fn on_unload(&mut self) -> PluginResult {
+        self.call_hook("on_unload", HookCall::OnUnload)
+    }
+}
+
+impl Drop for ProcessPlugin {
+    fn drop(&mut self) {
+        let child = self.child.get_mut().unwrap_or_else(|e| e.into_inner());
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::context::HostEvent;
+
+//Hook call sent to helper process, config is in `PluginConfig::to_transfer` form
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "hook", rename_all = "snake_case")]
+pub(crate) enum HookCall {
+    Name,
+    Execute,
+    OnLoad { plugin_id: String, host_version: String, plugin_dir: PathBuf },
+    OnEnable,
+    OnDisable,
+    OnConfigChanged { config: String },
+    OnEvent { event: HostEvent },
+    OnAction { action: String, args: serde_json::Value },
+    OnUnload,
+}
+
+//App to helper
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum HostMessage {
+    Call { call: HookCall },
+    HostResponse { id: u64, response: String },
+}
+
+//Helper to app. Host calls may come from plugin threads at any time, so they carry id
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum HelperMessage {
+    HostCall { id: u64, request: String },
+    Result { result: Result<serde_json::Value, String> },
+}
+
+//Messages are JSON lines
+pub(crate) fn send<T: Serialize>(out: &mut impl Write, message: &T) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    out.write_all(line.as_bytes())?;
+    out.flush()
+}
+
+//`None` when other side closed connection
+pub(crate) fn receive<T: DeserializeOwned>(input: &mut impl BufRead) -> std::io::Result<Option<T>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
@@ -0,0 +1,214 @@
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use crate::abi::panic_message;
+use crate::api::{PluginError, PluginResult, StarShipPluginAPI};
+use crate::context::{HostContext, HostEvent};
+use crate::isolation::StopReason;
+use crate::plugin_config::PluginConfig;
+
+type Job = Box<dyn FnOnce(&mut dyn StarShipPluginAPI) + Send>;
+
+//Runs plugin on its own thread, so host can stop waiting for a call that hangs.
+//Plugin that hung or whose thread died is never called again
+pub struct ThreadedPlugin {
+    name: String,
+    timeout: Duration,
+    //Closed on drop, thread drops plugin and exits after the jobs already sent
+    jobs: Mutex<Option<Sender<Job>>>,
+    //Disconnects once plugin and its library are dropped on plugin thread
+    finished: Mutex<Receiver<()>>,
+    thread: Option<JoinHandle<()>>,
+    stopped: StopReason,
+}
+
+impl ThreadedPlugin {
+    //`keep_alive` is dropped on plugin thread right after plugin, e.g. library plugin came from.
+    //Thread of hung plugin never ends, so its library is never unloaded under running code
+    pub fn spawn(plugin: Box<dyn StarShipPluginAPI>, keep_alive: Option<Box<dyn Any + Send>>, timeout: Duration) -> Result<Self, String> {
+        let name = catch_unwind(AssertUnwindSafe(|| plugin.name().to_string()))
+            .map_err(|panic| format!("plugin panicked: {}", panic_message(panic.as_ref())))?;
+        let (jobs, jobs_rx) = mpsc::channel::<Job>();
+        let (finished_tx, finished) = mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name(format!("plugin {}", name))
+            .spawn(move || {
+                let mut plugin = plugin;
+                for job in jobs_rx {
+                    job(plugin.as_mut());
+                }
+                drop(plugin);
+                drop(keep_alive);
+                drop(finished_tx);
+            })
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            name,
+            timeout,
+            jobs: Mutex::new(Some(jobs)),
+            finished: Mutex::new(finished),
+            thread: Some(thread),
+            stopped: StopReason::default(),
+        })
+    }
+
+    pub fn stopped(&self) -> Option<String> {
+        self.stopped.get()
+    }
+
+    pub fn stop_reason(&self) -> StopReason {
+        self.stopped.clone()
+    }
+
+    fn stop(&self, reason: String) -> PluginError {
+        self.stopped.set(reason.clone());
+        PluginError::new(reason)
+    }
+
+    fn call<T: Send + 'static>(&self, hook: &str, call: impl FnOnce(&mut dyn StarShipPluginAPI) -> PluginResult<T> + Send + 'static) -> PluginResult<T> {
+        if let Some(reason) = self.stopped() {
+            return Err(PluginError::new(format!("plugin is stopped: {}", reason)));
+        }
+        let (result_tx, result_rx) = mpsc::sync_channel(1);
+        let job: Job = Box::new(move |plugin| {
+            let result = catch_unwind(AssertUnwindSafe(|| call(plugin)))
+                .unwrap_or_else(|panic| Err(PluginError::new(format!("plugin panicked: {}", panic_message(panic.as_ref())))));
+            let _ = result_tx.send(result);
+        });
+        let sent = match self.jobs.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+            Some(jobs) => jobs.send(job).is_ok(),
+            None => false,
+        };
+        if !sent {
+            return Err(self.stop("plugin thread exited".to_string()));
+        }
+        match result_rx.recv_timeout(self.timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(self.stop(format!("{} didn't return in {:?}", hook, self.timeout))),
+            Err(RecvTimeoutError::Disconnected) => Err(self.stop("plugin thread exited".to_string())),
+        }
+    }
+}
+
+//Plugin is dropped by the time this returns, unless it hangs longer than call timeout.
+//Hung thread is left running and keeps its library loaded
+impl Drop for ThreadedPlugin {
+    fn drop(&mut self) {
+        drop(self.jobs.get_mut().unwrap_or_else(|e| e.into_inner()).take());
+        if self.stopped.get().is_some() {
+            return;
+        }
+        let finished = self.finished.get_mut().unwrap_or_else(|e| e.into_inner());
+        if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(self.timeout) {
+            tracing::warn!("Plugin {} didn't shut down in {:?}, its thread is left running", self.name, self.timeout);
+            return;
+        }
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl StarShipPluginAPI for ThreadedPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn execute(&self) -> PluginResult {
+        self.call("execute", |plugin| plugin.execute())
+    }
+
+    fn on_load(&mut self, host: HostContext) -> PluginResult {
+        self.call("on_load", move |plugin| plugin.on_load(host))
+    }
+
+    fn on_enable(&mut self) -> PluginResult {
+        self.call("on_enable", |plugin| plugin.on_enable())
+    }
+
+    fn on_disable(&mut self) -> PluginResult {
+        self.call("on_disable", |plugin| plugin.on_disable())
+    }
+
+    fn on_config_changed(&mut self, config: &PluginConfig) -> PluginResult {
+        let config = config.clone();
+        self.call("on_config_changed", move |plugin| plugin.on_config_changed(&config))
+    }
+
+    fn on_event(&mut self, event: &HostEvent) -> PluginResult {
+        let event = event.clone();
+        self.call("on_event", move |plugin| plugin.on_event(&event))
+    }
+
+    fn on_action(&mut self, action: &str, args: &serde_json::Value) -> PluginResult<serde_json::Value> {
+        let action = action.to_string();
+        let args = args.clone();
+        self.call("on_action", move |plugin| plugin.on_action(&action, &args))
+    }
+
+    fn on_unload(&mut self) -> PluginResult {
+        self.call("on_unload", |plugin| plugin.on_unload())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use super::*;
+
+    struct Sleepy {
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl StarShipPluginAPI for Sleepy {
+        fn name(&self) -> &str {
+            "sleepy"
+        }
+
+        fn execute(&self) -> PluginResult {
+            Ok(())
+        }
+
+        fn on_action(&mut self, action: &str, _args: &serde_json::Value) -> PluginResult<serde_json::Value> {
+            if action == "hang" {
+                std::thread::sleep(Duration::from_millis(300));
+            }
+            Ok(serde_json::Value::Null)
+        }
+    }
+
+    impl Drop for Sleepy {
+        fn drop(&mut self) {
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    fn spawn(timeout: Duration) -> (ThreadedPlugin, Arc<AtomicBool>) {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let plugin = Box::new(Sleepy { dropped: dropped.clone() });
+        (ThreadedPlugin::spawn(plugin, None, timeout).unwrap(), dropped)
+    }
+
+    #[test]
+    fn plugin_is_dropped_with_its_wrapper() {
+        let (mut plugin, dropped) = spawn(Duration::from_secs(5));
+        plugin.on_action("run", &serde_json::Value::Null).unwrap();
+        drop(plugin);
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn hung_plugin_is_stopped_for_good() {
+        let (mut plugin, _) = spawn(Duration::from_millis(50));
+        let stopped = plugin.stop_reason();
+        assert!(plugin.on_action("hang", &serde_json::Value::Null).is_err());
+        assert!(stopped.get().is_some());
+        std::thread::sleep(Duration::from_millis(400));
+        let error = plugin.on_action("run", &serde_json::Value::Null).unwrap_err();
+        assert!(error.message.starts_with("plugin is stopped"));
+    }
+}
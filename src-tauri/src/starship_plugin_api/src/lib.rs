@@ -1,3 +1,19 @@
 pub mod api;
+pub mod abi;
+pub mod context;
 pub mod plugin_config;
-
+pub mod settings;
+pub mod manifest;
+pub mod ui;
+pub mod search;
+pub mod files;
+#[cfg(feature = "host")]
+pub mod host;
+#[cfg(feature = "host")]
+pub mod loader;
+#[cfg(feature = "host")]
+pub mod isolation;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "guest")]
+pub mod guest;
@@ -0,0 +1,480 @@
+use std::any::Any;
+use std::fmt::{Display, Formatter};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use libloading::{Library, Symbol};
+use semver::Version;
+use serde::Serialize;
+use crate::abi::{FfiPlugin, PluginVTable, API_VERSION, API_VERSION_SYMBOL, EXPORT_SYMBOL, LEGACY_EXPORT_SYMBOL, MIN_SUPPORTED_API_VERSION};
+use crate::api::{PluginError, StarShipPluginAPI};
+use crate::isolation::{Isolation, IsolationPolicy, ProcessPlugin, StopReason, ThreadedPlugin};
+use crate::manifest::{resolve_order, ManifestError, PluginManifest, MANIFEST_FILE};
+#[cfg(feature = "wasm")]
+use crate::wasm::{WasmLimits, WasmRuntime};
+
+type ExportPlugin = unsafe extern "C" fn() -> PluginVTable;
+
+const WASM_EXTENSION: &str = "wasm";
+
+static SHADOW_COPIES: AtomicUsize = AtomicUsize::new(0);
+
+//Native plugins are shared libraries running with full app privileges,
+//WASM plugins are components running in sandbox with memory and CPU limits
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PluginKind {
+    Native,
+    Wasm,
+}
+
+impl PluginKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?;
+        if ext == std::env::consts::DLL_EXTENSION {
+            Some(PluginKind::Native)
+        } else if ext == WASM_EXTENSION {
+            Some(PluginKind::Wasm)
+        } else {
+            None
+        }
+    }
+}
+
+//Plugin instance behind its isolation layer. Native library is owned by plugin thread
+//and unloaded there after the instance is dropped
+pub struct LoadedPlugin {
+    plugin: Box<dyn StarShipPluginAPI>,
+    manifest: PluginManifest,
+    path: PathBuf,
+    kind: PluginKind,
+    isolation: Isolation,
+    stopped: StopReason,
+    //Dropped after `plugin`, so the copy is removed once its library is unloaded
+    _shadow: Option<ShadowCopy>,
+}
+
+impl LoadedPlugin {
+    pub fn kind(&self) -> PluginKind {
+        self.kind
+    }
+
+    pub fn isolation(&self) -> Isolation {
+        self.isolation
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn manifest(&self) -> &PluginManifest {
+        &self.manifest
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(&self.path)
+    }
+
+    //Set once isolation gave up on plugin after it hung or crashed, every later call fails
+    pub fn stopped(&self) -> Option<String> {
+        self.stopped.get()
+    }
+}
+
+impl Deref for LoadedPlugin {
+    type Target = dyn StarShipPluginAPI;
+
+    fn deref(&self) -> &Self::Target {
+        self.plugin.as_ref()
+    }
+}
+
+impl DerefMut for LoadedPlugin {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.plugin.as_mut()
+    }
+}
+
+//Copy of native library loaded in hot reload mode, removed when dropped
+struct ShadowCopy(PathBuf);
+
+impl Drop for ShadowCopy {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            tracing::warn!("Can't remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+//Plugin directory with valid manifest, not loaded yet
+#[derive(Clone, Debug)]
+pub struct DiscoveredPlugin {
+    pub dir: PathBuf,
+    pub library: PathBuf,
+    pub manifest: PluginManifest,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum PluginLoadErrorKind {
+    Io,
+    Manifest,
+    Library,
+    NotAPlugin,
+    Hook,
+    IncompatibleVersion { plugin: u32, min: u32, max: u32 },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PluginLoadError {
+    pub path: PathBuf,
+    pub manifest: Option<Box<PluginManifest>>,
+    pub kind: PluginLoadErrorKind,
+    pub error: String,
+}
+
+impl PluginLoadError {
+    fn new(path: &Path, kind: PluginLoadErrorKind, error: String) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            manifest: None,
+            kind,
+            error,
+        }
+    }
+
+    pub fn manifest(plugin: &DiscoveredPlugin, error: ManifestError) -> Self {
+        let mut e = Self::new(&plugin.dir, PluginLoadErrorKind::Manifest, error.to_string());
+        e.manifest = Some(Box::new(plugin.manifest.clone()));
+        e
+    }
+
+    //Plugin was created but one of its hooks failed
+    pub fn hook(plugin: &LoadedPlugin, error: PluginError) -> Self {
+        let mut e = Self::new(&plugin.path, PluginLoadErrorKind::Hook, error.message);
+        e.manifest = Some(Box::new(plugin.manifest.clone()));
+        e
+    }
+
+    pub fn plugin_id(&self) -> Option<&str> {
+        self.manifest.as_ref().map(|manifest| manifest.id.as_str())
+    }
+
+    fn incompatible(path: &Path, plugin: u32) -> Self {
+        let error = if plugin < MIN_SUPPORTED_API_VERSION {
+            format!("plugin was built for plugin API v{}, which is too old for this app (supported v{}..v{}), rebuild it with newer starship_plugin_api", plugin, MIN_SUPPORTED_API_VERSION, API_VERSION)
+        } else {
+            format!("plugin was built for plugin API v{}, which is newer than this app supports (v{}..v{}), update the app", plugin, MIN_SUPPORTED_API_VERSION, API_VERSION)
+        };
+        Self::new(path, PluginLoadErrorKind::IncompatibleVersion {
+            plugin,
+            min: MIN_SUPPORTED_API_VERSION,
+            max: API_VERSION,
+        }, error)
+    }
+}
+
+impl Display for PluginLoadError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.plugin_id() {
+            Some(id) => write!(f, "Failed to load plugin {} ({}): {}", id, self.path.display(), self.error),
+            None => write!(f, "Failed to load plugin {}: {}", self.path.display(), self.error)
+        }
+    }
+}
+
+//Finds plugin directories, checks manifests and creates plugin instances in dependency order.
+//Layout: `plugins/<name>/plugin.toml` with plugin library in the same directory
+pub struct PluginLoader {
+    dir: PathBuf,
+    host_version: Version,
+    //Native libraries are loaded from copies here, so original can be rebuilt while loaded
+    shadow_dir: Option<PathBuf>,
+    isolation: IsolationPolicy,
+    #[cfg(feature = "wasm")]
+    wasm: Option<WasmRuntime>,
+}
+
+impl PluginLoader {
+    pub fn new(dir: PathBuf, host_version: Version) -> Self {
+        Self {
+            dir,
+            host_version,
+            shadow_dir: None,
+            isolation: IsolationPolicy::default(),
+            #[cfg(feature = "wasm")]
+            wasm: Self::wasm_runtime(WasmLimits::default()),
+        }
+    }
+
+    pub fn with_isolation(mut self, isolation: IsolationPolicy) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    //Used for hot reload: Windows locks loaded libraries, and other systems may hand out
+    //already loaded library again when path is the same. Every process copies into its own
+    //subdirectory of `dir`, removed with the loader. Subdirectory left by earlier process
+    //with the same id is cleared
+    pub fn with_shadow_dir(mut self, dir: PathBuf) -> Self {
+        let dir = dir.join(std::process::id().to_string());
+        let _ = std::fs::remove_dir_all(&dir);
+        if let Err(e) = std::fs::create_dir_all(&dir) {
+            tracing::error!("Can't create {}: {}", dir.display(), e);
+        }
+        self.shadow_dir = Some(dir);
+        self
+    }
+
+    fn shadow_copy(&self, path: &Path) -> Result<Option<ShadowCopy>, PluginLoadError> {
+        let shadow_dir = match &self.shadow_dir {
+            Some(dir) => dir,
+            None => return Ok(None),
+        };
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let copy = shadow_dir.join(format!("{}-{}", SHADOW_COPIES.fetch_add(1, Ordering::Relaxed), file_name));
+        std::fs::copy(path, &copy).map_err(|e| PluginLoadError::new(path, PluginLoadErrorKind::Io, e.to_string()))?;
+        Ok(Some(ShadowCopy(copy)))
+    }
+
+    #[cfg(feature = "wasm")]
+    fn wasm_runtime(limits: WasmLimits) -> Option<WasmRuntime> {
+        match WasmRuntime::new(limits) {
+            Ok(runtime) => Some(runtime),
+            Err(e) => {
+                tracing::error!("WASM plugins are disabled, runtime failed to start: {}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "wasm")]
+    pub fn with_wasm_limits(mut self, limits: WasmLimits) -> Self {
+        self.wasm = Self::wasm_runtime(limits);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn host_version(&self) -> &Version {
+        &self.host_version
+    }
+
+    //Libraries with platform extension (`.so`, `.dll`, `.dylib`) or WASM components
+    fn is_library(path: &Path) -> bool {
+        path.is_file() && PluginKind::from_path(path).is_some()
+    }
+
+    pub fn discover(&self) -> (Vec<DiscoveredPlugin>, Vec<PluginLoadError>) {
+        discover(&self.dir, &self.host_version)
+    }
+
+    pub fn discover_dir(&self, dir: &Path) -> Result<DiscoveredPlugin, PluginLoadError> {
+        discover_dir(dir, &self.host_version)
+    }
+
+    //Host stages and swaps plugin versions in hidden directories next to plugins
+    fn is_hidden(path: &Path) -> bool {
+        path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'))
+    }
+
+    //Version is checked before any plugin code is called
+    fn check_version(library: &Library, path: &Path) -> Result<(), PluginLoadError> {
+        let version = unsafe { library.get::<*const u32>(API_VERSION_SYMBOL) }
+            .map(|symbol| unsafe { **symbol });
+        match version {
+            Ok(version) if (MIN_SUPPORTED_API_VERSION..=API_VERSION).contains(&version) => Ok(()),
+            Ok(version) => Err(PluginLoadError::incompatible(path, version)),
+            Err(_) if unsafe { library.get::<*const ()>(LEGACY_EXPORT_SYMBOL) }.is_ok() => Err(PluginLoadError::new(
+                path,
+                PluginLoadErrorKind::IncompatibleVersion { plugin: 0, min: MIN_SUPPORTED_API_VERSION, max: API_VERSION },
+                "plugin was built with unversioned plugin API, rebuild it with current starship_plugin_api".to_string(),
+            )),
+            Err(_) => Err(PluginLoadError::new(
+                path,
+                PluginLoadErrorKind::NotAPlugin,
+                "library doesn't export STARSHIP_PLUGIN_API_VERSION, it is not a SpaceTraveler plugin".to_string(),
+            ))
+        }
+    }
+
+    pub fn load(&self, plugin: &DiscoveredPlugin) -> Result<LoadedPlugin, PluginLoadError> {
+        self.load_plugin(plugin).map_err(|mut e| {
+            e.manifest = Some(Box::new(plugin.manifest.clone()));
+            e
+        })
+    }
+
+    fn load_plugin(&self, plugin: &DiscoveredPlugin) -> Result<LoadedPlugin, PluginLoadError> {
+        let path = &plugin.library;
+        let kind = PluginKind::from_path(path).unwrap_or(PluginKind::Native);
+        let mut shadow = None;
+        let (instance, isolation, stopped) = match kind {
+            PluginKind::Wasm => {
+                let (instance, stopped) = self.spawn_thread(path, self.load_wasm(path)?, None)?;
+                (instance, Isolation::Thread, stopped)
+            }
+            PluginKind::Native => {
+                shadow = self.shadow_copy(path)?;
+                let library = shadow.as_ref().map_or(path.as_path(), |copy| copy.0.as_path());
+                match self.isolation.helper_for(&plugin.manifest) {
+                    Some(helper) => {
+                        let instance = ProcessPlugin::spawn(helper, library, self.isolation.call_timeout)
+                            .map_err(|e| PluginLoadError::new(path, PluginLoadErrorKind::Library, e))?;
+                        let stopped = instance.stop_reason();
+                        (Box::new(instance) as Box<dyn StarShipPluginAPI>, Isolation::Process, stopped)
+                    }
+                    None => {
+                        let (instance, library) = Self::load_library(path, library)?;
+                        let (instance, stopped) = self.spawn_thread(path, Box::new(instance), Some(Box::new(library)))?;
+                        (instance, Isolation::Thread, stopped)
+                    }
+                }
+            }
+        };
+        Ok(LoadedPlugin {
+            plugin: instance,
+            manifest: plugin.manifest.clone(),
+            path: path.clone(),
+            kind,
+            isolation,
+            stopped,
+            _shadow: shadow,
+        })
+    }
+
+    fn spawn_thread(&self, path: &Path, plugin: Box<dyn StarShipPluginAPI>, keep_alive: Option<Box<dyn Any + Send>>) -> Result<(Box<dyn StarShipPluginAPI>, StopReason), PluginLoadError> {
+        let plugin = ThreadedPlugin::spawn(plugin, keep_alive, self.isolation.call_timeout)
+            .map_err(|e| PluginLoadError::new(path, PluginLoadErrorKind::Library, e))?;
+        let stopped = plugin.stop_reason();
+        Ok((Box::new(plugin), stopped))
+    }
+
+    #[cfg(feature = "wasm")]
+    fn load_wasm(&self, path: &Path) -> Result<Box<dyn StarShipPluginAPI>, PluginLoadError> {
+        let runtime = self.wasm.as_ref()
+            .ok_or_else(|| PluginLoadError::new(path, PluginLoadErrorKind::Library, "WASM runtime is not available".to_string()))?;
+        let plugin = runtime.load(path)
+            .map_err(|e| PluginLoadError::new(path, PluginLoadErrorKind::Library, e))?;
+        Ok(Box::new(plugin))
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    fn load_wasm(&self, path: &Path) -> Result<Box<dyn StarShipPluginAPI>, PluginLoadError> {
+        Err(PluginLoadError::new(path, PluginLoadErrorKind::Library, "WASM plugins are not supported by this build".to_string()))
+    }
+
+    //`library` is the file actually loaded, `path` or its shadow copy.
+    //Instance must be dropped before returned library
+    pub(crate) fn load_library(path: &Path, library: &Path) -> Result<(FfiPlugin, Library), PluginLoadError> {
+        //Loading runs library initializers, plugins are trusted code at this point
+        let library = unsafe { Library::new(library) }
+            .map_err(|e| PluginLoadError::new(path, PluginLoadErrorKind::Library, e.to_string()))?;
+        Self::check_version(&library, path)?;
+        let vtable = unsafe {
+            let export: Symbol<ExportPlugin> = library.get(EXPORT_SYMBOL)
+                .map_err(|e| PluginLoadError::new(path, PluginLoadErrorKind::NotAPlugin, e.to_string()))?;
+            export()
+        };
+        //Layout of unknown vtable can't be trusted, instance is leaked instead of dropped
+        if !(MIN_SUPPORTED_API_VERSION..=API_VERSION).contains(&vtable.api_version) {
+            return Err(PluginLoadError::incompatible(path, vtable.api_version));
+        }
+        Ok((unsafe { FfiPlugin::from_vtable(vtable) }, library))
+    }
+
+    //Broken plugin doesn't stop others from loading, every failure is returned separately.
+    //Plugins are loaded after their dependencies
+    pub fn load_all(&self) -> (Vec<LoadedPlugin>, Vec<PluginLoadError>) {
+        let (discovered, mut errors) = self.discover();
+        let manifests: Vec<PluginManifest> = discovered.iter().map(|plugin| plugin.manifest.clone()).collect();
+        let (order, manifest_errors) = resolve_order(&manifests);
+        for (i, e) in manifest_errors {
+            errors.push(PluginLoadError::manifest(&discovered[i], e));
+        }
+
+        let mut plugins: Vec<LoadedPlugin> = vec![];
+        for i in order {
+            let plugin = &discovered[i];
+            let failed_dep = plugin.manifest.dependencies.keys()
+                .find(|id| !plugins.iter().any(|loaded| loaded.manifest().id == **id));
+            if let Some(id) = failed_dep {
+                errors.push(PluginLoadError::manifest(plugin, ManifestError::DependencyFailed(id.clone())));
+                continue;
+            }
+            match self.load(plugin) {
+                Ok(loaded) => plugins.push(loaded),
+                Err(e) => errors.push(e)
+            }
+        }
+        (plugins, errors)
+    }
+}
+
+impl Drop for PluginLoader {
+    //Copies still in use stay behind on Windows, subdirectory of next process with this id clears them
+    fn drop(&mut self) {
+        if let Some(dir) = &self.shadow_dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+//Plugin directories in `dir`. Reads only the file system, so it can run without the loader
+pub fn discover(dir: &Path, host_version: &Version) -> (Vec<DiscoveredPlugin>, Vec<PluginLoadError>) {
+    let mut plugins = vec![];
+    let mut errors = vec![];
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return (plugins, vec![PluginLoadError::new(dir, PluginLoadErrorKind::Io, e.to_string())])
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect();
+    paths.sort();
+    for path in paths {
+        if PluginLoader::is_library(&path) {
+            errors.push(PluginLoadError::new(
+                &path,
+                PluginLoadErrorKind::Manifest,
+                format!("library has no {}, put it in its own directory with a manifest", MANIFEST_FILE),
+            ));
+        } else if path.is_dir() && !PluginLoader::is_hidden(&path) {
+            match discover_dir(&path, host_version) {
+                Ok(plugin) => plugins.push(plugin),
+                Err(e) => errors.push(e)
+            }
+        }
+    }
+    (plugins, errors)
+}
+
+pub fn discover_dir(dir: &Path, host_version: &Version) -> Result<DiscoveredPlugin, PluginLoadError> {
+    let manifest = PluginManifest::read(dir)
+        .map_err(|e| PluginLoadError::new(dir, PluginLoadErrorKind::Manifest, e.to_string()))?;
+    let library = match &manifest.library {
+        Some(library) => dir.join(library),
+        None => {
+            let libraries: Vec<PathBuf> = std::fs::read_dir(dir)
+                .map_err(|e| PluginLoadError::new(dir, PluginLoadErrorKind::Io, e.to_string()))?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| PluginLoader::is_library(path))
+                .collect();
+            if libraries.len() > 1 {
+                let mut e = PluginLoadError::new(dir, PluginLoadErrorKind::Manifest, "plugin directory has several libraries, set `library` in manifest".to_string());
+                e.manifest = Some(Box::new(manifest.clone()));
+                return Err(e);
+            }
+            libraries.into_iter().next().unwrap_or_default()
+        }
+    };
+    let plugin = DiscoveredPlugin {
+        dir: dir.to_path_buf(),
+        library,
+        manifest,
+    };
+    if !plugin.library.is_file() {
+        return Err(PluginLoadError::manifest(&plugin, ManifestError::Io("plugin library not found".to_string())));
+    }
+    plugin.manifest.check_host(host_version).map_err(|e| PluginLoadError::manifest(&plugin, e))?;
+    Ok(plugin)
+}
@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use crate::files::{self, FileHandlerSchema};
+use crate::search::{self, SearchProviderSchema};
+use crate::settings::{self, SettingSchema};
+use crate::ui::UiContributions;
+
+pub const MANIFEST_FILE: &str = "plugin.toml";
+
+//What plugin is allowed to do through the host, declared in manifest and checked by host
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum Capability {
+    Search,
+    FsRead,
+    FsWrite,
+    Events,
+    Notifications,
+    Ui,
+}
+
+//Content of `plugin.toml` placed next to plugin library
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PluginManifest {
+    pub id: String,
+    pub version: Version,
+    pub author: String,
+    #[serde(default)]
+    pub description: String,
+    pub min_host_version: Version,
+    //File name of library, default is the only library in plugin directory
+    #[serde(default)]
+    pub library: Option<String>,
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, VersionReq>,
+    //Settings plugin reads from its config section
+    #[serde(default)]
+    pub settings: Vec<SettingSchema>,
+    //Actions, menu entries and panels plugin adds to the app
+    #[serde(default)]
+    pub ui: UiContributions,
+    //Sources plugin adds to app search
+    #[serde(default)]
+    pub search_providers: Vec<SearchProviderSchema>,
+    //Handlers rendering previews of files they know
+    #[serde(default)]
+    pub previewers: Vec<FileHandlerSchema>,
+    //Handlers adding metadata to indexed files
+    #[serde(default)]
+    pub metadata_extractors: Vec<FileHandlerSchema>,
+}
+
+impl PluginManifest {
+    pub fn parse(con: &str) -> Result<Self, ManifestError> {
+        let manifest: PluginManifest = toml::from_str(con).map_err(|e| ManifestError::Parse(e.to_string()))?;
+        manifest.check_id()?;
+        manifest.check_settings()?;
+        manifest.check_ui()?;
+        manifest.check_search_providers()?;
+        manifest.check_file_handlers()?;
+        Ok(manifest)
+    }
+
+    pub fn read(dir: &Path) -> Result<Self, ManifestError> {
+        let con = std::fs::read_to_string(dir.join(MANIFEST_FILE)).map_err(|e| ManifestError::Io(e.to_string()))?;
+        Self::parse(&con)
+    }
+
+    //Ids are used as config keys and directory names, keep them simple
+    fn check_id(&self) -> Result<(), ManifestError> {
+        let valid = !self.id.is_empty() && self.id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-' || c == '_');
+        if valid {
+            Ok(())
+        } else {
+            Err(ManifestError::InvalidId(self.id.clone()))
+        }
+    }
+
+    fn check_settings(&self) -> Result<(), ManifestError> {
+        match self.settings.iter().find(|setting| settings::is_reserved(&setting.key)) {
+            Some(setting) => Err(ManifestError::InvalidSettings(format!("key `{}` is reserved by host", setting.key))),
+            None => Ok(()),
+        }
+    }
+
+    fn check_ui(&self) -> Result<(), ManifestError> {
+        if self.ui.is_empty() {
+            return Ok(());
+        }
+        if !self.has_capability(Capability::Ui) {
+            return Err(ManifestError::InvalidUi("`ui` section requires `ui` capability".to_string()));
+        }
+        self.ui.check().map_err(ManifestError::InvalidUi)
+    }
+
+    fn check_search_providers(&self) -> Result<(), ManifestError> {
+        if self.search_providers.is_empty() {
+            return Ok(());
+        }
+        if !self.has_capability(Capability::Search) {
+            return Err(ManifestError::InvalidSearchProviders("`search_providers` require `search` capability".to_string()));
+        }
+        search::check_providers(&self.search_providers).map_err(ManifestError::InvalidSearchProviders)
+    }
+
+    fn check_file_handlers(&self) -> Result<(), ManifestError> {
+        if self.previewers.is_empty() && self.metadata_extractors.is_empty() {
+            return Ok(());
+        }
+        if !self.has_capability(Capability::FsRead) {
+            return Err(ManifestError::InvalidFileHandlers("`previewers` and `metadata_extractors` require `fs-read` capability".to_string()));
+        }
+        files::check_handlers(&self.previewers)
+            .and_then(|_| files::check_handlers(&self.metadata_extractors))
+            .map_err(ManifestError::InvalidFileHandlers)
+    }
+
+    pub fn check_host(&self, host_version: &Version) -> Result<(), ManifestError> {
+        if *host_version < self.min_host_version {
+            return Err(ManifestError::HostTooOld {
+                required: self.min_host_version.clone(),
+                host: host_version.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub enum ManifestError {
+    Io(String),
+    Parse(String),
+    InvalidId(String),
+    InvalidSettings(String),
+    InvalidUi(String),
+    InvalidSearchProviders(String),
+    InvalidFileHandlers(String),
+    HostTooOld { required: Version, host: Version },
+    MissingDependency { id: String, requirement: VersionReq },
+    DependencyVersion { id: String, requirement: VersionReq, found: Version },
+    DependencyFailed(String),
+    DependencyCycle(Vec<String>),
+    Duplicate(String),
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManifestError::Io(e) => write!(f, "can't read {}: {}", MANIFEST_FILE, e),
+            ManifestError::Parse(e) => write!(f, "invalid {}: {}", MANIFEST_FILE, e),
+            ManifestError::InvalidId(id) => write!(f, "invalid plugin id `{}`, use lowercase letters, digits, `.`, `-` and `_`", id),
+            ManifestError::InvalidSettings(e) => write!(f, "invalid settings: {}", e),
+            ManifestError::InvalidUi(e) => write!(f, "invalid ui section: {}", e),
+            ManifestError::InvalidSearchProviders(e) => write!(f, "invalid search providers: {}", e),
+            ManifestError::InvalidFileHandlers(e) => write!(f, "invalid file handlers: {}", e),
+            ManifestError::HostTooOld { required, host } => write!(f, "plugin requires app version {} or newer, current is {}", required, host),
+            ManifestError::MissingDependency { id, requirement } => write!(f, "required plugin `{}` {} is not installed", id, requirement),
+            ManifestError::DependencyVersion { id, requirement, found } => write!(f, "required plugin `{}` {} has version {}", id, requirement, found),
+            ManifestError::DependencyFailed(id) => write!(f, "required plugin `{}` failed to load", id),
+            ManifestError::DependencyCycle(ids) => write!(f, "dependency cycle: {}", ids.join(" -> ")),
+            ManifestError::Duplicate(id) => write!(f, "plugin `{}` is installed more than once", id),
+        }
+    }
+}
+
+//Sorts manifests so that every plugin comes after its dependencies.
+//Plugins with unmet dependencies, and everything depending on them, are returned as errors
+pub fn resolve_order(manifests: &[PluginManifest]) -> (Vec<usize>, Vec<(usize, ManifestError)>) {
+    let mut errors: Vec<(usize, ManifestError)> = vec![];
+    let mut by_id: BTreeMap<&str, usize> = BTreeMap::new();
+    for (i, manifest) in manifests.iter().enumerate() {
+        if by_id.contains_key(manifest.id.as_str()) {
+            errors.push((i, ManifestError::Duplicate(manifest.id.clone())));
+        } else {
+            by_id.insert(&manifest.id, i);
+        }
+    }
+
+    let failed = |errors: &Vec<(usize, ManifestError)>, i: usize| errors.iter().any(|(e, _)| *e == i);
+
+    for (i, manifest) in manifests.iter().enumerate() {
+        if failed(&errors, i) {
+            continue;
+        }
+        for (id, requirement) in &manifest.dependencies {
+            match by_id.get(id.as_str()) {
+                None => {
+                    errors.push((i, ManifestError::MissingDependency { id: id.clone(), requirement: requirement.clone() }));
+                    break;
+                }
+                Some(dep) if !requirement.matches(&manifests[*dep].version) => {
+                    errors.push((i, ManifestError::DependencyVersion {
+                        id: id.clone(),
+                        requirement: requirement.clone(),
+                        found: manifests[*dep].version.clone(),
+                    }));
+                    break;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut order = vec![];
+    let mut pending: Vec<usize> = (0..manifests.len()).filter(|i| !failed(&errors, *i)).collect();
+    loop {
+        //Failure of dependency spreads to dependents
+        let mut changed = false;
+        for i in pending.clone() {
+            let broken_dep = manifests[i].dependencies.keys()
+                .find(|id| by_id.get(id.as_str()).is_some_and(|dep| failed(&errors, *dep)));
+            if let Some(id) = broken_dep {
+                errors.push((i, ManifestError::DependencyFailed(id.clone())));
+                pending.retain(|p| *p != i);
+                changed = true;
+            }
+        }
+
+        let ready: Vec<usize> = pending.iter().copied()
+            .filter(|i| manifests[*i].dependencies.keys().all(|id| order.contains(&by_id[id.as_str()])))
+            .collect();
+        if ready.is_empty() && !changed {
+            break;
+        }
+        for i in ready {
+            order.push(i);
+            pending.retain(|p| *p != i);
+        }
+    }
+
+    //Whatever is left waits on each other
+    if !pending.is_empty() {
+        let cycle: Vec<String> = pending.iter().map(|i| manifests[*i].id.clone()).collect();
+        for i in pending {
+            errors.push((i, ManifestError::DependencyCycle(cycle.clone())));
+        }
+    }
+    (order, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(id: &str, version: &str, dependencies: &[(&str, &str)]) -> PluginManifest {
+        let dependencies: String = dependencies.iter().map(|(id, req)| format!("{} = \"{}\"\n", id, req)).collect();
+        PluginManifest::parse(&format!(
+            "id = \"{}\"\nversion = \"{}\"\nauthor = \"test\"\nmin_host_version = \"0.1.0\"\n[dependencies]\n{}",
+            id, version, dependencies
+        )).unwrap()
+    }
+
+    fn ids(manifests: &[PluginManifest], order: &[usize]) -> Vec<String> {
+        order.iter().map(|i| manifests[*i].id.clone()).collect()
+    }
+
+    #[test]
+    fn dependencies_load_first() {
+        let manifests = vec![
+            manifest("sync", "1.0.0", &[("storage", "^1.0")]),
+            manifest("storage", "1.2.0", &[]),
+        ];
+        let (order, errors) = resolve_order(&manifests);
+        assert!(errors.is_empty());
+        assert_eq!(ids(&manifests, &order), vec!["storage", "sync"]);
+    }
+
+    #[test]
+    fn missing_dependency_fails_its_dependents() {
+        let manifests = vec![
+            manifest("sync", "1.0.0", &[("storage", "^1.0")]),
+            manifest("ui", "1.0.0", &[("sync", "^1.0")]),
+            manifest("notes", "1.0.0", &[]),
+        ];
+        let (order, errors) = resolve_order(&manifests);
+        assert_eq!(ids(&manifests, &order), vec!["notes"]);
+        assert!(matches!(&errors[0], (0, ManifestError::MissingDependency { id, .. }) if id == "storage"));
+        assert!(matches!(&errors[1], (1, ManifestError::DependencyFailed(id)) if id == "sync"));
+    }
+
+    #[test]
+    fn wrong_dependency_version_is_reported() {
+        let manifests = vec![
+            manifest("sync", "1.0.0", &[("storage", "^2.0")]),
+            manifest("storage", "1.2.0", &[]),
+        ];
+        let (order, errors) = resolve_order(&manifests);
+        assert_eq!(ids(&manifests, &order), vec!["storage"]);
+        assert!(matches!(&errors[0], (0, ManifestError::DependencyVersion { found, .. }) if *found == Version::new(1, 2, 0)));
+    }
+
+    #[test]
+    fn cycle_is_reported_for_every_member() {
+        let manifests = vec![
+            manifest("a", "1.0.0", &[("b", "^1.0")]),
+            manifest("b", "1.0.0", &[("a", "^1.0")]),
+            manifest("c", "1.0.0", &[]),
+        ];
+        let (order, errors) = resolve_order(&manifests);
+        assert_eq!(ids(&manifests, &order), vec!["c"]);
+        assert_eq!(errors.len(), 2);
+        for (_, error) in &errors {
+            assert!(matches!(error, ManifestError::DependencyCycle(cycle) if *cycle == vec!["a".to_string(), "b".to_string()]));
+        }
+    }
+
+    #[test]
+    fn duplicate_id_is_loaded_once() {
+        let manifests = vec![manifest("notes", "1.0.0", &[]), manifest("notes", "1.1.0", &[])];
+        let (order, errors) = resolve_order(&manifests);
+        assert_eq!(order, vec![0]);
+        assert!(matches!(&errors[0], (1, ManifestError::Duplicate(id)) if id == "notes"));
+    }
+}
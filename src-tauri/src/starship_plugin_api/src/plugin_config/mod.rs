@@ -1,6 +1,63 @@
+use std::collections::HashMap;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use crate::api::{PluginError, PluginResult};
 
-#[derive(Serialize, Deserialize)]
+//Keys of config section read by host itself, plugin settings can't use them
+pub const RESERVED_KEYS: &[&str] = &["secrets"];
+
+//Section `[plugins_conf.<plugin id>]` of app config
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug, Default)]
 pub struct PluginConfig {
+    //Names of secrets plugin needs, values are filled by host from secrets store
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<String>,
+    //Plugin own settings, described by `settings` of plugin manifest
+    #[serde(flatten)]
+    pub settings: toml::Table,
+    #[serde(skip)]
+    resolved_secrets: HashMap<String, String>,
+}
+
+//Config with resolved secrets as it is passed to plugin library
+#[derive(Serialize, Deserialize)]
+struct ConfigTransfer {
+    config: PluginConfig,
+    secrets: HashMap<String, String>,
+}
+
+impl PluginConfig {
+    pub fn secret(&self, name: &str) -> Option<&str> {
+        self.resolved_secrets.get(name).map(|value| value.as_str())
+    }
+
+    pub fn set_secret(&mut self, name: &str, value: &str) {
+        self.resolved_secrets.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn value(&self, key: &str) -> Option<&toml::Value> {
+        self.settings.get(key)
+    }
+
+    //Settings as plugin own type
+    pub fn parse<T: DeserializeOwned>(&self) -> PluginResult<T> {
+        toml::Value::Table(self.settings.clone())
+            .try_into()
+            .map_err(|e: toml::de::Error| PluginError::new(format!("invalid settings: {}", e)))
+    }
+
+    pub(crate) fn to_transfer(&self) -> String {
+        let transfer = ConfigTransfer {
+            config: self.clone(),
+            secrets: self.resolved_secrets.clone(),
+        };
+        serde_json::to_string(&transfer).unwrap_or_default()
+    }
 
-}
\ No newline at end of file
+    pub(crate) fn from_transfer(con: &str) -> Result<Self, String> {
+        let transfer: ConfigTransfer = serde_json::from_str(con).map_err(|e| e.to_string())?;
+        let mut config = transfer.config;
+        config.resolved_secrets = transfer.secrets;
+        Ok(config)
+    }
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::api::{PluginError, PluginResult};
+
+//Extra search source, e.g. bookmarks or app launchers. App calls `action` with `SearchQuery`
+//as arguments, action returns list of `SearchItem` ordered from best match
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SearchProviderSchema {
+    pub id: String,
+    //Shown next to results of this provider
+    pub title: String,
+    #[serde(default)]
+    pub icon: Option<String>,
+    pub action: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SearchQuery {
+    pub query: String,
+    pub limit: usize,
+}
+
+impl SearchQuery {
+    //Arguments of search provider action
+    pub fn parse(args: &serde_json::Value) -> PluginResult<Self> {
+        serde_json::from_value(args.clone()).map_err(|e| PluginError::new(format!("invalid search query: {}", e)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SearchItem {
+    pub title: String,
+    #[serde(default)]
+    pub detail: String,
+    //Item opens this path when chosen
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    //Relevance from 0 to 1, items without it are ranked by their position
+    #[serde(default)]
+    pub score: Option<f64>,
+}
+
+//Search providers section of manifest, requires `search` capability
+pub fn check_providers(providers: &[SearchProviderSchema]) -> Result<(), String> {
+    for (i, provider) in providers.iter().enumerate() {
+        if provider.id.is_empty() || provider.id.contains('/') {
+            return Err(format!("invalid search provider id `{}`", provider.id));
+        }
+        if providers[..i].iter().any(|other| other.id == provider.id) {
+            return Err(format!("search provider `{}` is declared twice", provider.id));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+use std::fmt::{Display, Formatter};
+use serde::{Deserialize, Serialize};
+use crate::plugin_config::RESERVED_KEYS;
+
+//Description of one plugin setting, declared in `[[settings]]` of plugin manifest.
+//UI renders settings form from it and host checks config against it before delivery
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SettingSchema {
+    pub key: String,
+    #[serde(default)]
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(default)]
+    pub default: Option<toml::Value>,
+    #[serde(flatten)]
+    pub kind: SettingKind,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SettingKind {
+    String,
+    Integer {
+        #[serde(default)]
+        min: Option<i64>,
+        #[serde(default)]
+        max: Option<i64>,
+    },
+    Float {
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    Boolean,
+    Enum { options: Vec<String> },
+    Path,
+    List,
+}
+
+impl SettingKind {
+    fn check(&self, value: &toml::Value) -> Result<(), String> {
+        match (self, value) {
+            (SettingKind::String, toml::Value::String(_)) | (SettingKind::Path, toml::Value::String(_)) => Ok(()),
+            (SettingKind::Boolean, toml::Value::Boolean(_)) => Ok(()),
+            (SettingKind::List, toml::Value::Array(_)) => Ok(()),
+            (SettingKind::Integer { min, max }, toml::Value::Integer(value)) => check_range(*value, *min, *max),
+            (SettingKind::Float { min, max }, toml::Value::Float(value)) => check_range(*value, *min, *max),
+            (SettingKind::Float { min, max }, toml::Value::Integer(value)) => check_range(*value as f64, *min, *max),
+            (SettingKind::Enum { options }, toml::Value::String(value)) => {
+                if options.contains(value) {
+                    Ok(())
+                } else {
+                    Err(format!("must be one of {}", options.join(", ")))
+                }
+            }
+            (kind, value) => Err(format!("expected {}, found {}", kind.name(), value.type_str())),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            SettingKind::String => "string",
+            SettingKind::Integer { .. } => "integer",
+            SettingKind::Float { .. } => "float",
+            SettingKind::Boolean => "boolean",
+            SettingKind::Enum { .. } => "one of options",
+            SettingKind::Path => "path",
+            SettingKind::List => "list",
+        }
+    }
+}
+
+fn check_range<T: PartialOrd + Display>(value: T, min: Option<T>, max: Option<T>) -> Result<(), String> {
+    match (min, max) {
+        (Some(min), _) if value < min => Err(format!("must be at least {}", min)),
+        (_, Some(max)) if value > max => Err(format!("must be at most {}", max)),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SettingError {
+    pub key: String,
+    pub message: String,
+}
+
+impl Display for SettingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "setting `{}` {}", self.key, self.message)
+    }
+}
+
+//Every problem of settings, unknown keys are errors only when plugin declares a schema
+pub fn validate(schema: &[SettingSchema], settings: &toml::Table) -> Vec<SettingError> {
+    let mut errors = vec![];
+    for key in settings.keys().filter(|key| is_reserved(key)) {
+        errors.push(SettingError {
+            key: key.clone(),
+            message: "is reserved by host".to_string(),
+        });
+    }
+    for setting in schema {
+        match settings.get(&setting.key) {
+            Some(value) => {
+                if let Err(message) = setting.kind.check(value) {
+                    errors.push(SettingError { key: setting.key.clone(), message });
+                }
+            }
+            None if setting.required && setting.default.is_none() => errors.push(SettingError {
+                key: setting.key.clone(),
+                message: "is required".to_string(),
+            }),
+            None => {}
+        }
+    }
+    if !schema.is_empty() {
+        for key in settings.keys().filter(|key| !is_reserved(key) && !schema.iter().any(|setting| setting.key == **key)) {
+            errors.push(SettingError {
+                key: key.clone(),
+                message: "is not declared by plugin".to_string(),
+            });
+        }
+    }
+    errors
+}
+
+pub fn is_reserved(key: &str) -> bool {
+    RESERVED_KEYS.contains(&key)
+}
+
+//Settings with defaults filled for missing keys
+pub fn with_defaults(schema: &[SettingSchema], settings: &toml::Table) -> toml::Table {
+    let mut settings = settings.clone();
+    for setting in schema {
+        if let Some(default) = &setting.default {
+            settings.entry(setting.key.clone()).or_insert_with(|| default.clone());
+        }
+    }
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Vec<SettingSchema> {
+        toml::from_str::<toml::Table>("[[settings]]\nkey = \"folder\"\ntype = \"path\"\nrequired = true\n[[settings]]\nkey = \"depth\"\ntype = \"integer\"\nmin = 1\ndefault = 3\n")
+            .unwrap()["settings"].clone().try_into().unwrap()
+    }
+
+    fn table(con: &str) -> toml::Table {
+        toml::from_str(con).unwrap()
+    }
+
+    #[test]
+    fn valid_settings() {
+        assert!(validate(&schema(), &table("folder = \"docs\"\ndepth = 2")).is_empty());
+        assert_eq!(with_defaults(&schema(), &table("folder = \"docs\"")).get("depth"), Some(&toml::Value::Integer(3)));
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let errors = validate(&schema(), &table("depth = 0\nextra = true"));
+        let keys: Vec<&str> = errors.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["folder", "depth", "extra"]);
+    }
+
+    #[test]
+    fn reserved_keys_are_refused() {
+        let errors = validate(&[], &table("secrets = \"token\""));
+        assert_eq!(errors, vec![SettingError { key: "secrets".to_string(), message: "is reserved by host".to_string() }]);
+        assert_eq!(validate(&schema(), &table("folder = \"docs\"\nsecrets = []")).len(), 1);
+    }
+}
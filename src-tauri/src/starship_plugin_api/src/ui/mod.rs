@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+
+//Named action plugin handles in `on_action`, frontend runs it with JSON arguments
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ActionSchema {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub icon: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MenuTarget {
+    File,
+    Dir,
+    #[default]
+    Any,
+}
+
+//Entry in context menu of files, action gets `{"paths": [...]}` with selected paths
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ContextMenuEntry {
+    pub action: String,
+    #[serde(default)]
+    pub target: MenuTarget,
+    //Shown only for files with one of these extensions, empty means all
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+//Button in toolbar, action gets `{"dir": ...}` with currently opened directory
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ToolbarEntry {
+    pub action: String,
+}
+
+//Sidebar panel, its content is whatever `action` returns as `PanelContent`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct PanelSchema {
+    pub id: String,
+    pub title: String,
+    #[serde(default)]
+    pub icon: Option<String>,
+    pub action: String,
+}
+
+//`[ui]` section of plugin manifest, requires `ui` capability
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UiContributions {
+    #[serde(default)]
+    pub actions: Vec<ActionSchema>,
+    #[serde(default)]
+    pub context_menu: Vec<ContextMenuEntry>,
+    #[serde(default)]
+    pub toolbar: Vec<ToolbarEntry>,
+    #[serde(default)]
+    pub panels: Vec<PanelSchema>,
+}
+
+impl UiContributions {
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty() && self.context_menu.is_empty() && self.toolbar.is_empty() && self.panels.is_empty()
+    }
+
+    pub fn action(&self, id: &str) -> Option<&ActionSchema> {
+        self.actions.iter().find(|action| action.id == id)
+    }
+
+    pub fn panel(&self, id: &str) -> Option<&PanelSchema> {
+        self.panels.iter().find(|panel| panel.id == id)
+    }
+
+    //Menu, toolbar and panel entries may only point to declared actions
+    pub fn check(&self) -> Result<(), String> {
+        for (i, action) in self.actions.iter().enumerate() {
+            if self.actions[..i].iter().any(|other| other.id == action.id) {
+                return Err(format!("action `{}` is declared twice", action.id));
+            }
+        }
+        let used = self.context_menu.iter().map(|entry| &entry.action)
+            .chain(self.toolbar.iter().map(|entry| &entry.action))
+            .chain(self.panels.iter().map(|panel| &panel.action));
+        for action in used {
+            if self.action(action).is_none() {
+                return Err(format!("action `{}` is not declared in ui.actions", action));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PanelItem {
+    pub label: String,
+    #[serde(default)]
+    pub detail: String,
+    //Item opens this path when clicked
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+}
+
+//What panel action returns, frontend renders it without running plugin code
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PanelContent {
+    Markdown { text: String },
+    List { items: Vec<PanelItem> },
+}
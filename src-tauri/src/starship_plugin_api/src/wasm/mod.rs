@@ -0,0 +1,228 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use wasmtime::component::{Component, Linker, ResourceTable};
+use wasmtime::{Config, Engine, Store, StoreLimits, StoreLimitsBuilder, Trap};
+use wasmtime_wasi::{IoView, WasiCtx, WasiCtxBuilder, WasiView};
+use crate::api::{PluginError, PluginResult, StarShipPluginAPI};
+use crate::context::{failed_response, HostCall, HostContext, HostEvent};
+use crate::plugin_config::PluginConfig;
+
+wasmtime::component::bindgen!({
+    path: "wit",
+    world: "starship-plugin",
+});
+
+use exports::starship::plugin::plugin::HostInfo;
+
+//Limits applied to every WASM plugin instance
+#[derive(Clone, Copy, Debug)]
+pub struct WasmLimits {
+    //Linear memory plugin may grow to
+    pub memory_bytes: usize,
+    //Fuel is refilled before every call, plugin that runs out is stopped
+    pub fuel_per_call: u64,
+    //Wall time of one call, also stops plugin blocked in host calls that use no fuel
+    pub time_per_call: Duration,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            memory_bytes: 64 << 20,
+            fuel_per_call: 1_000_000_000,
+            time_per_call: Duration::from_secs(10),
+        }
+    }
+}
+
+struct WasmState {
+    wasi: WasiCtx,
+    table: ResourceTable,
+    limits: StoreLimits,
+    host: Option<Arc<dyn HostCall>>,
+}
+
+impl IoView for WasmState {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+}
+
+impl WasiView for WasmState {
+    fn ctx(&mut self) -> &mut WasiCtx {
+        &mut self.wasi
+    }
+}
+
+impl starship::plugin::host::Host for WasmState {
+    fn call(&mut self, request: String) -> String {
+        match &self.host {
+            Some(host) => host.call(&request),
+            None => failed_response("host is not available before on_load"),
+        }
+    }
+}
+
+//Engine epoch is advanced this often, call deadlines are counted in these ticks
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+fn deadline_ticks(time: Duration) -> u64 {
+    (time.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64
+}
+
+//Ticks while engine is alive, plugins keep engine through their stores
+fn start_epoch_ticker(engine: &Engine) {
+    let engine = engine.weak();
+    std::thread::spawn(move || {
+        while let Some(engine) = engine.upgrade() {
+            engine.increment_epoch();
+            drop(engine);
+            std::thread::sleep(EPOCH_TICK);
+        }
+    });
+}
+
+//Engine and imports shared by all WASM plugins, every plugin gets its own store
+pub struct WasmRuntime {
+    engine: Engine,
+    linker: Linker<WasmState>,
+    limits: WasmLimits,
+}
+
+impl WasmRuntime {
+    pub fn new(limits: WasmLimits) -> Result<Self, String> {
+        let mut config = Config::new();
+        config.wasm_component_model(true);
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+        let engine = Engine::new(&config).map_err(|e| e.to_string())?;
+        start_epoch_ticker(&engine);
+        let mut linker = Linker::new(&engine);
+        wasmtime_wasi::add_to_linker_sync(&mut linker).map_err(|e| e.to_string())?;
+        StarshipPlugin::add_to_linker(&mut linker, |state: &mut WasmState| state).map_err(|e| e.to_string())?;
+        Ok(Self { engine, linker, limits })
+    }
+
+    pub fn limits(&self) -> &WasmLimits {
+        &self.limits
+    }
+
+    //Plugin gets WASI without preopened directories, environment or network,
+    //files are reachable only through host API
+    pub fn load(&self, path: &Path) -> Result<WasmPlugin, String> {
+        let component = Component::from_file(&self.engine, path).map_err(|e| e.to_string())?;
+        let state = WasmState {
+            wasi: WasiCtxBuilder::new().inherit_stderr().build(),
+            table: ResourceTable::new(),
+            limits: StoreLimitsBuilder::new().memory_size(self.limits.memory_bytes).build(),
+            host: None,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.limits.fuel_per_call).map_err(|e| e.to_string())?;
+        store.set_epoch_deadline(deadline_ticks(self.limits.time_per_call));
+        let bindings = StarshipPlugin::instantiate(&mut store, &component, &self.linker).map_err(|e| format!("{:#}", e))?;
+        let name = bindings.starship_plugin_plugin().call_name(&mut store).map_err(|e| trap_message(&e))?;
+        Ok(WasmPlugin {
+            name,
+            fuel_per_call: self.limits.fuel_per_call,
+            deadline_ticks: deadline_ticks(self.limits.time_per_call),
+            instance: Mutex::new(WasmInstance { store, bindings, trapped: None }),
+        })
+    }
+}
+
+struct WasmInstance {
+    store: Store<WasmState>,
+    bindings: StarshipPlugin,
+    //Instance state after trap is undefined, it isn't called again
+    trapped: Option<String>,
+}
+
+//Host side of WASM component, behaves like plugin implemented in host itself
+pub struct WasmPlugin {
+    name: String,
+    fuel_per_call: u64,
+    deadline_ticks: u64,
+    instance: Mutex<WasmInstance>,
+}
+
+fn trap_message(e: &wasmtime::Error) -> String {
+    match e.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => "plugin exceeded CPU limit".to_string(),
+        Some(Trap::Interrupt) => "plugin exceeded time limit".to_string(),
+        Some(Trap::UnreachableCodeReached) => "plugin panicked".to_string(),
+        _ => format!("plugin crashed: {:#}", e)
+    }
+}
+
+impl WasmPlugin {
+    fn call<T>(&self, f: impl FnOnce(&StarshipPlugin, &mut Store<WasmState>) -> wasmtime::Result<T>) -> PluginResult<T> {
+        let mut instance = self.instance.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(trap) = &instance.trapped {
+            return Err(PluginError::new(format!("plugin is stopped: {}", trap)));
+        }
+        let WasmInstance { store, bindings, trapped } = &mut *instance;
+        store.set_fuel(self.fuel_per_call).map_err(|e| PluginError::new(e.to_string()))?;
+        store.set_epoch_deadline(self.deadline_ticks);
+        f(bindings, store).map_err(|e| {
+            let message = trap_message(&e);
+            *trapped = Some(message.clone());
+            PluginError::new(message)
+        })
+    }
+
+    fn call_hook(&self, f: impl FnOnce(&StarshipPlugin, &mut Store<WasmState>) -> wasmtime::Result<Result<(), String>>) -> PluginResult {
+        self.call(f)?.map_err(PluginError::new)
+    }
+}
+
+impl StarShipPluginAPI for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn execute(&self) -> PluginResult {
+        self.call_hook(|plugin, store| plugin.starship_plugin_plugin().call_execute(store))
+    }
+
+    fn on_load(&mut self, host: HostContext) -> PluginResult {
+        let info = HostInfo {
+            plugin_id: host.plugin_id().to_string(),
+            host_version: host.host_version().to_string(),
+            plugin_dir: host.plugin_dir().to_string_lossy().to_string(),
+        };
+        self.instance.get_mut().unwrap_or_else(|e| e.into_inner()).store.data_mut().host = Some(host.host().clone());
+        self.call_hook(|plugin, store| plugin.starship_plugin_plugin().call_on_load(store, &info))
+    }
+
+    fn on_enable(&mut self) -> PluginResult {
+        self.call_hook(|plugin, store| plugin.starship_plugin_plugin().call_on_enable(store))
+    }
+
+    fn on_disable(&mut self) -> PluginResult {
+        self.call_hook(|plugin, store| plugin.starship_plugin_plugin().call_on_disable(store))
+    }
+
+    fn on_config_changed(&mut self, config: &PluginConfig) -> PluginResult {
+        let config = config.to_transfer();
+        self.call_hook(|plugin, store| plugin.starship_plugin_plugin().call_on_config_changed(store, &config))
+    }
+
+    fn on_event(&mut self, event: &HostEvent) -> PluginResult {
+        let event = serde_json::to_string(event).map_err(|e| PluginError::new(e.to_string()))?;
+        self.call_hook(|plugin, store| plugin.starship_plugin_plugin().call_on_event(store, &event))
+    }
+
+    fn on_action(&mut self, action: &str, args: &serde_json::Value) -> PluginResult<serde_json::Value> {
+        let args = serde_json::to_string(args).map_err(|e| PluginError::new(e.to_string()))?;
+        let value = self.call(|plugin, store| plugin.starship_plugin_plugin().call_on_action(store, action, &args))?
+            .map_err(PluginError::new)?;
+        serde_json::from_str(&value).map_err(|e| PluginError::new(format!("invalid action result: {}", e)))
+    }
+
+    fn on_unload(&mut self) -> PluginResult {
+        self.call_hook(|plugin, store| plugin.starship_plugin_plugin().call_on_unload(store))
+    }
+}
@@ -0,0 +1,41 @@
+//Plugin the harness tests load: `backup` action writes `<path>.bak` with content of `path`
+use std::path::PathBuf;
+use starship_plugin_api::api::{PluginError, PluginResult, StarShipPluginAPI};
+use starship_plugin_api::context::HostContext;
+use starship_plugin_api::export_plugin;
+
+#[derive(Default)]
+pub struct BackupPlugin {
+    host: Option<HostContext>,
+}
+
+impl StarShipPluginAPI for BackupPlugin {
+    fn name(&self) -> &str {
+        "backup"
+    }
+
+    fn execute(&self) -> PluginResult {
+        Ok(())
+    }
+
+    fn on_load(&mut self, host: HostContext) -> PluginResult {
+        host.info("backup loaded");
+        self.host = Some(host);
+        Ok(())
+    }
+
+    fn on_action(&mut self, action: &str, args: &serde_json::Value) -> PluginResult<serde_json::Value> {
+        if action != "backup" {
+            return Err(PluginError::new(format!("unknown action `{}`", action)));
+        }
+        let host = self.host.as_ref().ok_or("plugin is not loaded")?;
+        let path = args.get("path").and_then(|path| path.as_str()).map(PathBuf::from).ok_or("`path` is missing")?;
+        let data = host.read_file(&path)?;
+        let mut backup = path.into_os_string();
+        backup.push(".bak");
+        host.write_file(&PathBuf::from(backup), &data)?;
+        Ok(serde_json::json!({ "bytes": data.len() }))
+    }
+}
+
+export_plugin!(BackupPlugin);
@@ -0,0 +1,279 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use semver::Version;
+use starship_plugin_api::api::PluginError;
+use starship_plugin_api::context::{HostCall, HostContext, HostEvent, HostRequest, LogLevel};
+use starship_plugin_api::host::HostDispatcher;
+use starship_plugin_api::isolation::IsolationPolicy;
+use starship_plugin_api::loader::{LoadedPlugin, PluginLoadError, PluginLoader};
+use starship_plugin_api::manifest::{PluginManifest, MANIFEST_FILE};
+use starship_plugin_api::plugin_config::PluginConfig;
+use starship_plugin_api::settings::{self, SettingError};
+use crate::mock::MockHost;
+
+static TEST_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+//Library cargo built for plugin crate in `crate_dir`, found next to test executable in `target/<profile>/deps`.
+//Plugin crate needs `crate-type = ["cdylib", "rlib"]`, with `cdylib` alone `cargo test` doesn't rebuild the library
+pub fn built_library(crate_dir: &Path) -> Result<PathBuf, HarnessError> {
+    let cargo = std::fs::read_to_string(crate_dir.join("Cargo.toml")).map_err(|e| HarnessError::Io(e.to_string()))?;
+    let cargo: toml::Table = toml::from_str(&cargo).map_err(|e| HarnessError::Library(format!("invalid Cargo.toml: {}", e)))?;
+    let name = cargo.get("lib").and_then(|lib| lib.get("name"))
+        .or_else(|| cargo.get("package").and_then(|package| package.get("name")))
+        .and_then(|name| name.as_str())
+        .ok_or_else(|| HarnessError::Library("Cargo.toml has no package name".to_string()))?;
+    let file = format!("{}{}.{}", std::env::consts::DLL_PREFIX, name.replace('-', "_"), std::env::consts::DLL_EXTENSION);
+    let exe = std::env::current_exe().map_err(|e| HarnessError::Io(e.to_string()))?;
+    exe.ancestors().skip(1).take(2)
+        .map(|dir| dir.join(&file))
+        .find(|library| library.is_file())
+        .ok_or_else(|| HarnessError::Library(format!("{} is not built, set `crate-type = [\"cdylib\", \"rlib\"]` in plugin crate", file)))
+}
+
+//Loads plugin the way the app does: library and manifest are put in a plugin directory,
+//found and loaded by `PluginLoader`, then get `on_load`, config and `on_enable`
+pub struct PluginHarness {
+    crate_dir: PathBuf,
+    library: Option<PathBuf>,
+    host: MockHost,
+    config: PluginConfig,
+    host_version: Version,
+    call_timeout: Duration,
+}
+
+impl PluginHarness {
+    //`crate_dir` has `plugin.toml`, usually `env!("CARGO_MANIFEST_DIR")`
+    pub fn new(crate_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            crate_dir: crate_dir.into(),
+            library: None,
+            host: MockHost::new(),
+            config: PluginConfig::default(),
+            host_version: Version::new(u64::MAX, 0, 0),
+            call_timeout: IsolationPolicy::default().call_timeout,
+        }
+    }
+
+    //Library to load instead of the one cargo built, e.g. WASM component
+    pub fn with_library(mut self, library: impl Into<PathBuf>) -> Self {
+        self.library = Some(library.into());
+        self
+    }
+
+    pub fn with_host(mut self, host: MockHost) -> Self {
+        self.host = host;
+        self
+    }
+
+    //Settings as in `[plugins_conf.<id>]` of app config, checked against manifest like the app does
+    pub fn with_config(mut self, config: PluginConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    //Default is newer than any `min_host_version`
+    pub fn with_host_version(mut self, version: Version) -> Self {
+        self.host_version = version;
+        self
+    }
+
+    pub fn with_call_timeout(mut self, timeout: Duration) -> Self {
+        self.call_timeout = timeout;
+        self
+    }
+
+    pub fn load(self) -> Result<TestPlugin, HarnessError> {
+        let library = match self.library {
+            Some(library) => library,
+            None => built_library(&self.crate_dir)?,
+        };
+        let manifest = PluginManifest::read(&self.crate_dir).map_err(|e| HarnessError::Manifest(e.to_string()))?;
+        let root = std::env::temp_dir().join(format!("starship-plugin-test-{}-{}", std::process::id(), TEST_DIRS.fetch_add(1, Ordering::Relaxed)));
+        let dir = root.join(&manifest.id);
+        let copied = std::fs::create_dir_all(&dir)
+            .and_then(|_| std::fs::copy(self.crate_dir.join(MANIFEST_FILE), dir.join(MANIFEST_FILE)))
+            .and_then(|_| std::fs::copy(&library, dir.join(library.file_name().unwrap_or_default())));
+        let mut test = TestPlugin {
+            plugin: None,
+            host: Arc::new(self.host),
+            dispatcher: None,
+            requests: Arc::new(Mutex::new(vec![])),
+            enabled: false,
+            root,
+        };
+        copied.map_err(|e| HarnessError::Io(e.to_string()))?;
+
+        let loader = PluginLoader::new(test.root.clone(), self.host_version.clone()).with_isolation(IsolationPolicy {
+            call_timeout: self.call_timeout,
+            ..IsolationPolicy::default()
+        });
+        let mut plugin = loader.discover_dir(&dir).and_then(|plugin| loader.load(&plugin)).map_err(HarnessError::Load)?;
+        let dispatcher = Arc::new(HostDispatcher::new(plugin.manifest(), test.host.clone()));
+        let recorder = Arc::new(RecordingHost {
+            dispatcher: dispatcher.clone(),
+            requests: test.requests.clone(),
+        });
+        let context = HostContext::new(&plugin.manifest().id, &self.host_version.to_string(), plugin.dir(), recorder);
+        let loaded = plugin.on_load(context);
+        test.plugin = Some(plugin);
+        test.dispatcher = Some(dispatcher);
+        loaded.map_err(|error| HarnessError::Hook { hook: "on_load", error })?;
+        test.configure(self.config)?;
+        test.enable()?;
+        Ok(test)
+    }
+}
+
+//Records what plugin asked from host before passing it on
+struct RecordingHost {
+    dispatcher: Arc<HostDispatcher>,
+    requests: Arc<Mutex<Vec<HostRequest>>>,
+}
+
+impl HostCall for RecordingHost {
+    fn call(&self, request: &str) -> String {
+        if let Ok(parsed) = serde_json::from_str::<HostRequest>(request) {
+            self.requests.lock().unwrap_or_else(|e| e.into_inner()).push(parsed);
+        }
+        self.dispatcher.call(request)
+    }
+}
+
+//Loaded plugin with its mock host. Dropping it disables and unloads plugin
+pub struct TestPlugin {
+    //Always set once `load` returned
+    plugin: Option<LoadedPlugin>,
+    host: Arc<MockHost>,
+    dispatcher: Option<Arc<HostDispatcher>>,
+    requests: Arc<Mutex<Vec<HostRequest>>>,
+    enabled: bool,
+    root: PathBuf,
+}
+
+impl TestPlugin {
+    fn plugin(&mut self) -> &mut LoadedPlugin {
+        self.plugin.as_mut().expect("plugin is loaded")
+    }
+
+    pub fn manifest(&self) -> &PluginManifest {
+        self.plugin.as_ref().expect("plugin is loaded").manifest()
+    }
+
+    //Filesystem, search index and captured events and notifications
+    pub fn host(&self) -> &MockHost {
+        &self.host
+    }
+
+    //Every host call plugin made, including denied ones and logs
+    pub fn requests(&self) -> Vec<HostRequest> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn logs(&self) -> Vec<(LogLevel, String)> {
+        self.requests().into_iter()
+            .filter_map(|request| match request {
+                HostRequest::Log { level, message } => Some((level, message)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn enable(&mut self) -> Result<(), HarnessError> {
+        if self.enabled {
+            return Ok(());
+        }
+        self.plugin().on_enable().map_err(|error| HarnessError::Hook { hook: "on_enable", error })?;
+        self.enabled = true;
+        Ok(())
+    }
+
+    //Plugin counts as disabled even if `on_disable` fails, as in the app
+    pub fn disable(&mut self) -> Result<(), HarnessError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        self.enabled = false;
+        self.plugin().on_disable().map_err(|error| HarnessError::Hook { hook: "on_disable", error })
+    }
+
+    //Invalid settings never reach plugin, defaults of manifest are filled in
+    pub fn configure(&mut self, mut config: PluginConfig) -> Result<(), HarnessError> {
+        let schema = &self.manifest().settings;
+        let errors = settings::validate(schema, &config.settings);
+        if !errors.is_empty() {
+            return Err(HarnessError::InvalidSettings(errors));
+        }
+        config.settings = settings::with_defaults(schema, &config.settings);
+        self.plugin().on_config_changed(&config).map_err(|error| HarnessError::Hook { hook: "on_config_changed", error })
+    }
+
+    pub fn action(&mut self, action: &str, args: serde_json::Value) -> Result<serde_json::Value, HarnessError> {
+        self.plugin().on_action(action, &args).map_err(|error| HarnessError::Hook { hook: "on_action", error })
+    }
+
+    //Event is delivered only if plugin subscribed to its kind, returns whether it was
+    pub fn send_event(&mut self, event: &HostEvent) -> Result<bool, HarnessError> {
+        let subscribed = self.dispatcher.as_ref().is_some_and(|dispatcher| dispatcher.is_subscribed(event));
+        if !subscribed {
+            return Ok(false);
+        }
+        self.plugin().on_event(event).map_err(|error| HarnessError::Hook { hook: "on_event", error })?;
+        Ok(true)
+    }
+
+    //Deliver events caused by plugin's own host calls, as the app would
+    pub fn send_host_events(&mut self) -> Result<(), HarnessError> {
+        for event in self.host.take_events() {
+            self.send_event(&event)?;
+        }
+        Ok(())
+    }
+
+    //Plugin itself, for hooks not covered above
+    pub fn loaded(&mut self) -> &mut LoadedPlugin {
+        self.plugin()
+    }
+}
+
+impl Drop for TestPlugin {
+    fn drop(&mut self) {
+        if let Some(mut plugin) = self.plugin.take() {
+            if self.enabled {
+                let _ = plugin.on_disable();
+            }
+            let _ = plugin.on_unload();
+        }
+        let _ = std::fs::remove_dir_all(&self.root);
+    }
+}
+
+#[derive(Debug)]
+pub enum HarnessError {
+    Io(String),
+    Library(String),
+    Manifest(String),
+    Load(PluginLoadError),
+    InvalidSettings(Vec<SettingError>),
+    Hook { hook: &'static str, error: PluginError },
+}
+
+impl Display for HarnessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HarnessError::Io(e) => write!(f, "{}", e),
+            HarnessError::Library(e) => write!(f, "{}", e),
+            HarnessError::Manifest(e) => write!(f, "{}", e),
+            HarnessError::Load(e) => write!(f, "{}", e),
+            HarnessError::InvalidSettings(errors) => {
+                let errors: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+                write!(f, "settings are invalid: {}", errors.join("; "))
+            }
+            HarnessError::Hook { hook, error } => write!(f, "{} failed: {}", hook, error),
+        }
+    }
+}
+
+impl std::error::Error for HarnessError {}
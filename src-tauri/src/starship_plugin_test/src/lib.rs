@@ -0,0 +1,14 @@
+//Test support for plugin crates: loads plugin with the real loader against a mock host.
+//Plugin crate sets `crate-type = ["cdylib", "rlib"]` and adds this crate to `dev-dependencies`,
+//then in its `tests/*.rs`:
+//
+//    let mut plugin = PluginHarness::new(env!("CARGO_MANIFEST_DIR"))
+//        .with_host(MockHost::new().with_file("/notes/todo.txt", "buy milk"))
+//        .load()?;
+//    let result = plugin.action("count", serde_json::json!({"paths": ["/notes/todo.txt"]}))?;
+//    assert_eq!(plugin.host().events(), vec![]);
+pub mod harness;
+pub mod mock;
+
+pub use harness::{PluginHarness, TestPlugin, HarnessError};
+pub use mock::MockHost;
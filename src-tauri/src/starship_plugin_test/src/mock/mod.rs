@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use starship_plugin_api::context::{FileEntry, HostEvent, Notification, SearchHit};
+use starship_plugin_api::host::HostServices;
+
+#[derive(Clone, Debug)]
+enum Node {
+    File(Vec<u8>),
+    Dir,
+}
+
+#[derive(Default)]
+struct State {
+    fs: BTreeMap<PathBuf, Node>,
+    documents: Vec<SearchHit>,
+    events: Vec<HostEvent>,
+    notifications: Vec<Notification>,
+}
+
+//Host services without the app: in-memory filesystem and search index.
+//Changes made through it are published as events the way the app does it
+#[derive(Default)]
+pub struct MockHost {
+    state: Mutex<State>,
+}
+
+impl MockHost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //Missing parent directories are created
+    pub fn with_file(self, path: impl AsRef<Path>, data: impl Into<Vec<u8>>) -> Self {
+        {
+            let mut state = self.state();
+            let path = path.as_ref();
+            if let Some(parent) = path.parent() {
+                state.create_dirs(parent);
+            }
+            state.fs.insert(path.to_path_buf(), Node::File(data.into()));
+        }
+        self
+    }
+
+    pub fn with_dir(self, path: impl AsRef<Path>) -> Self {
+        self.state().create_dirs(path.as_ref());
+        self
+    }
+
+    //Search finds names of files and directories, plus documents added here
+    pub fn with_document(self, path: &str, name: &str) -> Self {
+        self.state().documents.push(SearchHit {
+            path: path.to_string(),
+            name: name.to_string(),
+        });
+        self
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn file(&self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        match self.state().fs.get(path.as_ref()) {
+            Some(Node::File(data)) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn exists(&self, path: impl AsRef<Path>) -> bool {
+        self.state().exists(path.as_ref())
+    }
+
+    //Events published since start or last `take_events`
+    pub fn events(&self) -> Vec<HostEvent> {
+        self.state().events.clone()
+    }
+
+    pub fn take_events(&self) -> Vec<HostEvent> {
+        std::mem::take(&mut self.state().events)
+    }
+
+    pub fn notifications(&self) -> Vec<Notification> {
+        self.state().notifications.clone()
+    }
+}
+
+impl State {
+    //Root and relative paths without parent always exist
+    fn exists(&self, path: &Path) -> bool {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => self.fs.contains_key(path),
+            _ => true,
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        match self.fs.get(path) {
+            Some(node) => matches!(node, Node::Dir),
+            None => self.exists(path),
+        }
+    }
+
+    fn create_dirs(&mut self, path: &Path) {
+        let missing: Vec<PathBuf> = path.ancestors().filter(|dir| !self.exists(dir)).map(|dir| dir.to_path_buf()).collect();
+        for dir in missing {
+            self.fs.insert(dir, Node::Dir);
+        }
+    }
+
+    fn check_parent(&self, path: &Path) -> Result<(), String> {
+        match path.parent() {
+            Some(parent) if !self.is_dir(parent) => Err(not_found(parent)),
+            _ => Ok(()),
+        }
+    }
+
+    //Path with everything under it
+    fn subtree(&self, path: &Path) -> Vec<(PathBuf, Node)> {
+        self.fs.range(path.to_path_buf()..)
+            .take_while(|(other, _)| other.starts_with(path))
+            .map(|(other, node)| (other.clone(), node.clone()))
+            .collect()
+    }
+
+    fn publish(&mut self, event: HostEvent) {
+        self.events.push(event);
+    }
+}
+
+//Where `path` under `from` ends up when `from` is copied or moved to `to`
+fn relocated(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(rest) if !rest.as_os_str().is_empty() => to.join(rest),
+        _ => to.to_path_buf(),
+    }
+}
+
+fn not_found(path: &Path) -> String {
+    format!("{} not found", path.display())
+}
+
+fn already_exists(path: &Path) -> String {
+    format!("{} already exists", path.display())
+}
+
+impl HostServices for MockHost {
+    fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, String> {
+        let state = self.state();
+        let query = query.to_lowercase();
+        let files = state.fs.keys().map(|path| SearchHit {
+            path: path.display().to_string(),
+            name: path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+        });
+        Ok(files.chain(state.documents.iter().cloned())
+            .filter(|hit| hit.name.to_lowercase().contains(&query))
+            .take(limit)
+            .collect())
+    }
+
+    fn list_dir(&self, path: &Path) -> Result<Vec<FileEntry>, String> {
+        let state = self.state();
+        if !state.exists(path) {
+            return Err(not_found(path));
+        }
+        if !state.is_dir(path) {
+            return Err(format!("{} is not a directory", path.display()));
+        }
+        let mut entries: Vec<FileEntry> = state.fs.iter()
+            .filter(|(other, _)| other.parent() == Some(path))
+            .map(|(other, node)| FileEntry {
+                path: other.clone(),
+                name: other.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default(),
+                is_dir: matches!(node, Node::Dir),
+                size: match node {
+                    Node::File(data) => data.len() as u64,
+                    Node::Dir => 0,
+                },
+                modified: None,
+            })
+            .collect();
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+        Ok(entries)
+    }
+
+    fn read_file(&self, path: &Path) -> Result<Vec<u8>, String> {
+        match self.state().fs.get(path) {
+            Some(Node::File(data)) => Ok(data.clone()),
+            Some(Node::Dir) => Err(format!("{} is a directory", path.display())),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), String> {
+        let mut state = self.state();
+        state.check_parent(path)?;
+        let existed = match state.fs.get(path) {
+            Some(Node::Dir) => return Err(format!("{} is a directory", path.display())),
+            Some(Node::File(_)) => true,
+            None => false,
+        };
+        state.fs.insert(path.to_path_buf(), Node::File(data.to_vec()));
+        state.publish(if existed {
+            HostEvent::FileModified { path: path.to_path_buf() }
+        } else {
+            HostEvent::FileCreated { path: path.to_path_buf() }
+        });
+        Ok(())
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<(), String> {
+        let mut state = self.state();
+        if !state.fs.contains_key(from) {
+            return Err(not_found(from));
+        }
+        if state.fs.contains_key(to) {
+            return Err(already_exists(to));
+        }
+        state.check_parent(to)?;
+        if to.starts_with(from) {
+            return Err(format!("can't put {} inside itself", from.display()));
+        }
+        for (path, node) in state.subtree(from) {
+            state.fs.insert(relocated(&path, from, to), node);
+        }
+        state.publish(HostEvent::FileCreated { path: to.to_path_buf() });
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<(), String> {
+        let mut state = self.state();
+        if !state.fs.contains_key(from) {
+            return Err(not_found(from));
+        }
+        if state.fs.contains_key(to) {
+            return Err(already_exists(to));
+        }
+        state.check_parent(to)?;
+        if to.starts_with(from) {
+            return Err(format!("can't put {} inside itself", from.display()));
+        }
+        for (path, node) in state.subtree(from) {
+            state.fs.remove(&path);
+            state.fs.insert(relocated(&path, from, to), node);
+        }
+        state.publish(HostEvent::FileMoved { from: from.to_path_buf(), to: to.to_path_buf() });
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), String> {
+        let mut state = self.state();
+        if !state.fs.contains_key(path) {
+            return Err(not_found(path));
+        }
+        for (other, _) in state.subtree(path) {
+            state.fs.remove(&other);
+        }
+        state.publish(HostEvent::FileRemoved { path: path.to_path_buf() });
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<(), String> {
+        let mut state = self.state();
+        if state.exists(path) {
+            return Err(already_exists(path));
+        }
+        state.check_parent(path)?;
+        state.fs.insert(path.to_path_buf(), Node::Dir);
+        state.publish(HostEvent::FileCreated { path: path.to_path_buf() });
+        Ok(())
+    }
+
+    fn notify(&self, _plugin_id: &str, notification: Notification) -> Result<(), String> {
+        self.state().notifications.push(notification);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn create_dir_needs_parent() {
+        let host = MockHost::new().with_dir("/notes");
+        assert!(host.create_dir(Path::new("/notes")).is_err());
+        assert!(host.create_dir(Path::new("/missing/inner")).is_err());
+        assert!(!host.exists("/missing"));
+        host.create_dir(Path::new("/notes/old")).unwrap();
+        assert!(host.list_dir(Path::new("/notes/old")).unwrap().is_empty());
+        assert_eq!(host.events(), vec![HostEvent::FileCreated { path: PathBuf::from("/notes/old") }]);
+    }
+
+    #[test]
+    fn write_reports_created_then_modified() {
+        let host = MockHost::new().with_dir("/notes");
+        host.write_file(Path::new("/notes/todo.txt"), b"milk").unwrap();
+        host.write_file(Path::new("/notes/todo.txt"), b"bread").unwrap();
+        assert_eq!(host.read_file(Path::new("/notes/todo.txt")).unwrap(), b"bread");
+        assert!(host.write_file(Path::new("/other/todo.txt"), b"").is_err());
+        assert!(host.write_file(Path::new("/notes"), b"").is_err());
+        assert_eq!(host.take_events(), vec![
+            HostEvent::FileCreated { path: PathBuf::from("/notes/todo.txt") },
+            HostEvent::FileModified { path: PathBuf::from("/notes/todo.txt") },
+        ]);
+        assert!(host.events().is_empty());
+    }
+
+    #[test]
+    fn copy_and_rename_take_subtree() {
+        let host = MockHost::new().with_file("/notes/work/todo.txt", "milk").with_dir("/backup");
+        host.copy(Path::new("/notes"), Path::new("/backup/notes")).unwrap();
+        assert_eq!(host.file("/backup/notes/work/todo.txt"), Some(b"milk".to_vec()));
+        assert!(host.copy(Path::new("/notes"), Path::new("/notes/work/notes")).is_err());
+        assert!(host.copy(Path::new("/notes"), Path::new("/backup/notes")).is_err());
+
+        host.rename(Path::new("/notes/work"), Path::new("/archive")).unwrap();
+        assert!(!host.exists("/notes/work/todo.txt"));
+        assert_eq!(host.file("/archive/todo.txt"), Some(b"milk".to_vec()));
+        assert!(host.rename(Path::new("/archive"), Path::new("/archive/inner")).is_err());
+    }
+
+    #[test]
+    fn delete_removes_subtree() {
+        let host = MockHost::new().with_file("/notes/work/todo.txt", "milk");
+        host.delete(Path::new("/notes")).unwrap();
+        assert!(!host.exists("/notes/work/todo.txt"));
+        assert!(host.delete(Path::new("/notes")).is_err());
+        assert_eq!(host.events(), vec![HostEvent::FileRemoved { path: PathBuf::from("/notes") }]);
+    }
+
+    #[test]
+    fn list_dir_puts_directories_first() {
+        let host = MockHost::new().with_file("/notes/b.txt", "bb").with_file("/notes/a.txt", "a").with_dir("/notes/z");
+        let names: Vec<String> = host.list_dir(Path::new("/notes")).unwrap().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, vec!["z", "a.txt", "b.txt"]);
+        assert!(host.list_dir(Path::new("/notes/a.txt")).is_err());
+        assert!(host.list_dir(Path::new("/missing")).is_err());
+    }
+
+    #[test]
+    fn search_finds_files_and_documents() {
+        let host = MockHost::new().with_file("/notes/Todo.txt", "milk").with_document("/remote/todo-list", "todo-list");
+        let hits: Vec<String> = host.search("todo", 10).unwrap().into_iter().map(|hit| hit.path).collect();
+        assert_eq!(hits, vec!["/notes/Todo.txt", "/remote/todo-list"]);
+        assert_eq!(host.search("todo", 1).unwrap().len(), 1);
+    }
+}
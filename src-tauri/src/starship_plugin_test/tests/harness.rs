@@ -0,0 +1,38 @@
+use std::path::{Path, PathBuf};
+use starship_plugin_api::context::{HostEvent, LogLevel};
+use starship_plugin_test::{HarnessError, MockHost, PluginHarness};
+
+//Cargo builds examples of this crate into `target/<profile>/examples`, next to `deps` with test executable
+fn example_library(name: &str) -> PathBuf {
+    let file = format!("{}{}.{}", std::env::consts::DLL_PREFIX, name, std::env::consts::DLL_EXTENSION);
+    let exe = std::env::current_exe().unwrap();
+    exe.ancestors().nth(2).unwrap().join("examples").join(file)
+}
+
+fn backup_plugin(host: MockHost) -> PluginHarness {
+    PluginHarness::new(Path::new(env!("CARGO_MANIFEST_DIR")).join("examples/backup_plugin"))
+        .with_library(example_library("backup_plugin"))
+        .with_host(host)
+}
+
+#[test]
+fn example_plugin_runs_against_mock_host() {
+    let mut plugin = backup_plugin(MockHost::new().with_file("/notes/todo.txt", "buy milk")).load().unwrap();
+    assert_eq!(plugin.manifest().id, "backup");
+    assert_eq!(plugin.logs(), vec![(LogLevel::Info, "backup loaded".to_string())]);
+
+    let result = plugin.action("backup", serde_json::json!({ "path": "/notes/todo.txt" })).unwrap();
+    assert_eq!(result, serde_json::json!({ "bytes": 8 }));
+    assert_eq!(plugin.host().file("/notes/todo.txt.bak"), Some(b"buy milk".to_vec()));
+    assert_eq!(plugin.host().events(), vec![HostEvent::FileCreated { path: PathBuf::from("/notes/todo.txt.bak") }]);
+}
+
+#[test]
+fn example_plugin_errors_reach_test() {
+    let mut plugin = backup_plugin(MockHost::new()).load().unwrap();
+    match plugin.action("backup", serde_json::json!({ "path": "/missing.txt" })) {
+        Err(HarnessError::Hook { hook: "on_action", error }) => assert!(error.message.contains("not found"), "{}", error),
+        other => panic!("unexpected {:?}", other.map(|_| ())),
+    }
+    assert!(plugin.host().events().is_empty());
+}
@@ -2,12 +2,20 @@
 use std::env::current_exe;
 use std::fmt::{Display, Formatter};
 use std::fs::{create_dir, File};
+use std::future::Future;
 use std::io::Write;
 use std::os::windows::process::CommandExt;
 use std::path::PathBuf;
 use std::process::{Child, Command};
 use tokio::io;
 use tracing::info;
+use crate::rpc::{self, Method, Protocol, RpcError};
+use crate::rpc::tasker::TaskerProtocol;
+use crate::ws_connector::WsConnector;
+
+//Task observer is reached through `WsConnector` under this key
+pub const CONNECTION_KEY: &str = TaskerProtocol::CONNECTION_KEY;
+pub const URL: &str = "ws://127.0.0.1:5000/";
 
 //Struct for run task observer
 pub struct Tasker {
@@ -53,6 +61,11 @@ impl Tasker {
             Err(e) => Err(TaskerError::Error(e.to_string()))
         }
     }
+
+    //Typed request to task observer, see `rpc::tasker` for its methods
+    pub fn call<M: Method<Protocol = TaskerProtocol>>(connector: &WsConnector, request: &M) -> impl Future<Output = Result<M::Result, RpcError>> {
+        rpc::call(connector, request)
+    }
 }
 
 
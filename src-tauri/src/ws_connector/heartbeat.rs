@@ -0,0 +1,55 @@
+use std::time::Duration;
+use serde::Serialize;
+use tokio::time::Instant;
+use crate::config_manager::ConnectorConfig;
+
+//Client pings every `interval`, connection without pong for `timeout` is dropped and reconnected
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Heartbeat {
+    //`None` when heartbeats are switched off
+    pub fn from_config(config: &ConnectorConfig) -> Option<Self> {
+        if config.heartbeat_interval_ms == 0 {
+            return None;
+        }
+        Some(Self {
+            interval: Duration::from_millis(config.heartbeat_interval_ms),
+            timeout: Duration::from_millis(config.heartbeat_timeout_ms.max(1)),
+        })
+    }
+}
+
+//Ping waiting for its pong
+pub struct Ping {
+    pub payload: [u8; 8],
+    pub sent: Instant,
+}
+
+//Round trip times of heartbeats, kept across reconnects
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct ConnectionMetrics {
+    pub rtt_ms: Option<f64>,
+    //Smoothed like TCP does, recent pongs weigh 1/8
+    pub avg_rtt_ms: Option<f64>,
+    pub min_rtt_ms: Option<f64>,
+    pub max_rtt_ms: Option<f64>,
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+    //Reconnects forced by missing pongs
+    pub timeouts: u64,
+}
+
+impl ConnectionMetrics {
+    pub fn record_rtt(&mut self, rtt: Duration) {
+        let rtt = rtt.as_secs_f64() * 1000.0;
+        self.pongs_received += 1;
+        self.rtt_ms = Some(rtt);
+        self.avg_rtt_ms = Some(self.avg_rtt_ms.map_or(rtt, |avg| avg + (rtt - avg) / 8.0));
+        self.min_rtt_ms = Some(self.min_rtt_ms.map_or(rtt, |min| min.min(rtt)));
+        self.max_rtt_ms = Some(self.max_rtt_ms.map_or(rtt, |max| max.max(rtt)));
+    }
+}
@@ -1,102 +1,576 @@
+pub mod heartbeat;
+pub mod requests;
+pub mod topics;
+pub mod transport;
+
 use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::future::Future;
 use std::sync::Arc;
-use tokio::sync::Mutex;
-use futures::SinkExt;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::{channel, Sender};
-use tokio::sync::mpsc::error::SendError;
-use tokio_stream::StreamExt;
-use tracing::{info, error};
-use websocket::{ClientBuilder, Message, OwnedMessage};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use futures::{SinkExt, StreamExt};
+use rand::Rng;
+use serde::Serialize;
+use serde_json::Value;
+use tauri::{command, State};
+use tokio::sync::{broadcast, oneshot, Mutex};
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
+use tracing::{error, info, trace, warn};
+pub use tokio_tungstenite::tungstenite::Message;
+use crate::config_manager::ConnectorConfig;
+use crate::ws_connector::heartbeat::{ConnectionMetrics, Heartbeat, Ping};
+use crate::ws_connector::requests::{reply_id, PendingRequests};
+use crate::ws_connector::topics::{Subscribers, Subscription, Topic};
+use crate::ws_connector::transport::{check_url, Transport, WsStream};
+
+//Close frame is not waited for longer on disconnect
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+//Send that doesn't finish sooner means connection is stuck, heartbeat timeout is used when set
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub type SharedConnector = Arc<Mutex<WsConnector>>;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    //Waiting before next attempt
+    Disconnected,
+    //Attempts ran out, messages are no longer accepted
+    GaveUp,
+}
+
+//Published on every state change of a connection
+#[derive(Serialize, Clone, Debug)]
+pub struct ConnectionEvent {
+    pub connection_key: String,
+    pub state: ConnectionState,
+    //Attempts since connection was last up
+    pub attempt: u32,
+    pub error: Option<String>,
+    //Set when disconnected
+    pub retry_in_ms: Option<u64>,
+}
+
+//Delays between attempts grow exponentially up to `max_delay`, each randomly shortened by up to half
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    //0 retries forever
+    pub max_attempts: u32,
+    //Outgoing messages kept while connection is down
+    pub queue_size: usize,
+}
+
+impl ReconnectPolicy {
+    pub fn from_config(config: &ConnectorConfig) -> Self {
+        Self {
+            initial_delay: Duration::from_millis(config.reconnect_initial_ms),
+            max_delay: Duration::from_millis(config.reconnect_max_ms.max(config.reconnect_initial_ms)),
+            max_attempts: config.reconnect_attempts,
+            queue_size: config.queue_size.max(1),
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self.initial_delay.saturating_mul(1 << exponent).min(self.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(rand::rng().random::<f64>())
+    }
 
+    fn gave_up(&self, attempt: u32) -> bool {
+        self.max_attempts != 0 && attempt >= self.max_attempts
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::from_config(&ConnectorConfig::default())
+    }
+}
+
+//Message in send queue, `request` is id of request waiting for its reply
+struct Outgoing {
+    message: Message,
+    request: Option<u64>,
+}
+
+struct Connection {
+    outgoing: Sender<Outgoing>,
+    pending: PendingRequests,
+    //Sending or dropping it closes connection
+    cancel: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+//Connections to local services. Every connection is kept up by its own task,
+//messages sent while it is down wait in a bounded queue
 pub struct WsConnector {
-    senders: HashMap<String, Sender<OwnedMessage>>
+    connections: HashMap<String, Connection>,
+    subscribers: HashMap<String, Subscribers>,
+    states: Arc<Mutex<HashMap<String, ConnectionState>>>,
+    metrics: Arc<Mutex<HashMap<String, ConnectionMetrics>>>,
+    events: broadcast::Sender<ConnectionEvent>,
+    policy: ReconnectPolicy,
+    transport: Transport,
+    heartbeat: Option<Heartbeat>,
+    request_timeout: Duration,
+    next_request: AtomicU64,
 }
 
 impl WsConnector {
-    pub fn init() -> Self {
-        Self {
-            senders: HashMap::new()
+    //Fails when configured CA files can't be loaded, connections must not silently trust less than configured
+    pub fn init(config: &ConnectorConfig) -> Result<Self, WsConnectorError> {
+        let (events, _) = broadcast::channel(64);
+        Ok(Self {
+            connections: HashMap::new(),
+            subscribers: HashMap::new(),
+            states: Arc::new(Mutex::new(HashMap::new())),
+            metrics: Arc::new(Mutex::new(HashMap::new())),
+            events,
+            policy: ReconnectPolicy::from_config(config),
+            transport: Transport::from_config(config)?,
+            heartbeat: Heartbeat::from_config(config),
+            request_timeout: Duration::from_millis(config.request_timeout_ms),
+            next_request: AtomicU64::new(1),
+        })
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    //Messages service pushes on its own with given topic, replies to requests aren't included.
+    //Can subscribe before connection is made, subscriptions stay while it reconnects
+    pub fn subscribe_to(&mut self, connection_key: &str, topic: Topic) -> Subscription {
+        self.subscribers.entry(connection_key.to_string()).or_default().subscribe(topic)
+    }
+
+    //Starts connecting in background, connection with the same key is replaced
+    pub async fn connect(&mut self, connection_key: &str, url: &str) -> Result<(), WsConnectorError> {
+        check_url(url)?;
+        self.disconnect(connection_key).await;
+
+        let (outgoing, outgoing_rx) = channel(self.policy.queue_size);
+        let (cancel, cancel_rx) = oneshot::channel();
+        let pending = PendingRequests::default();
+        let supervisor = Supervisor {
+            key: connection_key.to_string(),
+            url: url.to_string(),
+            policy: self.policy.clone(),
+            transport: self.transport.clone(),
+            heartbeat: self.heartbeat.clone(),
+            subscribers: self.subscribers.entry(connection_key.to_string()).or_default().clone(),
+            pending: pending.clone(),
+            states: self.states.clone(),
+            metrics: self.metrics.clone(),
+            events: self.events.clone(),
+        };
+        let task = tokio::task::spawn(supervisor.run(outgoing_rx, cancel_rx));
+        self.connections.insert(connection_key.to_string(), Connection { outgoing, pending, cancel, task });
+        Ok(())
+    }
+
+    //Closes connection and waits for its task, queued messages are dropped and requests fail
+    pub async fn disconnect(&mut self, connection_key: &str) {
+        if let Some(connection) = self.connections.remove(connection_key) {
+            let _ = connection.cancel.send(());
+            let _ = connection.task.await;
+            self.states.lock().await.remove(connection_key);
+            self.metrics.lock().await.remove(connection_key);
         }
     }
 
-    pub async fn connect(&mut self, connection_key: &str, url: &str, client_sender: Option<Sender<OwnedMessage>>) {
-        let client = ClientBuilder::new(url)
-            .unwrap()
-            .add_protocol("rust-websocket")
-            .connect_insecure()
-            .unwrap();
+    pub async fn state(&self, connection_key: &str) -> Option<ConnectionState> {
+        self.states.lock().await.get(connection_key).copied()
+    }
+
+    pub async fn states(&self) -> HashMap<String, ConnectionState> {
+        self.states.lock().await.clone()
+    }
 
-        let (mut rec, mut send) = client.split().unwrap();
-        let (tx, mut rx) = channel(32);
-        let tx_1 = tx.clone();
-        let client_sender = client_sender.clone();
+    //Round trip times measured by heartbeats
+    pub async fn metrics(&self, connection_key: &str) -> Option<ConnectionMetrics> {
+        self.metrics.lock().await.get(connection_key).cloned()
+    }
 
-        tokio::task::spawn(async move {
-            loop {
-                let message = rx.recv().await.unwrap();
+    pub async fn all_metrics(&self) -> HashMap<String, ConnectionMetrics> {
+        self.metrics.lock().await.clone()
+    }
 
-                match message {
-                    OwnedMessage::Close(_) => {
-                        let _ = send.send_message(&message);
-                        return;
-                    }
-                    _ => (),
-                }
+    //Queued until connection is up, fails when queue is full instead of waiting
+    pub async fn send(&self, connection_key: &str, message: &str) -> Result<(), WsConnectorError> {
+        let connection = self.connection(connection_key)?;
+        enqueue(connection_key, connection, Outgoing { message: Message::text(message), request: None })
+    }
 
-                send.send_message(&message).unwrap_or_else( |e | {
-                    error!("{}", e);
-                    let _ = send.send_message(&Message::close());
-                    return;
-                })
+    //Sends JSON object with `id` added and resolves to reply with the same `id`, payload must not have its own `id`.
+    //Request is queued right away, the future doesn't borrow connector and dropping it cancels request
+    pub fn request(&self, connection_key: &str, payload: Value) -> impl Future<Output = Result<Value, WsConnectorError>> {
+        self.request_with_timeout(connection_key, payload, self.request_timeout)
+    }
+
+    pub fn request_with_timeout(&self, connection_key: &str, mut payload: Value, timeout: Duration) -> impl Future<Output = Result<Value, WsConnectorError>> {
+        let key = connection_key.to_string();
+        let queued = self.connection(connection_key).and_then(|connection| {
+            let object = payload.as_object_mut()
+                .ok_or_else(|| WsConnectorError::InvalidPayload("request must be JSON object".to_string()))?;
+            if object.contains_key("id") {
+                return Err(WsConnectorError::InvalidPayload("request must not have `id`, connector sets it".to_string()));
             }
+            let id = self.next_request.fetch_add(1, Ordering::Relaxed);
+            object.insert("id".to_string(), Value::from(id));
+            let (reply, guard) = connection.pending.add(id);
+            enqueue(connection_key, connection, Outgoing { message: Message::text(payload.to_string()), request: Some(id) })?;
+            Ok((reply, guard))
         });
+        async move {
+            let (reply, _guard) = queued?;
+            match tokio::time::timeout(timeout, reply).await {
+                Ok(Ok(reply)) => reply,
+                Ok(Err(_)) => Err(WsConnectorError::ConnectionLost(key)),
+                Err(_) => Err(WsConnectorError::Timeout(key)),
+            }
+        }
+    }
 
-        tokio::task::spawn(async move {
-            for message in rec.incoming_messages() {
-                let message = message.unwrap_or_else( | e | {
-                    error!("{}", e);
-                    return OwnedMessage::Close(None);
-                });
-                match message {
-                    OwnedMessage::Text(data) => {
-                        if let Some(sender) = client_sender.clone() {
-                            if let Err(e) = sender.send(OwnedMessage::Text(data.clone())).await {
-                                error!("{e}")
-                            }
+    fn connection(&self, connection_key: &str) -> Result<&Connection, WsConnectorError> {
+        self.connections.get(connection_key)
+            .ok_or_else(|| WsConnectorError::NotConnected(connection_key.to_string()))
+    }
+}
+
+fn enqueue(connection_key: &str, connection: &Connection, message: Outgoing) -> Result<(), WsConnectorError> {
+    connection.outgoing.try_send(message).map_err(|e| match e {
+        TrySendError::Full(_) => WsConnectorError::QueueFull(connection_key.to_string()),
+        TrySendError::Closed(_) => WsConnectorError::GaveUp(connection_key.to_string()),
+    })
+}
+
+//Why a connected session ended
+enum SessionEnd {
+    Lost(String),
+    //Connection was cancelled or connector dropped the queue
+    Closed,
+}
+
+struct Supervisor {
+    key: String,
+    url: String,
+    policy: ReconnectPolicy,
+    transport: Transport,
+    heartbeat: Option<Heartbeat>,
+    subscribers: Subscribers,
+    pending: PendingRequests,
+    states: Arc<Mutex<HashMap<String, ConnectionState>>>,
+    metrics: Arc<Mutex<HashMap<String, ConnectionMetrics>>>,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl Supervisor {
+    async fn run(self, mut outgoing: Receiver<Outgoing>, mut cancel: oneshot::Receiver<()>) {
+        let gave_up = self.reconnect(&mut outgoing, &mut cancel).await;
+        let key = self.key.clone();
+        if gave_up {
+            self.pending.fail(false, || WsConnectorError::GaveUp(key.clone()));
+        } else {
+            self.pending.fail(false, || WsConnectorError::NotConnected(key.clone()));
+        }
+    }
+
+    //Keeps connection up until it is cancelled or attempts run out, true when they did
+    async fn reconnect(&self, outgoing: &mut Receiver<Outgoing>, cancel: &mut oneshot::Receiver<()>) -> bool {
+        let mut attempt = 0;
+        //Message that was being written when connection broke, goes first after reconnect
+        let mut unsent = None;
+        loop {
+            attempt += 1;
+            self.publish(ConnectionState::Connecting, attempt, None, None).await;
+            let connected = tokio::select! {
+                connected = self.transport.open(&self.url) => connected,
+                _ = &mut *cancel => return false,
+            };
+            let error = match connected {
+                Ok(stream) => {
+                    info!("Connected to {} ({})", self.key, self.url);
+                    self.publish(ConnectionState::Connected, 0, None, None).await;
+                    attempt = 1;
+                    match self.session(stream, outgoing, &mut unsent, cancel).await {
+                        SessionEnd::Lost(error) => {
+                            let key = self.key.clone();
+                            self.pending.fail(true, || WsConnectorError::ConnectionLost(key.clone()));
+                            error
+                        }
+                        SessionEnd::Closed => return false,
+                    }
+                }
+                Err(error) => error,
+            };
+            if self.policy.gave_up(attempt) {
+                error!("Gave up connecting to {} after {} attempts: {}", self.key, attempt, error);
+                self.publish(ConnectionState::GaveUp, attempt, Some(error), None).await;
+                outgoing.close();
+                let dropped = unsent.into_iter().count() + std::iter::from_fn(|| outgoing.try_recv().ok()).count();
+                if dropped > 0 {
+                    warn!("{} messages to {} are dropped", dropped, self.key);
+                }
+                return true;
+            }
+            let delay = self.policy.delay(attempt);
+            warn!("Connection to {} is down: {}, retrying in {:?}", self.key, error, delay);
+            self.publish(ConnectionState::Disconnected, attempt, Some(error), Some(delay.as_millis() as u64)).await;
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut *cancel => return false,
+            }
+        }
+    }
+
+    async fn session(&self, stream: WsStream, outgoing: &mut Receiver<Outgoing>, unsent: &mut Option<Outgoing>, cancel: &mut oneshot::Receiver<()>) -> SessionEnd {
+        let (mut write, mut read) = stream.split();
+        let mut ticks = self.heartbeat.as_ref().map(|heartbeat| {
+            let mut ticks = tokio::time::interval_at(Instant::now() + heartbeat.interval, heartbeat.interval);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            ticks
+        });
+        let mut pings: u64 = 0;
+        let mut ping: Option<Ping> = None;
+        loop {
+            let pong_deadline = ping.as_ref().zip(self.heartbeat.as_ref())
+                .map(|(ping, heartbeat)| ping.sent + heartbeat.timeout)
+                .unwrap_or_else(|| Instant::now() + Duration::from_secs(3600));
+            let message = match unsent.take() {
+                Some(message) => message,
+                None => tokio::select! {
+                    _ = &mut *cancel => {
+                        let _ = tokio::time::timeout(CLOSE_TIMEOUT, write.send(Message::Close(None))).await;
+                        return SessionEnd::Closed;
+                    }
+                    message = outgoing.recv() => match message {
+                        Some(message) => message,
+                        None => {
+                            let _ = tokio::time::timeout(CLOSE_TIMEOUT, write.send(Message::Close(None))).await;
+                            return SessionEnd::Closed;
                         }
-                        info!("{}", data);
+                    },
+                    _ = next_tick(&mut ticks), if ping.is_none() => {
+                        pings += 1;
+                        let payload = pings.to_be_bytes();
+                        ping = Some(Ping { payload, sent: Instant::now() });
+                        self.update_metrics(|metrics| metrics.pings_sent += 1).await;
+                        Outgoing { message: Message::Ping(payload.to_vec().into()), request: None }
                     }
-                    OwnedMessage::Close(_) => {
-                        let _ = tx_1.send(OwnedMessage::Close(None)).await;
-                        return;
+                    _ = tokio::time::sleep_until(pong_deadline), if ping.is_some() => {
+                        self.update_metrics(|metrics| metrics.timeouts += 1).await;
+                        let timeout = self.heartbeat.as_ref().map(|heartbeat| heartbeat.timeout).unwrap_or_default();
+                        return SessionEnd::Lost(format!("no pong in {:?}", timeout));
                     }
-                    OwnedMessage::Ping(data) => {
-                        if let Err(e) = tx_1.send(OwnedMessage::Pong(data)).await {
-                            error!("{}", e);
-                            return;
+                    //Pings are answered by the stream itself
+                    message = read.next() => match message {
+                        Some(Ok(Message::Text(data))) => {
+                            let parsed = serde_json::from_str::<Value>(data.as_str()).ok();
+                            match parsed.as_ref().and_then(reply_id) {
+                                Some(id) => self.pending.resolve(id, parsed.unwrap_or_default()),
+                                None => {
+                                    let method = parsed.as_ref().and_then(Topic::of);
+                                    self.deliver(Message::Text(data), method.as_deref());
+                                }
+                            }
+                            continue;
+                        }
+                        Some(Ok(Message::Pong(payload))) => {
+                            if let Some(sent) = ping.as_ref().filter(|ping| payload.as_ref() == ping.payload).map(|ping| ping.sent) {
+                                ping = None;
+                                self.update_metrics(|metrics| metrics.record_rtt(sent.elapsed())).await;
+                            }
+                            continue;
                         }
+                        Some(Ok(Message::Close(frame))) => {
+                            let reason = frame.map(|frame| frame.reason.to_string()).filter(|reason| !reason.is_empty());
+                            return SessionEnd::Lost(format!("closed by remote{}", reason.map(|reason| format!(": {}", reason)).unwrap_or_default()));
+                        }
+                        Some(Ok(_)) => continue,
+                        Some(Err(e)) => return SessionEnd::Lost(e.to_string()),
+                        None => return SessionEnd::Lost("connection lost".to_string()),
                     }
-                    _ => {}
                 }
+            };
+            if let Some(id) = message.request {
+                //Cancelled or timed out while queued
+                if !self.pending.is_waiting(id) {
+                    continue;
+                }
+            }
+            //Send waiting on a full socket buffer must not outlive pending pong either
+            let send_timeout = match &ping {
+                Some(_) => pong_deadline.saturating_duration_since(Instant::now()),
+                None => self.heartbeat.as_ref().map_or(SEND_TIMEOUT, |heartbeat| heartbeat.timeout),
+            };
+            let error = match tokio::time::timeout(send_timeout, write.send(message.message.clone())).await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(e.to_string()),
+                Err(_) => Some(format!("send didn't finish in {:?}", send_timeout)),
+            };
+            if let Some(error) = error {
+                //Ping of broken connection is not repeated after reconnect
+                if !matches!(message.message, Message::Ping(_)) {
+                    *unsent = Some(message);
+                }
+                return SessionEnd::Lost(error);
+            }
+            if let Some(id) = message.request {
+                self.pending.mark_sent(id);
             }
+        }
+    }
+
+    fn deliver(&self, message: Message, method: Option<&str>) {
+        trace!("{} received {}", self.key, message);
+        self.subscribers.publish(&self.key, &message, method);
+    }
+
+    async fn update_metrics(&self, update: impl FnOnce(&mut ConnectionMetrics)) {
+        update(self.metrics.lock().await.entry(self.key.clone()).or_default());
+    }
+
+    async fn publish(&self, state: ConnectionState, attempt: u32, error: Option<String>, retry_in_ms: Option<u64>) {
+        self.states.lock().await.insert(self.key.clone(), state);
+        let _ = self.events.send(ConnectionEvent {
+            connection_key: self.key.clone(),
+            state,
+            attempt,
+            error,
+            retry_in_ms,
         });
+    }
+}
 
-        self.senders.insert(connection_key.to_string(), tx);
+//Never ready when heartbeats are off
+async fn next_tick(ticks: &mut Option<Interval>) {
+    match ticks {
+        Some(ticks) => {
+            ticks.tick().await;
+        }
+        None => std::future::pending().await,
     }
+}
+
+#[derive(Serialize, Debug)]
+pub enum WsConnectorError {
+    InvalidUrl(String),
+    NotConnected(String),
+    //Connection is down and its queue is full
+    QueueFull(String),
+    GaveUp(String),
+    Tls(String),
+    InvalidPayload(String),
+    //No reply in time
+    Timeout(String),
+    //Connection dropped after request was sent
+    ConnectionLost(String),
+}
 
-    pub fn get_connection(&self, connection_key: &str) -> Option<Sender<OwnedMessage>> {
-        if let Some(tx) = self.senders.get(connection_key) {
-            return Some(tx.clone())
+impl Display for WsConnectorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WsConnectorError::InvalidUrl(e) => write!(f, "invalid url {}", e),
+            WsConnectorError::NotConnected(key) => write!(f, "no connection {}", key),
+            WsConnectorError::QueueFull(key) => write!(f, "connection {} is down and its queue is full", key),
+            WsConnectorError::GaveUp(key) => write!(f, "gave up reconnecting {}", key),
+            WsConnectorError::Tls(e) => write!(f, "TLS setup failed: {}", e),
+            WsConnectorError::InvalidPayload(e) => write!(f, "{}", e),
+            WsConnectorError::Timeout(key) => write!(f, "no reply from {} in time", key),
+            WsConnectorError::ConnectionLost(key) => write!(f, "connection {} was lost before reply", key),
         }
-        None
+    }
+}
+
+#[command]
+pub async fn connection_states(connector: State<'_, SharedConnector>) -> Result<HashMap<String, ConnectionState>, ()> {
+    Ok(connector.lock().await.states().await)
+}
+
+#[command]
+pub async fn connection_metrics(connector: State<'_, SharedConnector>) -> Result<HashMap<String, ConnectionMetrics>, ()> {
+    Ok(connector.lock().await.all_metrics().await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    //Nothing listens there, requests stay queued
+    async fn connector() -> WsConnector {
+        let config = ConnectorConfig { system_roots: false, ..ConnectorConfig::default() };
+        let mut connector = WsConnector::init(&config).unwrap();
+        connector.connect("service", "ws://127.0.0.1:9").await.unwrap();
+        connector
+    }
+
+    fn policy(initial_ms: u64, max_ms: u64, attempts: u32) -> ReconnectPolicy {
+        ReconnectPolicy::from_config(&ConnectorConfig {
+            reconnect_initial_ms: initial_ms,
+            reconnect_max_ms: max_ms,
+            reconnect_attempts: attempts,
+            ..ConnectorConfig::default()
+        })
     }
 
-    pub async fn send(&self, connection_key: &str, message: &str) {
-        if let Some(tx) = self.get_connection(connection_key) {
-            if let Err(e) =  tx.send(OwnedMessage::Text(message.to_string())).await {
-                error!("{}", e)
+    #[test]
+    fn delay_doubles_up_to_max() {
+        let policy = policy(100, 1000, 0);
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (40, 1000)] {
+            for _ in 0..20 {
+                let delay = policy.delay(attempt);
+                assert!(delay >= Duration::from_millis(full / 2) && delay <= Duration::from_millis(full), "attempt {}: {:?}", attempt, delay);
             }
         }
     }
+
+    #[test]
+    fn delay_is_jittered() {
+        let policy = policy(1000, 1000, 0);
+        let delays: std::collections::HashSet<Duration> = (0..20).map(|_| policy.delay(1)).collect();
+        assert!(delays.len() > 1);
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts() {
+        assert!(!policy(100, 1000, 3).gave_up(2));
+        assert!(policy(100, 1000, 3).gave_up(3));
+        assert!(!policy(100, 1000, 0).gave_up(u32::MAX));
+    }
+
+    #[tokio::test]
+    async fn payload_with_id_is_rejected() {
+        let connector = connector().await;
+        let result = connector.request("service", json!({"id": 7, "method": "ping"})).await;
+        assert!(matches!(result, Err(WsConnectorError::InvalidPayload(_))));
+        assert!(!connector.connections["service"].pending.is_waiting(1));
+    }
+
+    #[tokio::test]
+    async fn timed_out_request_is_forgotten() {
+        let connector = connector().await;
+        let result = connector.request_with_timeout("service", json!({"method": "ping"}), Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(WsConnectorError::Timeout(_))));
+        assert!(!connector.connections["service"].pending.is_waiting(1));
+    }
+
+    #[tokio::test]
+    async fn dropped_request_is_forgotten() {
+        let connector = connector().await;
+        let request = connector.request("service", json!({"method": "ping"}));
+        assert!(connector.connections["service"].pending.is_waiting(1));
+        drop(request);
+        assert!(!connector.connections["service"].pending.is_waiting(1));
+    }
 }
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use serde_json::Value;
+use tokio::sync::oneshot;
+use tracing::debug;
+use crate::ws_connector::WsConnectorError;
+
+pub type Reply = Result<Value, WsConnectorError>;
+
+struct Waiting {
+    reply: oneshot::Sender<Reply>,
+    //Written to connection, its reply is lost if connection drops
+    sent: bool,
+}
+
+//Requests of one connection waiting for reply, keyed by id
+#[derive(Clone, Default)]
+pub struct PendingRequests {
+    requests: Arc<Mutex<HashMap<u64, Waiting>>>,
+}
+
+impl PendingRequests {
+    fn requests(&self) -> MutexGuard<'_, HashMap<u64, Waiting>> {
+        self.requests.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn add(&self, id: u64) -> (oneshot::Receiver<Reply>, RequestGuard) {
+        let (reply, reply_rx) = oneshot::channel();
+        self.requests().insert(id, Waiting { reply, sent: false });
+        (reply_rx, RequestGuard { pending: self.clone(), id })
+    }
+
+    pub fn is_waiting(&self, id: u64) -> bool {
+        self.requests().contains_key(&id)
+    }
+
+    pub fn mark_sent(&self, id: u64) {
+        if let Some(waiting) = self.requests().get_mut(&id) {
+            waiting.sent = true;
+        }
+    }
+
+    //Reply to request that timed out or was cancelled is dropped
+    pub fn resolve(&self, id: u64, reply: Value) {
+        match self.requests().remove(&id) {
+            Some(waiting) => {
+                let _ = waiting.reply.send(Ok(reply));
+            }
+            None => debug!("Reply to request {} nobody waits for", id),
+        }
+    }
+
+    //Fails requests already written, or all of them when `sent_only` is false
+    pub fn fail(&self, sent_only: bool, error: impl Fn() -> WsConnectorError) {
+        let mut requests = self.requests();
+        let failed: Vec<u64> = requests.iter()
+            .filter(|(_, waiting)| waiting.sent || !sent_only)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in failed {
+            if let Some(waiting) = requests.remove(&id) {
+                let _ = waiting.reply.send(Err(error()));
+            }
+        }
+    }
+}
+
+//Forgets request when its future is dropped, so a cancelled request isn't sent or matched
+pub struct RequestGuard {
+    pending: PendingRequests,
+    id: u64,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.pending.requests().remove(&self.id);
+    }
+}
+
+//Replies carry id of request and no method, other messages are pushed by service on its own
+pub fn reply_id(message: &Value) -> Option<u64> {
+    if message.get("method").is_some() {
+        return None;
+    }
+    message.get("id").and_then(|id| id.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn reply_resolves_request() {
+        let pending = PendingRequests::default();
+        let (mut reply, _guard) = pending.add(1);
+        pending.resolve(1, json!({"id": 1, "result": true}));
+        assert_eq!(reply.try_recv().unwrap().unwrap(), json!({"id": 1, "result": true}));
+        assert!(!pending.is_waiting(1));
+    }
+
+    #[test]
+    fn reply_with_other_id_is_not_matched() {
+        let pending = PendingRequests::default();
+        let (mut reply, _guard) = pending.add(1);
+        pending.resolve(2, json!({"id": 2}));
+        assert!(reply.try_recv().is_err());
+        assert!(pending.is_waiting(1));
+    }
+
+    #[test]
+    fn reply_after_cancel_is_dropped() {
+        let pending = PendingRequests::default();
+        let (reply, guard) = pending.add(1);
+        drop(guard);
+        drop(reply);
+        assert!(!pending.is_waiting(1));
+        pending.resolve(1, json!({"id": 1}));
+        assert!(!pending.is_waiting(1));
+    }
+
+    #[test]
+    fn only_sent_requests_fail_on_reconnect() {
+        let pending = PendingRequests::default();
+        let (mut sent, _sent_guard) = pending.add(1);
+        let (_queued, _queued_guard) = pending.add(2);
+        pending.mark_sent(1);
+        pending.fail(true, || WsConnectorError::ConnectionLost("service".to_string()));
+        assert!(matches!(sent.try_recv().unwrap(), Err(WsConnectorError::ConnectionLost(_))));
+        assert!(pending.is_waiting(2));
+    }
+
+    #[test]
+    fn message_with_method_is_not_reply() {
+        assert_eq!(reply_id(&json!({"id": 3, "result": null})), Some(3));
+        assert_eq!(reply_id(&json!({"id": 3, "method": "progress"})), None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use serde_json::Value;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::warn;
+use crate::ws_connector::Message;
+
+//Messages kept for subscriber that doesn't keep up, newer ones are dropped for it
+const SUBSCRIBER_QUEUE: usize = 64;
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Topic {
+    //Every message service pushes
+    All,
+    //Messages with this JSON-RPC `method`, or `type` for services that don't speak JSON-RPC
+    Method(String),
+}
+
+impl Topic {
+    pub fn method(method: &str) -> Self {
+        Topic::Method(method.to_string())
+    }
+
+    //Method of pushed message, `None` when it has neither method nor type
+    pub fn of(message: &Value) -> Option<String> {
+        message.get("method").or_else(|| message.get("type"))
+            .and_then(|method| method.as_str())
+            .map(|method| method.to_string())
+    }
+}
+
+//Messages of one topic, dropping it unsubscribes
+pub struct Subscription {
+    topic: Topic,
+    messages: Receiver<Message>,
+}
+
+impl Subscription {
+    pub fn topic(&self) -> &Topic {
+        &self.topic
+    }
+
+    //`None` once connector is gone
+    pub async fn recv(&mut self) -> Option<Message> {
+        self.messages.recv().await
+    }
+}
+
+//Subscribers of one connection, kept while it reconnects
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    topics: Arc<Mutex<HashMap<Topic, Vec<Sender<Message>>>>>,
+}
+
+impl Subscribers {
+    fn topics(&self) -> MutexGuard<'_, HashMap<Topic, Vec<Sender<Message>>>> {
+        self.topics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn subscribe(&self, topic: Topic) -> Subscription {
+        let (sender, messages) = mpsc::channel(SUBSCRIBER_QUEUE);
+        self.topics().entry(topic.clone()).or_default().push(sender);
+        Subscription { topic, messages }
+    }
+
+    //Every subscriber of `method` and of `Topic::All` gets the message,
+    //dropped subscriptions are removed on the way
+    pub fn publish(&self, connection_key: &str, message: &Message, method: Option<&str>) {
+        let mut topics = self.topics();
+        for (topic, senders) in topics.iter_mut() {
+            let matches = match topic {
+                Topic::All => true,
+                Topic::Method(subscribed) => method == Some(subscribed.as_str()),
+            };
+            senders.retain(|sender| {
+                if !matches {
+                    return !sender.is_closed();
+                }
+                match sender.try_send(message.clone()) {
+                    Ok(_) => true,
+                    Err(TrySendError::Full(_)) => {
+                        warn!("Subscriber of {} {:?} is behind, message dropped", connection_key, topic);
+                        true
+                    }
+                    Err(TrySendError::Closed(_)) => false,
+                }
+            });
+        }
+        topics.retain(|_, senders| !senders.is_empty());
+    }
+}
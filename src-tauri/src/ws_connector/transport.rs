@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use rustls::{ClientConfig, RootCertStore};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{connect_async_tls_with_config, Connector, MaybeTlsStream, WebSocketStream};
+use tracing::warn;
+use crate::config_manager::ConnectorConfig;
+use crate::ws_connector::WsConnectorError;
+
+pub type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+//How connections are opened, `wss://` trusts system CA roots and PEM files from config
+#[derive(Clone)]
+pub struct Transport {
+    tls: Arc<ClientConfig>,
+    connect_timeout: Duration,
+}
+
+impl Transport {
+    pub fn from_config(config: &ConnectorConfig) -> Result<Self, WsConnectorError> {
+        let mut roots = RootCertStore::empty();
+        if config.system_roots {
+            let native = rustls_native_certs::load_native_certs();
+            for e in native.errors {
+                warn!("System CA certificates: {}", e);
+            }
+            roots.add_parsable_certificates(native.certs);
+        }
+        for path in &config.ca_certificates {
+            add_pem_file(&mut roots, path)?;
+        }
+        if roots.is_empty() {
+            warn!("No trusted CA certificates, wss connections will fail");
+        }
+        let tls = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| WsConnectorError::Tls(e.to_string()))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self {
+            tls: Arc::new(tls),
+            connect_timeout: Duration::from_millis(config.connect_timeout_ms),
+        })
+    }
+
+    pub async fn open(&self, url: &str) -> Result<WsStream, String> {
+        let connecting = connect_async_tls_with_config(url, None, true, Some(Connector::Rustls(self.tls.clone())));
+        match tokio::time::timeout(self.connect_timeout, connecting).await {
+            Ok(Ok((stream, _))) => Ok(stream),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!("no answer in {:?}", self.connect_timeout)),
+        }
+    }
+}
+
+fn add_pem_file(roots: &mut RootCertStore, path: &Path) -> Result<(), WsConnectorError> {
+    let invalid = |e: String| WsConnectorError::Tls(format!("{}: {}", path.display(), e));
+    let file = File::open(path).map_err(|e| invalid(e.to_string()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| invalid(e.to_string()))?;
+    if certs.is_empty() {
+        return Err(invalid("no certificates found".to_string()));
+    }
+    for cert in certs {
+        roots.add(cert).map_err(|e| invalid(e.to_string()))?;
+    }
+    Ok(())
+}
+
+//Checked before connection is added, a bad url would never connect
+pub fn check_url(url: &str) -> Result<(), WsConnectorError> {
+    let request = url.into_client_request().map_err(|e| WsConnectorError::InvalidUrl(format!("{}: {}", url, e)))?;
+    match request.uri().scheme_str() {
+        Some("ws") | Some("wss") => Ok(()),
+        _ => Err(WsConnectorError::InvalidUrl(format!("{}: scheme must be ws or wss", url))),
+    }
+}